@@ -4,3 +4,5 @@ pub const WINDOW_WIDTH: u32 = 1280;
 pub const WINDOW_HEIGHT: u32 = 720;
 pub const GAME_HEIGHT: f32 = 480.0;
 pub const GAME_WIDTH: f32 = WINDOW_WIDTH as f32 * (GAME_HEIGHT / WINDOW_HEIGHT as f32);
+
+pub const PREDICTION_TOLERANCE: f32 = 0.5; // predicted pawn position error left uncorrected
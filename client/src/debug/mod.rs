@@ -5,10 +5,13 @@ use cvar::{INode, IVisit};
 pub use gfx2d::math::*;
 pub use gfx2d::window;
 use hecs::World;
+use soldank_shared::networking::PacketStats;
 
 mod cli;
 mod entities;
+mod netgraph;
 mod render;
+mod servers;
 mod spawner;
 
 pub use render::RenderState;
@@ -20,6 +23,8 @@ pub struct DebugState {
     cli: cli::CliState,
     spawner: spawner::SpawnerState,
     entities: entities::EntitiesState,
+    netgraph: netgraph::NetGraphState,
+    servers: servers::ServersState,
     pub render: RenderState,
 }
 
@@ -34,6 +39,8 @@ impl IVisit for DebugState {
         f(&mut cvar::List("cli", &mut self.cli));
         f(&mut cvar::List("spawner", &mut self.spawner));
         f(&mut cvar::List("entities", &mut self.entities));
+        f(&mut cvar::List("netgraph", &mut self.netgraph));
+        f(&mut cvar::List("servers", &mut self.servers));
         f(&mut cvar::List("render", &mut self.render));
     }
 }
@@ -66,6 +73,8 @@ pub fn build_ui(egui_ctx: &egui::Context, eng: &Engine<'_>, game: &mut GameState
                     }
                     toggle_state(ui, &mut debug.spawner.visible, "Spawn");
                     toggle_state(ui, &mut debug.entities.visible, "Entities");
+                    toggle_state(ui, &mut debug.netgraph.visible, "Net");
+                    toggle_state(ui, &mut debug.servers.visible, "Servers");
                     toggle_state(ui, &mut debug.render.visible, "Render");
                 });
 
@@ -99,6 +108,9 @@ pub fn build_ui(egui_ctx: &egui::Context, eng: &Engine<'_>, game: &mut GameState
             .spawner
             .build_ui(egui_ctx, &mut game.world, x, y, scale, gravity);
         debug.entities.build_ui(egui_ctx, &mut game.world);
+        let stats = game.resources.get::<PacketStats>().ok();
+        debug.netgraph.build_ui(egui_ctx, stats.as_deref());
+        debug.servers.build_ui(egui_ctx, &game.config.net.master);
         debug.render.build_ui(egui_ctx);
     }
 }
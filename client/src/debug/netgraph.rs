@@ -0,0 +1,83 @@
+use super::*;
+use soldank_shared::networking::PacketStats;
+use std::collections::VecDeque;
+
+const GRAPH_SAMPLES: usize = 300;
+const GRAPH_HEIGHT: f32 = 60.;
+
+#[derive(Default)]
+pub struct NetGraphState {
+    pub(crate) visible: bool,
+
+    /// round trip time history, millis
+    rtt: VecDeque<f32>,
+}
+
+impl IVisit for NetGraphState {
+    fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
+        f(&mut cvar::Property("visible", &mut self.visible, false));
+    }
+}
+
+impl NetGraphState {
+    pub fn build_ui(&mut self, egui_ctx: &egui::Context, stats: Option<&PacketStats>) {
+        if let Some(stats) = stats {
+            self.rtt.push_back(stats.rtt);
+            while self.rtt.len() > GRAPH_SAMPLES {
+                self.rtt.pop_front();
+            }
+        }
+
+        let mut visible = self.visible;
+        egui::Window::new("Network")
+            .open(&mut visible)
+            .resizable(false)
+            .show(egui_ctx, |ui| {
+                let stats = match stats {
+                    Some(stats) => stats,
+                    None => {
+                        ui.label("Not connected");
+                        return;
+                    }
+                };
+
+                ui.scope(|ui| {
+                    ui.style_mut().override_text_style = Some(egui::TextStyle::Monospace);
+                    ui.label(format!(
+                        "ping {:4.0}ms \u{B1}{:.1} loss {:4.1}%",
+                        stats.rtt, stats.jitter, stats.loss
+                    ));
+                    ui.label(format!(
+                        "in {:6.0}B/s out {:6.0}B/s",
+                        stats.rate_rx, stats.rate_tx
+                    ));
+                });
+
+                let (rect, _) = ui.allocate_exact_size(
+                    egui::vec2(GRAPH_SAMPLES as f32, GRAPH_HEIGHT),
+                    egui::Sense::hover(),
+                );
+                let painter = ui.painter_at(rect);
+                painter.rect_stroke(rect, 0., egui::Stroke::new(1., egui::Color32::DARK_GRAY));
+
+                let max = self.rtt.iter().copied().fold(1., f32::max);
+                let points = self
+                    .rtt
+                    .iter()
+                    .enumerate()
+                    .map(|(i, rtt)| {
+                        egui::pos2(
+                            rect.left() + i as f32,
+                            rect.bottom() - rtt / max * GRAPH_HEIGHT,
+                        )
+                    })
+                    .collect();
+                painter.add(egui::Shape::line(
+                    points,
+                    egui::Stroke::new(1., egui::Color32::GREEN),
+                ));
+                ui.label(format!("max {:.0}ms", max));
+            });
+        self.visible = visible;
+    }
+}
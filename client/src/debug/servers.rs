@@ -0,0 +1,178 @@
+use super::*;
+use soldank_shared::{
+    constants::SERVER_PORT,
+    messages::{self, OperationCode, ServerStatus, VersionInfo},
+    networking::{decode_out_of_band, out_of_band, resolve_master},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+
+#[derive(Default)]
+pub struct ServersState {
+    pub(crate) visible: bool,
+
+    socket: Option<UdpSocket>,
+    /// servers found so far, with round trip time of info query, millis
+    servers: BTreeMap<SocketAddr, (ServerStatus, Option<f32>)>,
+    /// info queries waiting for reply
+    queries: HashMap<SocketAddr, Instant>,
+}
+
+impl IVisit for ServersState {
+    fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
+        f(&mut cvar::Property("visible", &mut self.visible, false));
+    }
+}
+
+impl ServersState {
+    pub fn build_ui(&mut self, egui_ctx: &egui::Context, master: &str) {
+        self.receive();
+
+        let mut visible = self.visible;
+        egui::Window::new("Servers")
+            .open(&mut visible)
+            .show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Refresh").clicked() {
+                        self.refresh(master);
+                    }
+                    ui.label(format!("{} found", self.servers.len()));
+                });
+                ui.separator();
+
+                egui::Grid::new("servers").striped(true).show(ui, |ui| {
+                    ui.strong("Name");
+                    ui.strong("Map");
+                    ui.strong("Players");
+                    ui.strong("Ping");
+                    ui.strong("Address");
+                    ui.end_row();
+
+                    let current = VersionInfo::current();
+                    for (address, (status, rtt)) in self.servers.iter() {
+                        if current.negotiate(&status.version).is_some() {
+                            ui.label(&status.name);
+                        } else {
+                            ui.colored_label(egui::Color32::RED, &status.name)
+                                .on_hover_text(format!("incompatible {}", status.version));
+                        }
+                        ui.label(&status.map);
+                        ui.label(format!("{}/{}", status.players, status.max_players));
+                        ui.label(match rtt {
+                            Some(rtt) => format!("{:.0}ms", rtt),
+                            None => "-".to_string(),
+                        });
+                        if ui
+                            .button(address.to_string())
+                            .on_hover_text("copy address for --connect")
+                            .clicked()
+                        {
+                            ui.output_mut(|o| o.copied_text = address.to_string());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        self.visible = visible;
+    }
+
+    /// Asks master server and LAN servers to describe themselves
+    fn refresh(&mut self, master: &str) {
+        if self.socket.is_none() {
+            match bind_socket() {
+                Ok(socket) => self.socket = Some(socket),
+                Err(error) => {
+                    log::error!("Cannot bind server browser socket: {}", error);
+                    return;
+                }
+            }
+        }
+
+        self.servers.clear();
+        self.queries.clear();
+
+        if !master.is_empty() {
+            if let Some(address) = resolve_master(master) {
+                self.send(address, &messages::master_list_request());
+            }
+        }
+
+        let broadcast = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), SERVER_PORT);
+        self.send(broadcast, &messages::server_info_request());
+        self.queries.insert(broadcast, Instant::now());
+    }
+
+    fn send(&self, address: SocketAddr, payload: &[u8]) {
+        if let Some(socket) = self.socket.as_ref() {
+            log::debug!("--> Sending {} bytes to [{}]", payload.len(), address);
+            if let Err(error) = socket.send_to(&out_of_band(payload), address) {
+                log::error!("Error sending payload to [{}]: {}", address, error);
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        let mut buffer = [0; u16::MAX as usize];
+        loop {
+            let socket = match self.socket.as_ref() {
+                Some(socket) => socket,
+                None => return,
+            };
+            let (len, address) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return,
+                Err(error) => {
+                    log::error!("Server browser socket error: {}", error);
+                    return;
+                }
+            };
+            log::debug!("<-- Received {} bytes from [{}]", len, address);
+
+            let data = match decode_out_of_band(&buffer[..len]) {
+                Some(data) if !data.is_empty() => data,
+                _ => continue,
+            };
+            match OperationCode::try_from(data[0]) {
+                Ok(OperationCode::MSREP_LIST) => match messages::decode_master_list(data) {
+                    Ok(servers) => {
+                        for (address, status) in servers {
+                            self.servers.insert(address, (status, None));
+                            self.send(address, &messages::server_info_request());
+                            self.queries.insert(address, Instant::now());
+                        }
+                    }
+                    Err(error) => log::error!("Malformed server list: {}", error),
+                },
+                Ok(OperationCode::SVREP_INFO) => match messages::decode_server_info(data) {
+                    Ok(status) => {
+                        // LAN servers answer broadcast query from their own address
+                        let sent = self.queries.remove(&address).or_else(|| {
+                            self.queries
+                                .get(&SocketAddr::new(
+                                    IpAddr::V4(Ipv4Addr::BROADCAST),
+                                    SERVER_PORT,
+                                ))
+                                .copied()
+                        });
+                        let rtt = sent.map(|sent| sent.elapsed().as_secs_f32() * 1000.);
+                        self.servers.insert(address, (status, rtt));
+                    }
+                    Err(error) => log::error!("Malformed server info: {}", error),
+                },
+                _ => log::error!("Unhandled server browser packet from [{}]", address),
+            }
+        }
+    }
+}
+
+fn bind_socket() -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+    socket.set_nonblocking(true)?;
+    socket.set_broadcast(true)?;
+    Ok(socket)
+}
@@ -2,4 +2,8 @@
 pub enum Event {
     ConfigChanged,
     Command(String),
+    /// Load map of this name, without `.pms` extension
+    MapChange(String),
+    /// Command to execute on server we are connected to
+    ServerCommand(String),
 }
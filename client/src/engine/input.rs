@@ -137,6 +137,7 @@ pub enum InputState {
     Reload,
     DropWeapon,
     ThrowGrenade,
+    ThrowFlag,
     Chat,
     TeamChat,
     Radio,
@@ -166,6 +167,7 @@ impl FromStr for InputState {
             "reload" => Ok(InputState::Reload),
             "dropweapon" => Ok(InputState::DropWeapon),
             "throwgrenade" => Ok(InputState::ThrowGrenade),
+            "throwflag" => Ok(InputState::ThrowFlag),
             "chat" => Ok(InputState::Chat),
             "teamchat" => Ok(InputState::TeamChat),
             "radio" => Ok(InputState::Radio),
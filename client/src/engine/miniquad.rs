@@ -194,5 +194,7 @@ impl<G: Game> mq::EventHandler for Runner<G> {
 
     fn window_restored_event(&mut self) {}
 
-    fn quit_requested_event(&mut self) {}
+    fn quit_requested_event(&mut self) {
+        self.game.quit();
+    }
 }
@@ -35,6 +35,7 @@ pub trait Game {
     fn update(&mut self, _eng: Engine<'_>) {}
     fn draw(&mut self, _quad_ctx: &mut mq::Context, _eng: Engine<'_>) {}
     fn draw_debug(&mut self, _egui_ctx: &egui::Context, _eng: Engine<'_>) {}
+    fn quit(&mut self) {}
 }
 
 pub struct Runner<G: Game> {
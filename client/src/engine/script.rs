@@ -19,6 +19,10 @@ pub struct ScriptEngine {
 
     event_send: BroadcastSender<Event>,
     event_recv: BroadcastReceiver<Event>,
+    /// last map change requested, for the game to pick up
+    map_change: Option<String>,
+    /// commands requested to run on server, for the game to send
+    server_commands: Vec<String>,
 }
 
 pub struct ScriptError {
@@ -107,6 +111,8 @@ impl ScriptEngine {
         commands.insert("unbindall", (0, unbind_all as CommandFunction));
         commands.insert("echo", (0, echo_args as CommandFunction));
         commands.insert("exit", (0, exit_game as CommandFunction));
+        commands.insert("map", (1, change_map as CommandFunction));
+        commands.insert("cmd", (1, server_command as CommandFunction));
         commands.insert("eval", (1, eval_rhai as CommandFunction));
         commands.insert("run", (1, run_rhai as CommandFunction));
 
@@ -140,6 +146,8 @@ impl ScriptEngine {
 
             event_send,
             event_recv,
+            map_change: None,
+            server_commands: Vec::new(),
         }
     }
 
@@ -336,8 +344,11 @@ impl ScriptEngine {
         let mut commands = Vec::new();
         for event in &self.event_recv {
             log::trace!("ScriptEngine Event consumer got {:?}", event);
-            if let Event::Command(script) = event {
-                commands.push(script);
+            match event {
+                Event::Command(script) => commands.push(script),
+                Event::MapChange(name) => self.map_change = Some(name),
+                Event::ServerCommand(command) => self.server_commands.push(command),
+                Event::ConfigChanged => {}
             }
         }
         for command in commands {
@@ -347,6 +358,14 @@ impl ScriptEngine {
         }
     }
 
+    pub(crate) fn take_map_change(&mut self) -> Option<String> {
+        self.map_change.take()
+    }
+
+    pub(crate) fn take_server_commands(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.server_commands)
+    }
+
     pub(crate) fn drain_events(&mut self) {
         for event in &self.event_recv {
             if let Event::Command(script) = event {
@@ -522,6 +541,20 @@ fn echo_args(args: &[&str], env: &mut Env) -> Result<Option<String>, String> {
     }
 }
 
+fn change_map(args: &[&str], env: &mut Env) -> Result<Option<String>, String> {
+    env.event_sender
+        .try_send(Event::MapChange(args[0].to_string()))
+        .map_err(|err| format!("Cannot send MapChange Event: {}", err))?;
+    Ok(None)
+}
+
+fn server_command(args: &[&str], env: &mut Env) -> Result<Option<String>, String> {
+    env.event_sender
+        .try_send(Event::ServerCommand(args.join(" ")))
+        .map_err(|err| format!("Cannot send ServerCommand Event: {}", err))?;
+    Ok(None)
+}
+
 fn exit_game(_args: &[&str], _env: &mut Env) -> Result<Option<String>, String> {
     if cfg!(debug_assertions) {
         log::info!("Script exit");
@@ -7,12 +7,12 @@ use crate::{
     game::{self as game, components, physics},
     mapfile::MapFile,
     mq,
+    networking::Networking,
     physics::*,
     render::{self as render, components::Camera},
     soldier::Soldier,
     Weapon, WeaponKind,
 };
-use enumflags2::BitFlags;
 use hecs::With;
 
 impl Game for GameState {
@@ -69,6 +69,23 @@ impl Game for GameState {
             }
         }
 
+        if let Some(connect) = self.connect.take() {
+            let mut networking = Networking::new(
+                Some(&connect.address),
+                &self.config,
+                eng.event_sender.clone(),
+            );
+            if let Some(key) = connect.key {
+                networking.connection_key = key;
+            }
+            if let Some(nick) = connect.nick {
+                networking.nick_name = nick;
+            }
+            self.networking = Some(networking);
+            // pawn is spawned by server
+            return;
+        }
+
         // spawn Player
         let map = self.resources.get::<MapFile>().unwrap();
         let soldier = Soldier::new(&map.spawnpoints[0], self.config.phys.gravity);
@@ -126,13 +143,7 @@ impl Game for GameState {
                     mass_properties: ColliderMassProps::Density(0.5),
                     material: ColliderMaterial::new(3.0, 0.1),
                     flags: ColliderFlags {
-                        collision_groups: physics::InteractionGroups::new(
-                            BitFlags::<physics::InteractionFlag>::from(
-                                physics::InteractionFlag::Player,
-                            )
-                            .bits(),
-                            BitFlags::<physics::InteractionFlag>::all().bits(),
-                        ),
+                        collision_groups: physics::soldier_interaction_groups(false),
                         ..Default::default()
                     },
                     ..Default::default()
@@ -166,13 +177,7 @@ impl Game for GameState {
                 ColliderBundle {
                     shape: ColliderShape::ball(4.5 / self.config.phys.scale),
                     flags: ColliderFlags {
-                        collision_groups: physics::InteractionGroups::new(
-                            BitFlags::<physics::InteractionFlag>::from(
-                                physics::InteractionFlag::Player,
-                            )
-                            .bits(),
-                            BitFlags::<physics::InteractionFlag>::all().bits(),
-                        ),
+                        collision_groups: physics::soldier_interaction_groups(false),
                         active_events: ActiveEvents::CONTACT_EVENTS,
                         active_hooks: ActiveHooks::FILTER_CONTACT_PAIRS,
                         ..Default::default()
@@ -253,6 +258,17 @@ impl Game for GameState {
             &mut self.filesystem,
             &mut self.world,
         );
+        if let Some(name) = eng.script.take_map_change() {
+            self.pending_map = Some(name);
+        }
+        for command in eng.script.take_server_commands() {
+            match self.networking.as_mut() {
+                Some(networking) => networking.send_command(command),
+                None => log::error!("Not connected to server: {}", command),
+            }
+        }
+
+        self.update_networking(&eng, (mouse_x, mouse_y));
 
         game::systems::primitive_movement(&mut self.world);
         game::systems::force_movement(&mut self.world, &self.config);
@@ -263,6 +279,7 @@ impl Game for GameState {
             (mouse_x, mouse_y),
             eng.now,
         );
+        game::systems::update_flaggers(&mut self.world);
 
         self.step_physics(eng.delta);
 
@@ -271,12 +288,18 @@ impl Game for GameState {
         game::physics::update_previous_physics(&mut self.world);
         game::physics::process_contact_events(&mut self.world, &self.resources, eng.now);
         game::systems::follow_camera(&mut self.world, &self.config);
-        game::systems::update_soldiers(&mut self.world, &self.resources, &self.config);
+        if self.networking.is_none() {
+            game::systems::update_soldiers(&mut self.world, &self.resources, &self.config);
+        }
 
         self.world.clear_trackers();
     }
 
     fn draw(&mut self, quad_ctx: &mut mq::Context, eng: Engine<'_>) {
+        if let Some(name) = self.pending_map.take() {
+            self.load_map(quad_ctx, &name);
+        }
+
         render::debug::debug_render(
             &mut self.graphics,
             &self.world,
@@ -298,4 +321,10 @@ impl Game for GameState {
     fn draw_debug(&mut self, egui_ctx: &egui::Context, eng: Engine<'_>) {
         debug::build_ui(egui_ctx, &eng, self);
     }
+
+    fn quit(&mut self) {
+        if let Some(networking) = self.networking.as_mut() {
+            networking.disconnect();
+        }
+    }
 }
@@ -1,7 +1,22 @@
-use crate::{cvars::Config, physics::*, render::GameGraphics};
+use crate::{
+    constants::TIMESTEP_RATE,
+    cvars::Config,
+    engine::{world::WorldCameraExt, Engine},
+    mapfile::{MapFile, MapSpawnpoint},
+    math::*,
+    mq,
+    networking::{ConnectOptions, Networking},
+    physics::*,
+    render::{
+        components::{Camera, Position},
+        GameGraphics,
+    },
+    soldier::Soldier,
+};
 use ::resources::Resources;
 use gvfs::filesystem::Filesystem;
 use hecs::World;
+use soldank_shared::networking::PacketStats;
 
 pub mod components;
 mod main;
@@ -16,6 +31,13 @@ pub struct GameState {
 
     context: gfx2d::Gfx2dContext,
     graphics: GameGraphics,
+    /// map to load before drawing next frame
+    pending_map: Option<String>,
+    /// server to connect to on start
+    connect: Option<ConnectOptions>,
+    networking: Option<Networking>,
+    /// time not simulated yet in network ticks
+    net_time: f64,
 }
 
 impl GameState {
@@ -25,10 +47,15 @@ impl GameState {
         resources: Resources,
         filesystem: Filesystem,
         config: Config,
+        connect: Option<ConnectOptions>,
     ) -> Self {
         GameState {
             context,
             graphics: GameGraphics::new(),
+            pending_map: None,
+            connect,
+            networking: None,
+            net_time: 0.,
             world,
             resources,
             filesystem,
@@ -36,6 +63,96 @@ impl GameState {
         }
     }
 
+    /// Replaces current map with map `name`, along with its colliders and graphics
+    pub fn load_map(&mut self, ctx: &mut mq::Context, name: &str) {
+        let file_name = format!("{}.pms", name);
+        if !MapFile::exists(&self.filesystem, &file_name) {
+            log::error!("Map {} not found", name);
+            return;
+        }
+        if let Some(map) = self.resources.get::<MapFile>() {
+            if map.filename.ends_with(&file_name) {
+                return;
+            }
+        }
+
+        let map = MapFile::load_map_file(&mut self.filesystem, &file_name);
+        log::info!("Using map: {}", map.mapname);
+        self.graphics.load_map(ctx, &mut self.filesystem, &map);
+        self.resources.insert(map);
+
+        self::physics::despawn_map_colliders(&mut self.world);
+        self::physics::create_map_colliders(&mut self.world, &self.resources, &self.config);
+    }
+
+    /// Exchanges packets with server and predicts pawn for every network tick due
+    fn update_networking(&mut self, eng: &Engine<'_>, mouse: (f32, f32)) {
+        let networking = match self.networking.as_mut() {
+            Some(networking) => networking,
+            None => return,
+        };
+        networking.process(&self.resources, &mut self.config, &mut self.world);
+
+        // netgraph shows "Not connected" without stats
+        match networking.stats() {
+            Some(stats) => {
+                self.resources.insert(stats.clone());
+            }
+            None => {
+                self.resources.remove::<PacketStats>();
+            }
+        }
+
+        let pawn = networking.pawn(&self.world);
+        if let Some(pawn) = pawn {
+            if self.world.get::<Soldier>(pawn).is_err() {
+                let position = self.world.get::<Position>(pawn).map_or(Vec2::ZERO, |p| p.0);
+                let soldier = Soldier::new(
+                    &MapSpawnpoint {
+                        active: false,
+                        x: position.x as i32,
+                        y: position.y as i32,
+                        team: 0,
+                    },
+                    self.config.phys.gravity,
+                );
+                let camera = Camera {
+                    zoom: self.config.debug.initial_zoom,
+                    ..Default::default()
+                };
+                let _ = self.world.insert(pawn, (soldier, components::Pawn, camera));
+                let _ = self.world.make_active_camera(pawn);
+            }
+        }
+
+        let (camera, camera_position) = self.world.get_camera_and_camera_position();
+        let (x, y) = camera.mouse_to_world(camera_position.0, mouse.0, mouse.1);
+        let aim = pawn
+            .and_then(|pawn| self.world.get::<Soldier>(pawn).ok().map(|s| s.particle.pos))
+            .map_or(Vec2::ZERO, |pos| (vec2(x, y) - pos).normalize_or_zero());
+        let control =
+            systems::control_flags(eng.input.state) - networking.disallowed_controls(&self.world);
+
+        let map = self.resources.get::<MapFile>().unwrap();
+        self.net_time += eng.delta;
+        while self.net_time >= TIMESTEP_RATE {
+            self.net_time -= TIMESTEP_RATE;
+            networking.predict(
+                &mut self.world,
+                &map,
+                self.config.phys.gravity,
+                control,
+                aim,
+            );
+            systems::update_remote_soldiers(&mut self.world, &map, self.config.phys.gravity);
+        }
+
+        // remote entities are shown `net.interpolation_delay` behind server
+        networking.interpolate(&mut self.world, &self.config);
+
+        networking.post_process(&self.config);
+    }
+
     pub fn config_update(&self) {
         // let app_events = self.resources.get::<AppEventsQueue>().unwrap();
         // if app_events
@@ -173,7 +173,7 @@ pub fn update_previous_physics(world: &mut World) {
 
 impl From<PolyType> for InteractionGroups {
     fn from(polytype: PolyType) -> Self {
-        let memberships = BitFlags::<InteractionFlag>::all();
+        let mut memberships = BitFlags::<InteractionFlag>::all();
         let mut filter = BitFlags::<InteractionFlag>::all();
 
         match polytype {
@@ -226,6 +226,7 @@ impl From<PolyType> for InteractionGroups {
                 filter = InteractionFlag::Flagger.into();
             }
             PolyType::NotFlaggers => {
+                memberships.remove(InteractionFlag::Flagger);
                 filter.remove(InteractionFlag::Flagger);
             }
             PolyType::FlagCollide => {
@@ -240,6 +241,27 @@ impl From<PolyType> for InteractionGroups {
     }
 }
 
+/// Collision groups of soldier colliders
+///
+/// Flaggers filter on `Flagger` bit only, so they pass through `NotFlaggers`
+/// polygons, which are the only ones missing it in memberships.
+pub fn soldier_interaction_groups(flagger: bool) -> InteractionGroups {
+    if flagger {
+        InteractionGroups::new(
+            (InteractionFlag::Player | InteractionFlag::Flagger).bits(),
+            BitFlags::<InteractionFlag>::from(InteractionFlag::Flagger).bits(),
+        )
+    } else {
+        InteractionGroups::new(
+            BitFlags::<InteractionFlag>::from(InteractionFlag::Player).bits(),
+            BitFlags::<InteractionFlag>::all().bits(),
+        )
+    }
+}
+
+/// Marks colliders of map polygons and colliders
+pub struct MapCollider;
+
 pub fn create_map_colliders(world: &mut World, resources: &Resources, config: &Config) {
     let map = resources.get::<MapFile>().unwrap();
     let scale = config.phys.scale;
@@ -266,7 +288,8 @@ pub fn create_map_colliders(world: &mut World, resources: &Resources, config: &C
         if polygon.polytype == PolyType::Bouncy {
             collider.material.restitution = polygon.bounciness;
         }
-        world.spawn(collider);
+        let entity = world.spawn(collider);
+        world.insert_one(entity, MapCollider).unwrap();
     }
 
     for coll in map.colliders.iter() {
@@ -283,6 +306,18 @@ pub fn create_map_colliders(world: &mut World, resources: &Resources, config: &C
             },
             ..Default::default()
         };
-        world.spawn(collider);
+        let entity = world.spawn(collider);
+        world.insert_one(entity, MapCollider).unwrap();
+    }
+}
+
+pub fn despawn_map_colliders(world: &mut World) {
+    let colliders: Vec<Entity> = world
+        .query::<&MapCollider>()
+        .iter()
+        .map(|(entity, _collider)| entity)
+        .collect();
+    for entity in colliders {
+        world.despawn(entity).unwrap();
     }
 }
@@ -5,7 +5,9 @@ use crate::{
     particles::Particle,
     render::components::*,
 };
+use enumflags2::BitFlags;
 use hecs::{With, World};
+use soldank_shared::control::Control;
 
 mod debug;
 mod movement;
@@ -19,3 +21,21 @@ pub fn apply_input(world: &mut World, eng: &Engine) {
         input.state = eng.input.state;
     }
 }
+
+/// Network control flags of pressed input `state`
+pub fn control_flags(state: BitFlags<InputState>) -> Control {
+    let mut control = Control::empty();
+    control.set(Control::LEFT, state.contains(InputState::MoveLeft));
+    control.set(Control::RIGHT, state.contains(InputState::MoveRight));
+    control.set(Control::UP, state.contains(InputState::Jump));
+    control.set(Control::DOWN, state.contains(InputState::Crouch));
+    control.set(Control::FIRE, state.contains(InputState::Fire));
+    control.set(Control::JETS, state.contains(InputState::Jet));
+    control.set(Control::CHANGE, state.contains(InputState::ChangeWeapon));
+    control.set(Control::THROW, state.contains(InputState::ThrowGrenade));
+    control.set(Control::DROP, state.contains(InputState::DropWeapon));
+    control.set(Control::RELOAD, state.contains(InputState::Reload));
+    control.set(Control::PRONE, state.contains(InputState::Prone));
+    control.set(Control::FLAG_THROW, state.contains(InputState::ThrowFlag));
+    control
+}
@@ -3,12 +3,15 @@ use crate::{
     engine::{input::InputState, world::WorldCameraExt},
     game,
     physics::*,
-    Config, EmitterItem, Soldier,
+    Config, EmitterItem, MapFile, MapSpawnpoint, Soldier,
 };
 use ::resources::Resources;
+use hecs::{Entity, Without};
+use soldank_shared::components::{self, SoldierState};
 use std::collections::HashMap;
 
 pub fn update_soldiers(world: &mut World, resources: &Resources, config: &Config) {
+    let map = resources.get::<MapFile>().unwrap();
     let mut emitter = Vec::new();
 
     for (_entity, (mut soldier, input, rb_pos)) in world
@@ -28,10 +31,10 @@ pub fn update_soldiers(world: &mut World, resources: &Resources, config: &Config
             soldier.control.drop = input.state.contains(InputState::DropWeapon);
             soldier.control.reload = input.state.contains(InputState::Reload);
             soldier.control.prone = input.state.contains(InputState::Prone);
-            // soldier.control.flag_throw = input.state.contains(InputState::);
+            soldier.control.flag_throw = input.state.contains(InputState::ThrowFlag);
         }
 
-        soldier.update(resources, &mut emitter, config);
+        soldier.update(&map, &mut emitter, config.phys.gravity);
 
         if let Some(rb_pos) = rb_pos {
             soldier.particle.pos = Vec2::from(rb_pos.next_position.translation) * config.phys.scale;
@@ -46,6 +49,40 @@ pub fn update_soldiers(world: &mut World, resources: &Resources, config: &Config
     }
 }
 
+/// Gives soldiers of other players a simulation, so they are rendered like the pawn.
+/// It only animates them - they stay at their replicated, interpolated `Position`.
+/// Alpha of all soldiers comes from server, which knows about their predator bonus.
+pub fn update_remote_soldiers(world: &mut World, map: &MapFile, gravity: f32) {
+    let spawned: Vec<(Entity, Vec2)> = world
+        .query::<Without<Soldier, With<components::Soldier, &Position>>>()
+        .iter()
+        .map(|(entity, position)| (entity, **position))
+        .collect();
+    for (entity, pos) in spawned {
+        let spawn = MapSpawnpoint {
+            active: false,
+            x: pos.x as i32,
+            y: pos.y as i32,
+            team: 0,
+        };
+        let _ = world.insert_one(entity, Soldier::new(&spawn, gravity));
+    }
+
+    for (_entity, (soldier, position)) in world
+        .query::<Without<Pawn, (&mut Soldier, &Position)>>()
+        .iter()
+    {
+        soldier.particle.pos = **position;
+        soldier.particle.old_pos = **position;
+        soldier.update(map, &mut Vec::new(), gravity);
+        soldier.particle.pos = **position;
+    }
+
+    for (_entity, (soldier, state)) in world.query::<(&mut Soldier, &SoldierState)>().iter() {
+        soldier.alpha = state.alpha;
+    }
+}
+
 pub fn soldier_movement(
     world: &mut World,
     resources: &Resources,
@@ -148,3 +185,32 @@ pub fn soldier_movement(
         }
     }
 }
+
+/// Gives flag carriers, and their legs, the `Flagger` interaction bit
+pub fn update_flaggers(world: &mut World) {
+    let mut legs_parents = HashMap::new();
+    for (entity, parent) in world
+        .query::<With<game::components::Legs, &Parent>>()
+        .iter()
+    {
+        legs_parents.insert(**parent, entity);
+    }
+
+    let soldiers: Vec<(Entity, bool)> = world
+        .query::<&Soldier>()
+        .iter()
+        .map(|(entity, soldier)| (entity, soldier.flagger))
+        .collect();
+
+    for (body, flagger) in soldiers {
+        let groups = game::physics::soldier_interaction_groups(flagger);
+        let legs = legs_parents.get(&body).copied();
+        for entity in std::iter::once(body).chain(legs) {
+            if let Ok(mut flags) = world.get_mut::<ColliderFlags>(entity) {
+                if flags.collision_groups != groups {
+                    flags.collision_groups = groups;
+                }
+            }
+        }
+    }
+}
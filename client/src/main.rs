@@ -5,44 +5,35 @@ macro_rules! iif(
     ($cond:expr, $then:expr, $else:expr) => (if $cond { $then } else { $else })
 );
 
-mod anims;
-mod bullet;
 mod calc;
 mod cli;
 mod constants;
-mod control;
 mod cvars;
 mod debug;
 mod engine;
 mod game;
-mod mapfile;
-mod particles;
+mod networking;
 mod render;
-mod soldier;
-mod weapons;
 
 use anims::*;
 use bullet::*;
 use calc::*;
 use constants::*;
-use control::*;
 use mapfile::*;
 use particles::*;
 use render::*;
 use soldier::*;
 use weapons::*;
 
-pub use soldank_shared::physics;
+pub use soldank_shared::{anims, bullet, mapfile, particles, physics, soldier, weapons};
 
 use cvars::{set_cli_cvars, Config};
 use gfx2d::{math, mq};
-use gvfs::filesystem::{File, Filesystem};
+use gvfs::filesystem::Filesystem;
 use hecs::World;
+use networking::ConnectOptions;
 use quad_rand as rand;
 use resources::Resources;
-use std::{env, path};
-
-use crate::game::components::{EmitterItem, Team};
 
 fn main() {
     color_eyre::install().unwrap();
@@ -51,44 +42,7 @@ fn main() {
     let cmd = cli::parse_cli_args();
 
     let mut filesystem = Filesystem::new(env!("CARGO_PKG_NAME"), "Soldat2k").unwrap();
-
-    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
-        let mut path = path::PathBuf::from(manifest_dir);
-        path.push("../resources");
-        filesystem.mount(path.canonicalize().unwrap().as_path(), true);
-    }
-    log::info!("Full VFS info: {:#?}", filesystem);
-
-    let mut mods = Vec::new();
-
-    let soldat_smod = path::Path::new("/soldat.smod");
-    if filesystem.is_file(soldat_smod) {
-        mods.push((
-            filesystem.open(soldat_smod).unwrap(),
-            soldat_smod.to_string_lossy().to_string(),
-        ));
-    }
-
-    for f in filesystem.read_dir(path::Path::new("/")).unwrap() {
-        let f = f.as_path();
-        if let Some(name) = f.to_str() {
-            if filesystem.is_file(f) && f != soldat_smod && name.ends_with(".smod") {
-                mods.push((filesystem.open(f).unwrap(), name.to_string()));
-            }
-        }
-    }
-    for (md, path) in mods.drain(..) {
-        match md {
-            File::VfsFile(file) => {
-                filesystem.add_zip_file(file).unwrap_or_else(|err| {
-                    panic!(
-                        "Failed to add `{}` file to VFS. (Make sure it is a proper ZIP file.): {}",
-                        path, err
-                    )
-                });
-            }
-        }
-    }
+    soldank_shared::vfs::mount_resources(&mut filesystem);
 
     let mut map_name = cmd
         .get_one::<String>("map")
@@ -103,8 +57,16 @@ fn main() {
     config.debug.visible = cmd.contains_id("debug");
     set_cli_cvars(&mut config, &cmd);
 
+    let connect = cmd
+        .get_one::<String>("connect")
+        .map(|address| ConnectOptions {
+            address: address.clone(),
+            key: cmd.get_one::<String>("key").cloned(),
+            nick: cmd.get_one::<String>("nick").cloned(),
+        });
+
     AnimData::initialize(&mut filesystem);
-    Soldier::initialize(&mut filesystem, &config);
+    Soldier::initialize(&mut filesystem, config.phys.gravity);
 
     let weapons: Vec<Weapon> = WeaponKind::values()
         .iter()
@@ -133,7 +95,7 @@ fn main() {
         let context = gfx2d::Gfx2dContext::new(&mut *ctx);
         let runner = engine::Runner::new(
             ctx,
-            game::GameState::new(context, world, resources, filesystem, config),
+            game::GameState::new(context, world, resources, filesystem, config, connect),
         );
 
         Box::new(runner)
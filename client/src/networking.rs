@@ -1,57 +1,91 @@
-use instant::Instant;
-use laminar::{
-    Config as LaminarConfig, Connection, ConnectionMessenger, Packet as LaminarPacket,
-    VirtualConnection,
-};
-use naia_client_socket::{
-    find_my_ip_address, ClientSocket, ClientSocketTrait, LinkConditionerConfig, MessageSender,
-    Packet as NaiaPacket,
-};
+use hecs::{Entity, World};
 use resources::Resources;
-use smol::channel::{unbounded, Receiver, Sender};
-use std::{collections::HashMap, convert::TryFrom, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::Instant,
+};
 
-use crate::{cvars::Config, engine};
-use orb::client::Client;
+use crate::{
+    constants::{MAX_PACKET_SIZE, PREDICTION_TOLERANCE, SERVER_PORT, TIMESTEP_RATE},
+    cvars::Config,
+    engine,
+    mapfile::MapFile,
+    soldier::Soldier,
+};
 use soldank_shared::{
-    constants::SERVER_PORT,
+    auth::{auth_mac, RejectReason},
+    components::{self, Position, SoldierState},
     control::Control,
-    math::vec2,
-    messages::{self, NetworkMessage},
-    networking::{GameWorld, PacketStats},
+    math::Vec2,
+    messages::{self, DecodeError, DisconnectReason, GameState, NetworkMessage, VersionInfo},
+    networking::{
+        Channels, FragmentBuffer, InterpolationBuffer, NetCommand, NetworkSimulator, Packet,
+        PacketStats,
+    },
+    prediction::Prediction,
+    replication::{EntitiesSnapshot, ReplicationRegistry, SnapshotDelta, SnapshotHistory},
     trace_dump_packet,
 };
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ConnectionState {
     Disconnected,
     Connected,
+    Rejected(RejectReason),
+    /// Server does not speak any protocol version we do - carries versions it supports
+    VersionMismatch(VersionInfo),
+    /// Connection closed by either side - carries the reason and optional server message
+    Closed(DisconnectReason, String),
     Error,
 }
 
-type ReceiveEvent = <VirtualConnection as Connection>::ReceiveEvent;
-
-const MAX_INPUTS_RETAIN: usize = 60;
+/// Server to connect to on start, as given on command line
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub address: String,
+    pub key: Option<String>,
+    pub nick: Option<String>,
+}
 
 pub struct Networking {
     server_address: SocketAddr,
-    client_socket: Box<dyn ClientSocketTrait>,
-    messenger: PacketMessenger,
-    event_receiver: Receiver<ReceiveEvent>,
-    connection: VirtualConnection,
+    socket: UdpSocket,
+    channels: Channels,
+    simulator: NetworkSimulator<Vec<u8>>,
     pub connection_key: String,
     pub nick_name: String,
     state: ConnectionState,
+    /// negotiated with server on connection
+    pub protocol_version: Option<u8>,
     stats: PacketStats,
+    fragments: FragmentBuffer,
+    registry: ReplicationRegistry,
     backoff_round: i32,
     authorized: bool,
     cvars_received: bool,
+    /// server match state and tick it ends at, `0` when it waits for players
+    pub match_state: Option<(GameState, usize)>,
 
     pub tick: usize,
     server_tick_received: usize,
+    server_tick_received_at: Instant,
+    /// last input tick applied by server
+    input_ack_tick: usize,
 
     // game state
-    control: HashMap<usize, (Control, i32, i32)>,
+    /// pawn inputs applied ahead of server, replayed on mispredictions
+    prediction: Prediction<Soldier>,
+    /// server entity -> local entity
+    entities: HashMap<Entity, Entity>,
+    /// server entity controlled by this client
+    pawn: Option<Entity>,
+    /// authoritative states received from server, used as bases for following deltas
+    states: SnapshotHistory,
+    /// positions of remote entities, keyed by server tick time
+    interpolation: InterpolationBuffer,
     game_event_sender: multiqueue2::BroadcastSender<engine::Event>,
 }
 
@@ -59,168 +93,126 @@ fn backoff_enabled(round: i32) -> bool {
     (round & (round - 1)) == 0
 }
 
-struct PacketMessenger {
-    config: LaminarConfig,
-    sender: MessageSender,
-    event_sender: Sender<ReceiveEvent>,
-}
-
-impl ConnectionMessenger<ReceiveEvent> for PacketMessenger {
-    fn config(&self) -> &LaminarConfig {
-        &self.config
-    }
-
-    fn send_event(&mut self, _address: &SocketAddr, event: ReceiveEvent) {
-        if let Err(error) = smol::block_on(self.event_sender.send(event)) {
-            panic!("Error sending via event channel: {}", error);
-        }
-    }
-
-    fn send_packet(&mut self, _address: &SocketAddr, payload: &[u8]) {
-        log::debug!("--> Sending {} bytes", payload.len());
-        trace_dump_packet(payload);
-        self.sender
-            .send(NaiaPacket::new(payload.to_vec()))
-            .expect("send packet error");
-    }
-}
-
 impl Networking {
     pub fn new(
         connect_to: Option<&str>,
+        config: &Config,
         game_event_sender: multiqueue2::BroadcastSender<engine::Event>,
     ) -> Networking {
         let server_socket_address = if let Some(addr) = connect_to {
             addr.parse().expect("cannot parse connect address")
         } else {
-            let addr = find_my_ip_address().expect("can't find local ip address");
-            SocketAddr::new(addr, SERVER_PORT)
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), SERVER_PORT)
         };
 
         log::info!("Will connect to server: {}", server_socket_address);
-        let mut client_socket = ClientSocket::connect(server_socket_address);
-        if cfg!(debug_assertions) {
-            client_socket =
-                client_socket.with_link_conditioner(&LinkConditionerConfig::good_condition());
-        }
-        let sender = client_socket.get_sender();
-
-        let (event_sender, event_receiver) = unbounded();
-        let mut messenger = PacketMessenger {
-            config: LaminarConfig::default(),
-            sender,
-            event_sender,
-        };
-        let connection = VirtualConnection::create_connection(
-            &mut messenger,
-            server_socket_address,
-            Instant::now(),
-        );
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+            .expect("cannot bind client socket");
+        socket
+            .set_nonblocking(true)
+            .expect("cannot set client socket non-blocking");
 
         Networking {
-            server_address: "0.0.0.0:0".parse().unwrap(),
-            client_socket,
-            messenger,
-            event_receiver,
-            connection,
+            server_address: server_socket_address,
+            socket,
+            channels: Channels::default(),
+            simulator: NetworkSimulator::new(config.net.sim.clone()),
             connection_key: "1337".to_string(),
             nick_name: "Player".to_string(),
             state: ConnectionState::Disconnected,
+            protocol_version: None,
             stats: Default::default(),
+            fragments: Default::default(),
+            registry: components::replication_registry(),
             backoff_round: 0,
             authorized: false,
             cvars_received: false,
+            match_state: None,
 
             tick: 0,
             server_tick_received: 0,
-
-            control: Default::default(),
+            server_tick_received_at: Instant::now(),
+            input_ack_tick: 0,
+
+            prediction: Default::default(),
+            entities: Default::default(),
+            pawn: None,
+            states: Default::default(),
+            interpolation: Default::default(),
             game_event_sender,
         }
     }
 
-    pub fn update(&mut self) {
-        let time = Instant::now();
-        let messenger = &mut self.messenger;
-
-        // pull all newly arrived packets and handle them
-        loop {
-            match self.client_socket.receive() {
-                Ok(event) => match event {
-                    Some(packet) => {
-                        let data = packet.payload();
-                        let len = data.len();
-                        log::debug!("<-- Received {} bytes", len);
-                        self.stats.add_rx(len);
-                        trace_dump_packet(data);
+    /// Passes datagrams released by network simulator to the socket
+    fn flush(&mut self) {
+        let now = Instant::now();
+        for (_, datagram) in self.channels.poll(now) {
+            self.simulator.send(now, datagram);
+        }
 
-                        if len > 0 {
-                            self.connection.process_packet(messenger, data, time);
-                        }
-                    }
-                    None => {
-                        break;
-                    }
-                },
-                Err(err) => {
-                    log::error!("Client Error: {}", err);
-                    self.state = ConnectionState::Error;
-                }
+        for data in self.simulator.poll(now) {
+            log::debug!("--> Sending {} bytes", data.len());
+            trace_dump_packet(&data);
+            if let Err(error) = self.socket.send_to(&data, self.server_address) {
+                log::error!("Error sending payload: {}", error);
             }
         }
-
-        // update connection
-        self.connection.update(messenger, time);
     }
 
-    pub fn process(&mut self, resources: &Resources, client: &mut Client<GameWorld>) {
+    pub fn process(&mut self, resources: &Resources, config: &mut Config, world: &mut World) {
         if self.state == ConnectionState::Disconnected {
             if backoff_enabled(self.backoff_round) {
                 let msg = messages::connection_request();
                 log::info!("--> Connecting server (backoff {})", self.backoff_round);
-                self.send(LaminarPacket::unreliable(self.server_address, msg.to_vec()));
+                self.send(Packet::unreliable(self.server_address, msg.to_vec()));
             }
             self.backoff_round += 1;
         }
 
-        while let Ok(event) = self.event_receiver.try_recv() {
-            match event {
-                laminar::SocketEvent::Packet(packet) => {
-                    self.process_packet(packet, resources, client)
-                }
-                laminar::SocketEvent::Connect(addr) => {
-                    log::info!("!! Connect {}", addr)
-                }
-                laminar::SocketEvent::Timeout(addr) => {
-                    log::info!("!! Timeout {}", addr)
+        // pull all newly arrived datagrams and handle them
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, address)) => {
+                    if address != self.server_address {
+                        log::warn!("Dropping {} bytes from unknown [{}]", len, address);
+                        continue;
+                    }
+                    let data = &buffer[..len];
+                    log::debug!("<-- Received {} bytes", len);
+                    self.stats.add_rx(len);
+                    trace_dump_packet(data);
+
+                    if len > 0 {
+                        for payload in self.channels.receive(address, data) {
+                            self.process_packet(&payload, resources, config, world);
+                        }
+                    }
                 }
-                laminar::SocketEvent::Disconnect(addr) => {
-                    log::info!("!! Disconnect {}", addr)
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    log::error!("Client Error: {}", error);
+                    self.state = ConnectionState::Error;
+                    break;
                 }
             }
         }
 
         if self.state == ConnectionState::Connected {
-            let mut inputs = self
-                .control
-                .iter()
-                .map(|(&key, v)| (key, v.0, v.1, v.2))
-                .collect::<Vec<(usize, Control, i32, i32)>>();
+            let inputs: Vec<(usize, Control, Vec2)> =
+                self.prediction.inputs(self.input_ack_tick).collect();
 
             if !inputs.is_empty() {
-                inputs.sort_by_key(|v| v.0);
-
                 let msg = NetworkMessage::ControlState {
                     ack_tick: self.server_tick_received,
                     begin_tick: inputs[0].0,
                     control: inputs
                         .iter()
-                        // TODO: pack aim vector as two u8
-                        .map(|&(_t, c, x, y)| (c, vec2(x as f32, y as f32).normalize_or_zero()))
+                        .map(|&(_t, control, aim)| (control, aim))
                         .collect(),
                 };
                 log::debug!("--> Sending {:?}", msg);
-                self.send(LaminarPacket::unreliable(
+                self.send(Packet::unreliable(
                     self.server_address,
                     messages::encode_message(msg).to_vec(),
                 ));
@@ -228,19 +220,42 @@ impl Networking {
         }
     }
 
-    pub fn send(&mut self, event: LaminarPacket) {
-        self.stats.add_tx(event.payload().len());
-        self.connection
-            .process_event(&mut self.messenger, event, Instant::now());
+    /// Sends command to be executed on server
+    pub fn send_command<S: Into<String>>(&mut self, command: S) {
+        let msg = NetworkMessage::Command {
+            tick: self.tick,
+            command: NetCommand::Command(command.into()),
+        };
+        log::debug!("--> Sending {:?}", msg);
+        self.send(Packet::reliable_ordered(
+            self.server_address,
+            messages::encode_message(msg).to_vec(),
+        ));
+    }
+
+    /// Tells server we are leaving, so it does not wait for timeout
+    pub fn disconnect(&mut self) {
+        if self.state == ConnectionState::Connected {
+            log::info!("--> Disconnecting");
+            let msg = messages::connection_disconnect(DisconnectReason::Quit);
+            self.send(Packet::unreliable(self.server_address, msg.to_vec()));
+        }
+        self.state = ConnectionState::Closed(DisconnectReason::Quit, String::new());
+    }
+
+    pub fn send(&mut self, packet: Packet) {
+        self.stats.add_tx(packet.payload().len());
+        self.channels.send(packet);
+        self.flush();
     }
 
     fn process_packet(
         &mut self,
-        packet: LaminarPacket,
+        data: &[u8],
         resources: &Resources,
-        client: &mut Client<GameWorld>,
+        config: &mut Config,
+        world: &mut World,
     ) {
-        let data = packet.payload();
         if data.is_empty() {
             return;
         }
@@ -251,40 +266,119 @@ impl Networking {
                 messages::OperationCode::CCREP_ACCEPT => {
                     if self.state == ConnectionState::Disconnected && messages::packet_verify(data)
                     {
-                        log::info!("<-> Connection accepted");
-                        self.state = ConnectionState::Connected;
-
-                        self.send(LaminarPacket::reliable_unordered(
-                            self.server_address,
-                            messages::encode_message(NetworkMessage::ConnectionAuthorize {
-                                nick: self.nick_name.clone(),
-                                key: self.connection_key.clone(),
-                            })
-                            .to_vec(),
-                        ));
+                        match messages::decode_accept(data) {
+                            Ok((version, _, build))
+                                if !VersionInfo::current().supports(version) =>
+                            {
+                                log::error!(
+                                    "<-> Server {} picked unsupported protocol v{}, we speak {}",
+                                    build,
+                                    version,
+                                    VersionInfo::current()
+                                );
+                                self.state = ConnectionState::VersionMismatch(VersionInfo {
+                                    min: version,
+                                    max: version,
+                                    build,
+                                });
+                            }
+                            Ok((version, nonce, build)) => {
+                                log::info!(
+                                    "<-> Connection accepted by {} (protocol v{})",
+                                    build,
+                                    version
+                                );
+                                self.state = ConnectionState::Connected;
+                                self.protocol_version = Some(version);
+
+                                let mac = auth_mac(&self.connection_key, &nonce, &self.nick_name);
+                                self.send(Packet::reliable_unordered(
+                                    self.server_address,
+                                    messages::encode_message(NetworkMessage::ConnectionAuthorize {
+                                        nick: self.nick_name.clone(),
+                                        mac,
+                                    })
+                                    .to_vec(),
+                                ));
+                            }
+                            Err(error) => {
+                                log::error!("Malformed accept packet: {}", error);
+                                trace_dump_packet(data);
+                            }
+                        }
                     }
                 }
-                messages::OperationCode::CCREP_REJECT => {
-                    log::info!("<-> Connection rejected");
-                    self.state = ConnectionState::Error;
-                }
+                messages::OperationCode::CCREP_REJECT => match messages::decode_reject(data) {
+                    Ok((_, Some(server))) => {
+                        log::error!(
+                            "<-> Connection rejected: server speaks {}, we speak {}",
+                            server,
+                            VersionInfo::current()
+                        );
+                        self.state = ConnectionState::VersionMismatch(server);
+                    }
+                    Ok((reason, None)) => {
+                        log::error!("<-> Connection rejected: {}", reason);
+                        self.state = ConnectionState::Rejected(reason);
+                    }
+                    Err(error) => {
+                        log::error!("<-> Connection rejected: {}", error);
+                        self.state = ConnectionState::Error;
+                    }
+                },
                 messages::OperationCode::CCREP_AUTHORIZED => {
-                    self.authorized = true;
-                    if data.len() > 1 {
-                        let motd_len = data[1] as usize;
-                        if data.len() >= motd_len + 2 {
-                            let motd = String::from_utf8_lossy(&data[2..motd_len + 2]);
+                    match messages::decode_authorized(data) {
+                        Ok((motd, entity)) => {
+                            self.authorized = true;
+                            self.pawn = Some(entity);
                             log::info!("Got server MOTD: {}", motd);
                         }
+                        Err(error) => {
+                            log::error!("Malformed authorization packet: {}", error);
+                            trace_dump_packet(data);
+                        }
+                    }
+                }
+                messages::OperationCode::STT_PING | messages::OperationCode::STT_PONG => {
+                    match messages::decode_ping(data) {
+                        Ok((messages::OperationCode::STT_PING, sequence)) => {
+                            let msg = messages::pong(sequence);
+                            self.send(Packet::unreliable(self.server_address, msg.to_vec()));
+                        }
+                        Ok((_, sequence)) => self.stats.pong(sequence),
+                        Err(error) => log::error!("Malformed ping packet: {}", error),
+                    }
+                }
+                messages::OperationCode::CCREP_KICK => match messages::decode_disconnect(data) {
+                    Ok((reason, message)) => {
+                        log::error!("<-> Disconnected by server: {} {}", reason, message);
+                        self.state = ConnectionState::Closed(reason, message);
+                    }
+                    Err(error) => {
+                        log::error!("<-> Disconnected by server: {}", error);
+                        self.state = ConnectionState::Error;
+                    }
+                },
+                messages::OperationCode::STT_FRAGMENT => {
+                    if let Some(data) = self.fragments.insert(data) {
+                        if let Err(error) = self.process_message(&data, resources, config, world) {
+                            log::error!(
+                                "Unhandled reassembled packet: {} bytes: {}",
+                                data.len(),
+                                error
+                            );
+                            trace_dump_packet(&data);
+                        }
                     }
                 }
                 _ => {
-                    if !self.process_message(data, resources, client) {
+                    if let Err(error) = self.process_message(data, resources, config, world) {
                         log::error!(
-                            "Unhandled packet: 0x{:x} ({:?}) {} bytes",
+                            "Unhandled packet: 0x{:x} ({:?}) {} bytes: {}",
                             code,
                             op_code,
-                            data.len()
+                            data.len(),
+                            error
                         );
                         trace_dump_packet(data);
                     }
@@ -300,145 +394,368 @@ impl Networking {
         &mut self,
         data: &[u8],
         resources: &Resources,
-        client: &mut Client<GameWorld>,
-    ) -> bool {
-        if let Some(msg) = messages::decode_message(data) {
-            match msg {
-                NetworkMessage::ConnectionAuthorize { .. }
-                | NetworkMessage::ControlState { .. } => {
-                    log::error!("Should not receive message: {:?}", msg);
-                }
-                NetworkMessage::Cvars(cvars) => {
-                    log::info!("--- cvars server sync:");
-                    let config = &mut *resources.get_mut::<Config>().unwrap();
-                    for (path, val) in cvars {
-                        if let Some(old_val) = cvar::console::get(config, path.as_str()) {
-                            if old_val == val {
-                                continue;
-                            }
-                            match cvar::console::set(config, path.as_str(), val.as_str()) {
-                                Ok(set) => {
-                                    if set {
-                                        log::info!("{} = `{}`", path, val);
-                                    }
-                                }
-                                Err(err) => {
-                                    log::error!("Error for {} = `{}`: {}", path, val, err)
+        config: &mut Config,
+        world: &mut World,
+    ) -> Result<(), DecodeError> {
+        let msg = messages::decode_message(data)?;
+        match msg {
+            NetworkMessage::Command {
+                command: NetCommand::Output(output),
+                ..
+            } => {
+                log::info!("{}", output);
+            }
+            NetworkMessage::ConnectionAuthorize { .. }
+            | NetworkMessage::ControlState { .. }
+            | NetworkMessage::Command { .. } => {
+                log::error!("Should not receive message: {:?}", msg);
+            }
+            NetworkMessage::Cvars(cvars) => {
+                log::info!("--- cvars server sync:");
+                for (path, val) in cvars {
+                    if let Some(old_val) = cvar::console::get(config, path.as_str()) {
+                        if old_val == val {
+                            continue;
+                        }
+                        match cvar::console::set(config, path.as_str(), val.as_str()) {
+                            Ok(set) => {
+                                if set {
+                                    log::info!("{} = `{}`", path, val);
                                 }
                             }
+                            Err(err) => {
+                                log::error!("Error for {} = `{}`: {}", path, val, err)
+                            }
                         }
                     }
-
-                    if !self.cvars_received {
-                        self.send(LaminarPacket::reliable_unordered(
-                            self.server_address,
-                            messages::connection_ready().to_vec(),
-                        ));
-                    }
-                    self.cvars_received = true;
-                    if let Err(err) = self
-                        .game_event_sender
-                        .try_send(engine::Event::ConfigChanged)
-                    {
-                        log::error!("Cannot send engine::Event: {}", err);
-                    }
                 }
-                NetworkMessage::GameState { tick, entities } => {
-                    if tick > self.server_tick_received {
-                        self.server_tick_received = tick;
-                        self.tick = tick;
 
-                        // TODO: integrate game state to ECS world
-                        log::debug!("Entity sync: {:?}", entities);
-                    }
+                if !self.cvars_received {
+                    self.send(Packet::reliable_unordered(
+                        self.server_address,
+                        messages::connection_ready().to_vec(),
+                    ));
                 }
-                NetworkMessage::Snapshot(snapshot) => {
-                    log::debug!("Got snapshot {}", snapshot.timestamp());
-                    client.client.enqueue_incoming_snapshot(snapshot);
+                self.cvars_received = true;
+                if let Err(err) = self
+                    .game_event_sender
+                    .try_send(engine::Event::ConfigChanged)
+                {
+                    log::error!("Cannot send engine::Event: {}", err);
                 }
-                NetworkMessage::Command(command) => {
-                    log::debug!("Got command {}: {:?}", command.timestamp(), command.inner());
-                    client.client.enqueue_incoming_command(command);
+            }
+            NetworkMessage::MatchState { tick, state, ends } => {
+                log::info!("Match {:?} since tick {}", state, tick);
+                self.match_state = Some((state, ends));
+            }
+            NetworkMessage::MapChange(name) => {
+                log::info!("Server changed map to {}", name);
+                // inputs predicted on previous map cannot be replayed on the new one
+                self.prediction.clear();
+                if let Err(err) = self
+                    .game_event_sender
+                    .try_send(engine::Event::MapChange(name))
+                {
+                    log::error!("Cannot send engine::Event: {}", err);
+                }
+            }
+            NetworkMessage::GameState {
+                tick,
+                base_tick,
+                ack_tick,
+                entities,
+                removed,
+                despawned,
+            } => {
+                if tick > self.server_tick_received {
+                    let delta = SnapshotDelta {
+                        entities,
+                        removed,
+                        despawned,
+                    };
+                    log::debug!(
+                        "Entity sync {} (base {}): {} changed, {} removed, {} despawned",
+                        tick,
+                        base_tick,
+                        delta.entities.len(),
+                        delta.removed.len(),
+                        delta.despawned.len()
+                    );
+                    let state = if base_tick == 0 {
+                        delta.apply(&EntitiesSnapshot::new())
+                    } else if let Some(base) = self.states.get(base_tick) {
+                        delta.apply(base)
+                    } else {
+                        log::warn!("Dropping entity sync {}: base {} is gone", tick, base_tick);
+                        return Ok(());
+                    };
+
+                    self.server_tick_received = tick;
+                    self.server_tick_received_at = Instant::now();
+                    self.input_ack_tick = ack_tick;
+                    // client runs ahead of server, so its inputs arrive in time
+                    self.tick = usize::max(self.tick, tick);
+
+                    self.apply_game_state(world, tick, base_tick, state)?;
+                    let map = resources.get::<MapFile>().unwrap();
+                    self.reconcile(world, &map, config.phys.gravity)?;
+                }
+            }
+            NetworkMessage::Snapshot { tick, snapshot } => {
+                if tick >= self.server_tick_received {
+                    log::info!("Resync to snapshot {}", tick);
+                    self.server_tick_received = tick;
+                    self.server_tick_received_at = Instant::now();
+                    self.tick = usize::max(self.tick, tick);
+
+                    self.apply_game_state(world, tick, 0, snapshot.entities)?;
+                    let map = resources.get::<MapFile>().unwrap();
+                    self.reconcile(world, &map, config.phys.gravity)?;
                 }
             }
-            return true;
         }
-
-        false
+        Ok(())
     }
 
-    pub fn set_input_state(&mut self, control: &crate::control::Control) {
-        let mut flags = Control::default();
-        if control.left {
-            flags.insert(Control::LEFT);
-        }
-        if control.right {
-            flags.insert(Control::RIGHT);
-        }
-        if control.up {
-            flags.insert(Control::UP);
-        }
-        if control.down {
-            flags.insert(Control::DOWN);
-        }
-        if control.fire {
-            flags.insert(Control::FIRE);
-        }
-        if control.jets {
-            flags.insert(Control::JETS);
+    /// Brings local entities to server `state` at `tick` and keeps it as a base for next deltas
+    fn apply_game_state(
+        &mut self,
+        world: &mut World,
+        tick: usize,
+        base_tick: usize,
+        state: EntitiesSnapshot,
+    ) -> Result<(), DecodeError> {
+        self.buffer_positions(tick, &state)?;
+
+        if base_tick == 0 {
+            // full state - everything not listed is gone
+            let gone: Vec<Entity> = self
+                .entities
+                .keys()
+                .filter(|entity| !state.contains_key(entity))
+                .copied()
+                .collect();
+            for entity in gone {
+                if let Some(local) = self.entities.remove(&entity) {
+                    let _ = world.despawn(local);
+                }
+            }
         }
-        if control.grenade {
-            flags.insert(Control::GRENADE);
+
+        // changes against the state local world reflects now
+        let empty = EntitiesSnapshot::new();
+        let delta = SnapshotDelta::diff(self.states.latest().unwrap_or(&empty), &state);
+
+        for entity in delta.despawned {
+            if let Some(local) = self.entities.remove(&entity) {
+                let _ = world.despawn(local);
+            }
         }
-        if control.change {
-            flags.insert(Control::CHANGE);
+
+        for (entity, components) in delta.entities {
+            let local = match self.entities.get(&entity) {
+                Some(&local) if world.contains(local) => local,
+                _ => {
+                    let local = world.spawn(());
+                    self.entities.insert(entity, local);
+                    local
+                }
+            };
+            for component in components.iter() {
+                self.registry.insert(world, local, component)?;
+            }
         }
-        if control.throw {
-            flags.insert(Control::THROW);
+
+        for (entity, ids) in delta.removed {
+            if let Some(&local) = self.entities.get(&entity) {
+                for id in ids {
+                    self.registry.remove(world, local, id)?;
+                }
+            }
         }
-        if control.drop {
-            flags.insert(Control::DROP);
+
+        self.states.push(tick, state);
+        // server never goes back to a base older than the one it used already
+        self.states.acknowledge(base_tick);
+        Ok(())
+    }
+
+    /// Stores remote entities positions for interpolation. Pawn is predicted, so it is skipped.
+    fn buffer_positions(
+        &mut self,
+        tick: usize,
+        state: &EntitiesSnapshot,
+    ) -> Result<(), DecodeError> {
+        let position_id = match self.registry.id_of::<Position>() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let mut positions = HashMap::new();
+        for (&entity, components) in state {
+            if Some(entity) == self.pawn {
+                continue;
+            }
+            if let Some(component) = components.iter().find(|c| c.id == position_id) {
+                positions.insert(entity, component.get::<Position>()?.0);
+            }
         }
-        if control.reload {
-            flags.insert(Control::RELOAD);
+
+        self.interpolation
+            .push(tick as f64 * TIMESTEP_RATE, positions);
+        Ok(())
+    }
+
+    /// Moves remote entities to their positions `net.interpolation_delay` in the past
+    pub fn interpolate(&mut self, world: &mut World, config: &Config) {
+        let server_time = self.server_tick_received as f64 * TIMESTEP_RATE
+            + self.server_tick_received_at.elapsed().as_secs_f64();
+        let time = server_time - config.net.interpolation_delay as f64 / 1000.;
+        let positions = self
+            .interpolation
+            .sample(time, config.net.extrapolation_limit as f64 / 1000.);
+
+        for (entity, position) in positions {
+            if let Some(&local) = self.entities.get(&entity) {
+                if let Ok(mut pos) = world.get_mut::<Position>(local) {
+                    pos.0 = position;
+                }
+            }
         }
-        if control.prone {
-            flags.insert(Control::PRONE);
+    }
+
+    /// Statistics of the connection, once established
+    pub fn stats(&self) -> Option<&PacketStats> {
+        match self.state {
+            ConnectionState::Connected => Some(&self.stats),
+            _ => None,
         }
-        if control.flag_throw {
-            flags.insert(Control::FLAG_THROW);
+    }
+
+    /// Local entity of the pawn controlled by this client
+    pub fn pawn(&self, world: &World) -> Option<Entity> {
+        self.pawn
+            .and_then(|pawn| self.entities.get(&pawn).copied())
+            .filter(|&local| world.contains(local))
+    }
+
+    /// Controls server does not take from the pawn now - predicting them would go wrong
+    pub fn disallowed_controls(&self, world: &World) -> Control {
+        let dead = self.pawn(world).map_or(false, |pawn| {
+            world
+                .get::<SoldierState>(pawn)
+                .map_or(false, |state| state.health == 0)
+        });
+        let state = self
+            .match_state
+            .map_or(GameState::InGame, |(state, _ends)| state);
+        Control::disallowed(dead, state)
+    }
+
+    /// Compares predicted pawn with its authoritative position and replays inputs
+    /// not yet applied by server on top of it, if prediction went wrong
+    fn reconcile(
+        &mut self,
+        world: &mut World,
+        map: &MapFile,
+        gravity: f32,
+    ) -> Result<(), DecodeError> {
+        let local = match self.pawn(world) {
+            Some(local) => local,
+            None => return Ok(()),
+        };
+        let position_id = self.registry.id_of::<Position>();
+        let position = match self
+            .pawn
+            .and_then(|pawn| self.states.latest()?.get(&pawn))
+            .and_then(|components| components.iter().find(|c| Some(c.id) == position_id))
+        {
+            Some(component) => component.get::<Position>()?.0,
+            None => return Ok(()),
+        };
+
+        if let Ok(mut soldier) = world.get_mut::<Soldier>(local) {
+            let mispredicted = self.prediction.reconcile(
+                self.input_ack_tick,
+                &mut *soldier,
+                |soldier| {
+                    let error = position - soldier.particle.pos;
+                    if error.length() <= PREDICTION_TOLERANCE {
+                        return false;
+                    }
+                    soldier.particle.pos += error;
+                    soldier.particle.old_pos += error;
+                    true
+                },
+                |soldier, control, aim| soldier.step(control, aim, map, &mut Vec::new(), gravity),
+            );
+            if mispredicted {
+                log::debug!("Pawn mispredicted at tick {}", self.input_ack_tick);
+            }
         }
+        sync_position(world, local);
+        Ok(())
+    }
 
-        self.control
-            .insert(self.tick, (flags, control.mouse_aim_x, control.mouse_aim_y));
+    /// Applies current tick input to the pawn ahead of server and advances the tick
+    pub fn predict(
+        &mut self,
+        world: &mut World,
+        map: &MapFile,
+        gravity: f32,
+        control: Control,
+        aim: Vec2,
+    ) {
+        if let Some(local) = self.pawn(world) {
+            if let Ok(mut soldier) = world.get_mut::<Soldier>(local) {
+                self.prediction.predict(
+                    self.tick,
+                    control,
+                    aim,
+                    &mut *soldier,
+                    |soldier, control, aim| {
+                        soldier.step(control, aim, map, &mut Vec::new(), gravity)
+                    },
+                );
+            }
+            sync_position(world, local);
+        }
+        self.tick += 1;
     }
 
     pub fn post_process(&mut self, config: &Config) {
-        let low_tick = usize::max(
-            self.server_tick_received,
-            self.tick - usize::min(self.tick, MAX_INPUTS_RETAIN),
-        );
-        let high_tick = self.tick;
-
-        self.control
-            .retain(move |&t, _| t > low_tick && t <= high_tick);
-
-        if self.state != ConnectionState::Disconnected {
-            if self.state != ConnectionState::Error
-                && config.net.send_keepalive > 0
-                && self.stats.last_tx.elapsed().as_secs_f32() > config.net.send_keepalive as f32
+        self.prediction.retain(self.input_ack_tick, self.tick);
+
+        if self.state == ConnectionState::Connected {
+            if let Some(sequence) = self.stats.ping(config.net.ping_interval) {
+                let msg = messages::ping(sequence);
+                self.send(Packet::unreliable(self.server_address, msg.to_vec()));
+            }
+            if config.net.send_keepalive > 0
+                && self.stats.last_tx.elapsed().as_millis() > config.net.send_keepalive as u128
             {
-                log::warn!("Sending keepalive packet");
-                if let Err(error) = self.messenger.sender.send(NaiaPacket::new(Vec::new())) {
-                    log::error!("Error sending keepalive: {}", error);
-                }
+                log::debug!("Sending keepalive packet");
+                self.send(Packet::unreliable(self.server_address, Vec::new()));
             }
             if config.net.keepalive_timeout > 0
-                && self.stats.last_rx.elapsed().as_secs_f32() > config.net.keepalive_timeout as f32
+                && self.stats.last_rx.elapsed().as_millis() > config.net.keepalive_timeout as u128
             {
-                log::error!("Server connection timeout - exiting");
-                std::process::abort();
+                log::error!("Server connection timeout");
+                self.state = ConnectionState::Closed(DisconnectReason::Timeout, String::new());
             }
         }
+
+        self.flush();
+    }
+}
+
+/// Moves pawn replicated `Position` to where its simulation is
+fn sync_position(world: &mut World, local: Entity) {
+    let pos = match world.get::<Soldier>(local) {
+        Ok(soldier) => soldier.particle.pos,
+        Err(_) => return,
+    };
+    if let Ok(mut position) = world.get_mut::<Position>(local) {
+        position.0 = pos;
     }
 }
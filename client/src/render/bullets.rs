@@ -1,5 +1,5 @@
 use super::*;
-use gfx::SpriteData;
+use gfx::{SpriteData, WeaponSprites};
 
 pub fn render_bullet(
     bullet: &Bullet,
@@ -39,7 +39,7 @@ pub fn render_bullet(
             );
         }
         _ => {
-            if let Some(sprite) = bullet.sprite {
+            if let Some(sprite) = bullet.weapon.bullet_sprite() {
                 let sprite = &sprites[sprite.group().id()][sprite.id()];
                 let hit = lerp(bullet.hit_multiply_prev, bullet.hit_multiply, frame_percent);
 
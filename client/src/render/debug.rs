@@ -71,7 +71,7 @@ pub fn debug_render(
             let [v1, v2, v3] = &poly.vertices;
             let w = 0.7 * zoom;
             let a = |mc: MapColor| {
-                let mut c: Color = mc.into();
+                let mut c = rgba(mc.r, mc.g, mc.b, mc.a);
                 if c.a < u8::MAX / 2 {
                     c.a = u8::MAX - c.a
                 }
@@ -35,6 +35,48 @@ impl std::ops::Sub<usize> for SoldierPart {
     }
 }
 
+pub trait WeaponSprites {
+    fn clip_sprite(&self) -> Option<Weapon>;
+    fn bullet_sprite(&self) -> Option<Weapon>;
+}
+
+impl WeaponSprites for WeaponKind {
+    fn clip_sprite(&self) -> Option<Weapon> {
+        match *self {
+            WeaponKind::DesertEagles => Some(Weapon::DeaglesClip),
+            WeaponKind::MP5 => Some(Weapon::Mp5Clip),
+            WeaponKind::Ak74 => Some(Weapon::Ak74Clip),
+            WeaponKind::SteyrAUG => Some(Weapon::SteyrClip),
+            WeaponKind::M79 => Some(Weapon::M79Clip),
+            WeaponKind::Barrett => Some(Weapon::BarrettClip),
+            WeaponKind::Minimi => Some(Weapon::MinimiClip),
+            WeaponKind::USSOCOM => Some(Weapon::SocomClip),
+            WeaponKind::FlameBow => Some(Weapon::BowS),
+            WeaponKind::Bow => Some(Weapon::BowS),
+            WeaponKind::Flamer => Some(Weapon::Flamer),
+            WeaponKind::FragGrenade => Some(Weapon::FragGrenade),
+            WeaponKind::ClusterGrenade => Some(Weapon::FragGrenade),
+            WeaponKind::Cluster => Some(Weapon::FragGrenade),
+            _ => None,
+        }
+    }
+
+    fn bullet_sprite(&self) -> Option<Weapon> {
+        match *self {
+            WeaponKind::DesertEagles => Some(Weapon::DeaglesBullet),
+            WeaponKind::MP5 => Some(Weapon::Mp5Bullet),
+            WeaponKind::Ak74 => Some(Weapon::Ak74Bullet),
+            WeaponKind::SteyrAUG => Some(Weapon::SteyrBullet),
+            WeaponKind::Ruger77 => Some(Weapon::RugerBullet),
+            WeaponKind::Barrett => Some(Weapon::BarrettBullet),
+            WeaponKind::Minimi => Some(Weapon::MinimiBullet),
+            WeaponKind::Minigun => Some(Weapon::MinigunBullet),
+            WeaponKind::USSOCOM => Some(Weapon::ColtBullet),
+            _ => None,
+        }
+    }
+}
+
 // Note: images that have a "2" version go together (opposite gostek direction).
 sprites! {
     // The order of the gostek images must match the order of the Team2 versions
@@ -0,0 +1,27 @@
+use super::*;
+use gfx::SpriteData;
+use soldank_shared::{components::Position, pickup::PickupKind};
+
+fn pickup_sprite(kind: PickupKind) -> gfx::Object {
+    match kind {
+        PickupKind::Medikit => gfx::Object::Medikit,
+        PickupKind::Grenades => gfx::Object::Grenadekit,
+        PickupKind::Clusters => gfx::Object::Clusterkit,
+        PickupKind::Vest => gfx::Object::Vestkit,
+        PickupKind::Berserker => gfx::Object::Berserkerkit,
+        PickupKind::Predator => gfx::Object::Predatorkit,
+    }
+}
+
+/// Draws kits lying around. Taken ones have no `PickupKind` until they respawn.
+pub fn render_pickups(world: &World, sprites: &[Vec<Sprite>], batch: &mut DrawBatch) {
+    for (_entity, (&kind, position)) in world.query::<(&PickupKind, &Position)>().iter() {
+        let object = pickup_sprite(kind);
+        let sprite = &sprites[object.group().id()][object.id()];
+        batch.add_sprite(
+            sprite,
+            rgb(255, 255, 255),
+            Transform::Pos(**position - vec2(sprite.width, sprite.height) / 2.),
+        );
+    }
+}
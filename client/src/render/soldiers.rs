@@ -1,6 +1,6 @@
 use super::*;
 use bit_array::BitArray;
-use gfx::{SoldierPart, SpriteData};
+use gfx::{SoldierPart, SpriteData, WeaponSprites};
 use ini::Ini;
 use std::str::FromStr;
 use typenum::U256;
@@ -387,7 +387,7 @@ fn parts_visibility(base_visibility: &BitSet, soldier: &Soldier, blood: bool) ->
 
             visible.set(first.id() + index, true);
 
-            if weapon.clip_sprite.is_some()
+            if weapon.kind.clip_sprite().is_some()
                 && (ammo > 0
                     || ammo == 0
                         && (reload_count < weapon.clip_in_time
@@ -0,0 +1,16 @@
+pub fn parse_cli_args() -> clap::ArgMatches {
+    clap::Command::new(env!("CARGO_PKG_NAME"))
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .arg(
+            clap::Arg::new("bind")
+                .value_name("address:port")
+                .help("IP address and port to bind")
+                .short('b')
+                .long("bind")
+                .num_args(1)
+                .env("SOLDANK_MASTER_BIND"),
+        )
+        .get_matches()
+}
@@ -0,0 +1,99 @@
+use bytes::Bytes;
+use color_eyre::eyre::Result;
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use soldank_shared::{
+    constants::MASTER_PORT,
+    messages::{self, OperationCode},
+    networking::{decode_out_of_band, out_of_band},
+    trace_dump_packet,
+};
+
+mod cli;
+mod servers;
+
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    env_logger::init();
+
+    let cmd = cli::parse_cli_args();
+
+    let bind_address = if let Some(addr) = cmd.get_one::<String>("bind") {
+        addr.parse().expect("cannot parse bind address")
+    } else {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MASTER_PORT)
+    };
+    let socket = UdpSocket::bind(bind_address)?;
+    socket.set_read_timeout(Some(EXPIRE_INTERVAL))?;
+    log::info!("Bound master socket: [{}]", bind_address);
+
+    let mut servers = servers::ServerList::default();
+    let mut buffer = [0; u16::MAX as usize];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((len, address)) => {
+                let data = &buffer[..len];
+                log::debug!("<-- Received {} bytes from [{}]", len, address);
+                trace_dump_packet(data);
+
+                if let Some(data) = decode_out_of_band(data) {
+                    for reply in process_packet(&mut servers, address, data) {
+                        if let Err(error) = socket.send_to(&out_of_band(&reply), address) {
+                            log::error!("Error sending payload to [{}]: {}", address, error);
+                        }
+                    }
+                }
+            }
+            Err(error)
+                if error.kind() == std::io::ErrorKind::WouldBlock
+                    || error.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(error) => {
+                log::error!("Socket error: {}", error);
+            }
+        }
+
+        servers.expire(Instant::now());
+    }
+}
+
+/// Handles master server request. Returns datagrams to send back.
+fn process_packet(
+    servers: &mut servers::ServerList,
+    address: SocketAddr,
+    data: &[u8],
+) -> Vec<Bytes> {
+    let code = match data.first() {
+        Some(&code) => code,
+        None => return Vec::new(),
+    };
+
+    match OperationCode::try_from(code) {
+        Ok(OperationCode::MSREQ_HEARTBEAT) => match messages::decode_heartbeat(data) {
+            Ok(status) => {
+                log::debug!("Heartbeat from [{}]: {:?}", address, status);
+                servers.heartbeat(Instant::now(), address, status);
+            }
+            Err(error) => log::error!("Malformed heartbeat from [{}]: {}", address, error),
+        },
+        Ok(OperationCode::MSREQ_LIST) if messages::packet_verify(data) => {
+            if let Some(list) = servers.list(Instant::now(), address) {
+                log::debug!("--> Sending {} servers to [{}]", list.len(), address);
+                return messages::master_list(&list);
+            }
+        }
+        Ok(op_code) => {
+            log::error!("Unhandled packet from [{}]: {:?}", address, op_code);
+        }
+        Err(_) => {
+            log::error!("Unknown packet from [{}]: 0x{:x}", address, code);
+        }
+    }
+
+    Vec::new()
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use soldank_shared::{constants::MASTER_HEARTBEAT, messages::ServerStatus};
+
+/// servers missing this many heartbeats in a row are dropped
+const MISSED_HEARTBEATS: u32 = 3;
+/// min time between list replies to the same host
+const LIST_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_SERVERS_PER_HOST: usize = 32;
+
+/// Servers registered through heartbeats
+#[derive(Debug, Default)]
+pub struct ServerList {
+    servers: HashMap<SocketAddr, (Instant, ServerStatus)>,
+    listed: HashMap<IpAddr, Instant>,
+}
+
+impl ServerList {
+    pub fn heartbeat(&mut self, now: Instant, addr: SocketAddr, status: ServerStatus) {
+        if !self.servers.contains_key(&addr)
+            && self
+                .servers
+                .keys()
+                .filter(|server| server.ip() == addr.ip())
+                .count()
+                >= MAX_SERVERS_PER_HOST
+        {
+            log::warn!("Too many servers from [{}] - ignoring", addr.ip());
+            return;
+        }
+
+        if self.servers.insert(addr, (now, status)).is_none() {
+            log::info!("Registered server [{}]", addr);
+        }
+    }
+
+    /// Registered servers, unless `addr` asked for them too recently
+    pub fn list(
+        &mut self,
+        now: Instant,
+        addr: SocketAddr,
+    ) -> Option<Vec<(SocketAddr, ServerStatus)>> {
+        if let Some(&last) = self.listed.get(&addr.ip()) {
+            if now.duration_since(last) < LIST_INTERVAL {
+                log::debug!("Throttling list request from [{}]", addr);
+                return None;
+            }
+        }
+        self.listed.insert(addr.ip(), now);
+
+        Some(
+            self.servers
+                .iter()
+                .map(|(&addr, (_, status))| (addr, status.clone()))
+                .collect(),
+        )
+    }
+
+    /// Drops servers which stopped sending heartbeats
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = Duration::from_millis((MASTER_HEARTBEAT * MISSED_HEARTBEATS) as u64);
+        self.servers.retain(|addr, (last, _)| {
+            let alive = now.duration_since(*last) < timeout;
+            if !alive {
+                log::info!("Server [{}] timed out", addr);
+            }
+            alive
+        });
+        self.listed
+            .retain(|_, last| now.duration_since(*last) < LIST_INTERVAL);
+    }
+}
@@ -1,8 +1,214 @@
 use bitflags::bitflags;
+use hecs::{Entity, World};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    constants::*,
+    cvars::Config,
+    networking::{Connection, Networking},
+    systems::Time,
+};
+use soldank_shared::{
+    control::Control,
+    math::Vec2,
+    messages::{DisconnectReason, GameState},
+    soldier::Soldier,
+};
 
 bitflags! {
     #[derive(Default)]
     pub struct Cheats: u32 {
-        const MOUSE_AIM = 0b00000000000000000000000000000001;
+        const MOUSE_AIM     = 0b00000000000000000000000000000001;
+        /// input for ticks too far in the future or acknowledging a tick not sent yet
+        const TICK_RANGE    = 0b00000000000000000000000000000010;
+        /// more inputs in single `ControlState` than client can possibly buffer
+        const INPUT_FLOOD   = 0b00000000000000000000000000000100;
+        /// aim vector which is not normalized
+        const AIM_RANGE     = 0b00000000000000000000000000001000;
+        /// wrong admin password
+        const ADMIN_LOGIN   = 0b00000000000000000000000000010000;
+    }
+}
+
+/// Cheat violations detected within last `cheat.window` seconds
+#[derive(Debug, Default)]
+pub struct Violations {
+    detected: VecDeque<Instant>,
+}
+
+impl Violations {
+    /// Records violation detected at `now`, returns number of violations in `window`
+    pub fn add(&mut self, now: Instant, window: Duration) -> u32 {
+        self.expire(now, window);
+        self.detected.push_back(now);
+        self.count()
+    }
+
+    /// Forgets violations older than `window`
+    pub fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some(&at) = self.detected.front() {
+            if now.saturating_duration_since(at) <= window {
+                break;
+            }
+            self.detected.pop_front();
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.detected.len() as u32
+    }
+}
+
+/// Checks tick range and inputs count of received `ControlState`
+pub fn validate_control_state(
+    time: &Time,
+    ack_tick: usize,
+    begin_tick: usize,
+    count: usize,
+) -> Result<(), Cheats> {
+    if count > MAX_INPUTS_PER_MESSAGE {
+        return Err(Cheats::INPUT_FLOOD);
+    }
+    if ack_tick > time.tick || begin_tick.saturating_add(count) > time.tick + MAX_INPUT_LEAD {
+        return Err(Cheats::TICK_RANGE);
+    }
+    Ok(())
+}
+
+/// Aim vector has to be normalized, or zero when there is no aim
+pub fn valid_aim(aim: Vec2) -> bool {
+    aim.is_finite() && (aim == Vec2::ZERO || (aim.length() - 1.).abs() <= AIM_EPSILON)
+}
+
+/// Controls the soldier `entity` cannot use in its current state and match `state`.
+/// These are stripped from input, but not reported - client learns about its death
+/// or match state change a round trip later, honest players keep sending them meanwhile.
+pub fn disallowed_controls(world: &World, entity: Entity, state: GameState) -> Control {
+    match world.get::<Soldier>(entity) {
+        // FLAG_THROW is harmless without a flag, and may still be held after throwing it
+        Ok(soldier) => Control::disallowed(soldier.dead_meat, state),
+        Err(_) => Control::all(),
+    }
+}
+
+/// Records cheat `violation` of a connection
+pub fn report(
+    connection: &mut Connection,
+    address: SocketAddr,
+    violation: Cheats,
+    config: &Config,
+) {
+    connection.cheats |= violation;
+    let violations = connection.violations.add(Instant::now(), window(config));
+    log::debug!(
+        "[{}] {:?} violation {:?} ({})",
+        address,
+        connection.nick,
+        violation,
+        violations
+    );
+
+    if config.cheat.warn > 0 && violations == config.cheat.warn {
+        log::warn!(
+            "[{}] {:?} is suspected of cheating: {:?}",
+            address,
+            connection.nick,
+            connection.cheats
+        );
+    }
+}
+
+/// Kicks or bans connections which exceeded configured violations thresholds
+pub fn enforce(world: &mut World, networking: &mut Networking, config: &Config) {
+    let mut offenders = Vec::new();
+    let now = Instant::now();
+
+    for (&address, connection) in networking.connections.iter_mut() {
+        connection.violations.expire(now, window(config));
+        let violations = connection.violations.count();
+        if config.cheat.ban > 0 && violations >= config.cheat.ban {
+            offenders.push((address, DisconnectReason::Banned, connection.cheats));
+        } else if config.cheat.kick > 0 && violations >= config.cheat.kick {
+            offenders.push((address, DisconnectReason::Kicked, connection.cheats));
+        }
+    }
+
+    for (address, reason, cheats) in offenders {
+        log::warn!("[{}] {} for cheating: {:?}", address, reason, cheats);
+        if reason == DisconnectReason::Banned {
+            networking.bans.insert(address.ip());
+        }
+        networking.kick(world, address, reason, "cheating detected");
+    }
+}
+
+fn window(config: &Config) -> Duration {
+    Duration::from_secs(config.cheat.window as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soldank_shared::math::vec2;
+
+    fn time(tick: usize) -> Time {
+        Time {
+            time: Instant::now(),
+            tick,
+            frame_percent: 1.,
+        }
+    }
+
+    #[test]
+    fn control_state() {
+        let time = time(1000);
+        assert_eq!(validate_control_state(&time, 990, 995, 10), Ok(()));
+        assert_eq!(
+            validate_control_state(&time, 990, 995, MAX_INPUTS_PER_MESSAGE + 1),
+            Err(Cheats::INPUT_FLOOD)
+        );
+        assert_eq!(
+            validate_control_state(&time, 1001, 995, 10),
+            Err(Cheats::TICK_RANGE)
+        );
+        assert_eq!(
+            validate_control_state(&time, 990, 1000 + MAX_INPUT_LEAD, 1),
+            Err(Cheats::TICK_RANGE)
+        );
+        assert_eq!(
+            validate_control_state(&time, 990, usize::MAX, 2),
+            Err(Cheats::TICK_RANGE)
+        );
+    }
+
+    #[test]
+    fn aim() {
+        assert!(valid_aim(Vec2::ZERO));
+        assert!(valid_aim(vec2(0., -1.)));
+        assert!(valid_aim(vec2(0.6, 0.8)));
+        assert!(!valid_aim(vec2(0.5, 0.5)));
+        assert!(!valid_aim(vec2(3., 4.)));
+        assert!(!valid_aim(vec2(f32::NAN, 1.)));
+        assert!(!valid_aim(vec2(f32::INFINITY, 0.)));
+    }
+
+    #[test]
+    fn violations_window() {
+        let now = Instant::now();
+        let secs = |secs| now + Duration::from_secs(secs);
+        let window = Duration::from_secs(10);
+        let mut violations = Violations::default();
+
+        assert_eq!(violations.add(now, window), 1);
+        assert_eq!(violations.add(secs(5), window), 2);
+        assert_eq!(violations.add(secs(10), window), 3);
+        // first violation is out of window
+        assert_eq!(violations.add(secs(11), window), 3);
+        violations.expire(secs(30), window);
+        assert_eq!(violations.count(), 0);
     }
 }
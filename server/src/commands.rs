@@ -0,0 +1,323 @@
+use gvfs::filesystem::Filesystem;
+use hecs::World;
+use std::{collections::VecDeque, net::SocketAddr, time::Instant};
+
+use crate::{
+    cheat::{self, Cheats},
+    constants::*,
+    cvars::Config,
+    maps::{self, MapRotation},
+    networking::{Connection, Networking},
+    systems::Time,
+};
+use soldank_shared::{
+    messages::{DisconnectReason, NetworkMessage},
+    networking::NetCommand,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Player,
+    Admin,
+}
+
+impl Permission {
+    fn of(connection: &Connection) -> Permission {
+        if connection.admin {
+            Permission::Admin
+        } else {
+            Permission::Player
+        }
+    }
+}
+
+/// Permission required to execute command, `None` if command is unknown
+fn required_permission(command: &str) -> Option<Permission> {
+    match command {
+        "resync" | "votemap" | "login" => Some(Permission::Player),
+        "set" | "kick" | "status" | "map" => Some(Permission::Admin),
+        _ => None,
+    }
+}
+
+/// Checks whether `permission` is enough to execute command `name`
+fn check_permission(permission: Permission, name: &str) -> Result<(), String> {
+    match required_permission(name) {
+        Some(required) if permission >= required => Ok(()),
+        Some(_) => Err(format!("Not permitted to execute `{}`", name)),
+        None => Err(format!("Unknown command `{}`", name)),
+    }
+}
+
+/// Splits command line into command name and its arguments
+fn parse(command: &str) -> (&str, Vec<&str>) {
+    let mut args = command.split_whitespace();
+    let name = args.next().unwrap_or_default();
+    (name, args.collect())
+}
+
+pub fn process_commands(
+    world: &mut World,
+    config: &mut Config,
+    time: &Time,
+    networking: &mut Networking,
+    rotation: &mut MapRotation,
+    fs: &Filesystem,
+    messages: &mut VecDeque<(SocketAddr, NetworkMessage)>,
+) {
+    let mut unprocessed = Vec::new();
+
+    for (addr, message) in messages.drain(..) {
+        match message {
+            NetworkMessage::Command {
+                tick,
+                command: NetCommand::Command(command),
+            } => {
+                let permission = match networking.connections.get(&addr) {
+                    Some(connection) => Permission::of(connection),
+                    None => {
+                        log::error!("Command from unknown connection: [{}]", addr);
+                        continue;
+                    }
+                };
+
+                let (name, args) = parse(&command);
+                let output = check_permission(permission, name).and_then(|()| {
+                    // do not log admin password
+                    if name == "login" {
+                        log::info!("[{}] executes `{}` (tick {})", addr, name, tick);
+                    } else {
+                        log::info!("[{}] executes `{}` (tick {})", addr, command, tick);
+                    }
+                    execute(
+                        world, config, time, networking, rotation, fs, addr, name, &args,
+                    )
+                });
+
+                let output = match output {
+                    Ok(output) => {
+                        log::info!("[{}] {}", addr, output);
+                        output
+                    }
+                    Err(error) => {
+                        log::warn!("[{}] {}", addr, error);
+                        error
+                    }
+                };
+                if !output.is_empty() {
+                    networking.send_command_output(addr, time.tick, output);
+                }
+            }
+            _ => {
+                unprocessed.push((addr, message));
+            }
+        }
+    }
+
+    messages.extend(unprocessed);
+}
+
+/// Executes permitted command, returns its output for the sender
+#[allow(clippy::too_many_arguments)]
+fn execute(
+    world: &mut World,
+    config: &mut Config,
+    time: &Time,
+    networking: &mut Networking,
+    rotation: &mut MapRotation,
+    fs: &Filesystem,
+    addr: SocketAddr,
+    name: &str,
+    args: &[&str],
+) -> Result<String, String> {
+    match (name, args) {
+        ("resync", []) => match networking.connections.get(&addr) {
+            // every snapshot is a full reliable state - do not let clients flood with them
+            Some(connection) if !connection.resync_due(time.time) => {
+                Err("Resync requested too soon".to_string())
+            }
+            Some(_) => {
+                networking.send_snapshot(world, time, addr);
+                Ok(String::new())
+            }
+            None => Err("Not connected".to_string()),
+        },
+        ("login", [password]) => match networking.connections.get_mut(&addr) {
+            Some(connection) => login(config, connection, addr, password, time.time),
+            None => Err("Not connected".to_string()),
+        },
+        ("set", [cvar, value]) => match cvar::console::set(config, cvar, value) {
+            Ok(true) => Ok(format!("{} = `{}`", cvar, value)),
+            Ok(false) => Err(format!(
+                "Cannot set cvar `{} = {}`: not available",
+                cvar, value
+            )),
+            Err(err) => Err(format!("Cannot parse `{} = {}`: {}", cvar, value, err)),
+        },
+        ("kick", [target, message @ ..]) => match find_connection(networking, target) {
+            Some(address) => {
+                networking.kick(world, address, DisconnectReason::Kicked, &message.join(" "));
+                Ok(format!("Kicked {}", target))
+            }
+            None => Err(format!("No player `{}` to kick", target)),
+        },
+        ("status", []) => Ok(status(networking)),
+        ("map", [name]) if maps::exists(fs, name) => {
+            rotation.change(name);
+            Ok(format!("Changing map to {}", name))
+        }
+        ("map", [name]) => Err(format!("No map `{}`", name)),
+        ("votemap", [name]) if maps::exists(fs, name) => vote(networking, rotation, addr, name),
+        ("votemap", [name]) => Err(format!("No map `{}` to vote for", name)),
+        _ => Err(format!("Invalid arguments for `{}`: {:?}", name, args)),
+    }
+}
+
+/// Grants admin rights to connection which knows `server.admin_password`.
+/// Attempts are throttled to one per `LOGIN_RATE`, wrong passwords count as cheating.
+fn login(
+    config: &Config,
+    connection: &mut Connection,
+    address: SocketAddr,
+    password: &str,
+    now: Instant,
+) -> Result<String, String> {
+    let too_soon = connection.last_login.map_or(false, |last| {
+        now.saturating_duration_since(last).as_secs_f64() < LOGIN_RATE
+    });
+    if too_soon {
+        return Err("Login attempted too soon".to_string());
+    }
+    connection.last_login = Some(now);
+
+    let admin_password = &config.server.admin_password;
+    if admin_password.is_empty() || password != admin_password {
+        cheat::report(connection, address, Cheats::ADMIN_LOGIN, config);
+        return Err("Invalid admin password".to_string());
+    }
+    connection.admin = true;
+    Ok(format!("{} logged in as admin", connection.nick))
+}
+
+/// Network statistics of all connections
+fn status(networking: &Networking) -> String {
+    let mut lines = vec![
+        format!("--- status: {} connections", networking.connections.len()),
+        format!(
+            "{:<16} {:<21} {:>6} {:>6} {:>6} {:>8} {:>8}",
+            "nick", "address", "ping", "jitter", "loss", "in B/s", "out B/s"
+        ),
+    ];
+
+    let mut connections: Vec<_> = networking.connections.iter().collect();
+    connections.sort_by(|(_, a), (_, b)| a.nick.cmp(&b.nick));
+    for (address, connection) in connections {
+        let stats = &connection.stats;
+        lines.push(format!(
+            "{:<16} {:<21} {:>6.0} {:>6.1} {:>5.1}% {:>8.0} {:>8.0}",
+            connection.nick,
+            address.to_string(),
+            stats.rtt,
+            stats.jitter,
+            stats.loss,
+            stats.rate_rx,
+            stats.rate_tx
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Counts player vote for the next map, only players in game can vote
+fn vote(
+    networking: &Networking,
+    rotation: &mut MapRotation,
+    addr: SocketAddr,
+    name: &str,
+) -> Result<String, String> {
+    let voters = networking.players();
+    if !voters.contains(&addr) {
+        return Err("Only players in game can vote".to_string());
+    }
+    rotation.retain_votes(&voters);
+    rotation.vote(addr, name);
+
+    let nick = networking
+        .connections
+        .get(&addr)
+        .map_or("", |connection| connection.nick.as_str());
+    if rotation.voted(voters.len()) == Some(name) {
+        Ok(format!(
+            "{:?} votes for next map {} - vote passed",
+            nick, name
+        ))
+    } else {
+        Ok(format!("{:?} votes for next map {}", nick, name))
+    }
+}
+
+/// Finds connection by player nick or address
+fn find_connection(networking: &Networking, target: &str) -> Option<SocketAddr> {
+    networking
+        .connections
+        .iter()
+        .find(|(address, connection)| connection.nick == target || address.to_string() == target)
+        .map(|(&address, _)| address)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_command() {
+        assert_eq!(
+            parse("kick  Major bye now"),
+            ("kick", vec!["Major", "bye", "now"])
+        );
+        assert_eq!(parse("status"), ("status", vec![]));
+        assert_eq!(parse("   "), ("", vec![]));
+    }
+
+    #[test]
+    fn permissions() {
+        assert!(check_permission(Permission::Player, "votemap").is_ok());
+        assert!(check_permission(Permission::Player, "login").is_ok());
+        assert!(check_permission(Permission::Player, "map").is_err());
+        assert!(check_permission(Permission::Player, "set").is_err());
+        assert!(check_permission(Permission::Admin, "map").is_ok());
+        assert!(check_permission(Permission::Admin, "kick").is_ok());
+        assert!(check_permission(Permission::Admin, "shutdown").is_err());
+        assert!(check_permission(Permission::Admin, "").is_err());
+    }
+
+    #[test]
+    fn admin_login() {
+        let mut config = Config::default();
+        let mut connection = Connection::new();
+        let address = "127.0.0.1:1234".parse().unwrap();
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        assert_eq!(Permission::of(&connection), Permission::Player);
+
+        // no password set - nobody gets admin
+        assert!(login(&config, &mut connection, address, "", at(0.)).is_err());
+        assert_eq!(Permission::of(&connection), Permission::Player);
+
+        config.server.admin_password = "secret".to_string();
+        let step = LOGIN_RATE + 0.1;
+        assert!(login(&config, &mut connection, address, "guess", at(step)).is_err());
+        assert_eq!(Permission::of(&connection), Permission::Player);
+        assert_eq!(connection.violations.count(), 2);
+        assert!(connection.cheats.contains(Cheats::ADMIN_LOGIN));
+
+        // too soon after last attempt, even with the right password
+        let result = login(&config, &mut connection, address, "secret", at(step + 0.1));
+        assert!(result.is_err());
+        assert_eq!(Permission::of(&connection), Permission::Player);
+        assert_eq!(connection.violations.count(), 2);
+
+        assert!(login(&config, &mut connection, address, "secret", at(step * 2.)).is_ok());
+        assert_eq!(Permission::of(&connection), Permission::Admin);
+    }
+}
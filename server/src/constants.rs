@@ -1,3 +1,42 @@
+use crate::cvars::GameMode;
 pub use soldank_shared::constants::*;
 
 pub const BROADCAST_RATE: f64 = 1.0 / 3.0;
+pub const RESYNC_RATE: f64 = 1.0; // min seconds between snapshots sent to lost client
+pub const LOGIN_RATE: f64 = 2.0; // min seconds between admin login attempts of a connection
+
+pub const MAX_PLAYERS: u32 = 16;
+pub const DEFAULT_SERVER_NAME: &str = "Soldank Server";
+pub const WARMUP_TIME: u32 = 60; // seconds, 0 waits for all players to get ready
+pub const COUNTDOWN_TIME: u32 = 5; // seconds
+pub const INTERMISSION_TIME: u32 = 10; // seconds
+
+pub const DEFAULT_GAME_MODE: GameMode = GameMode::Ctf;
+pub const SCORE_LIMIT: u32 = 10; // captures, 0 disables
+pub const FRAG_LIMIT: u32 = 30; // 0 disables
+pub const TIME_LIMIT: u32 = 900; // seconds, 0 disables
+pub const FLAG_RETURN: u32 = 15000; // millis, 0 disables
+pub const RESPAWN_TIME: u32 = 3000; // millis
+pub const PICKUP_RESPAWN: u32 = 20000; // millis
+pub const CAPTURE_SCORE: i32 = 5; // player score for capturing a flag
+
+pub const MAX_DATAGRAM_SIZE: usize = 65536; // receive buffer, fits any UDP datagram
+
+pub const QUERY_RATE: f32 = 2.; // out-of-band queries answered per second, per host
+pub const QUERY_BURST: f32 = 5.;
+pub const QUERY_GLOBAL_RATE: f32 = 50.; // out-of-band queries answered per second, in total
+pub const QUERY_GLOBAL_BURST: f32 = 100.;
+
+pub const HANDSHAKE_RATE: f32 = 1.; // connection requests accepted per second, per host
+pub const HANDSHAKE_BURST: f32 = 3.;
+pub const MAX_PENDING_HANDSHAKES: usize = 32; // connections accepted, but not authorized yet
+pub const HANDSHAKE_TIMEOUT: u128 = 5000; // millis to authorize accepted connection
+
+pub const MAX_INPUT_LEAD: usize = 60; // ticks client input may be ahead of server
+pub const MAX_INPUTS_PER_MESSAGE: usize = 64;
+pub const AIM_EPSILON: f32 = 0.01; // allowed deviation of aim vector length from 1
+
+pub const CHEAT_WARN: u32 = 1; // violations count thresholds, 0 disables
+pub const CHEAT_KICK: u32 = 10;
+pub const CHEAT_BAN: u32 = 0;
+pub const CHEAT_WINDOW: u32 = 60; // seconds violations are counted over
@@ -0,0 +1,135 @@
+use hecs::{Entity, World};
+
+use crate::{constants::*, cvars::Config, systems::nick};
+use soldank_shared::{
+    components::{self, Team},
+    flag::{self, Flag, FlagEvent},
+    mapfile::{MapFile, SpawnType},
+    math::{vec2, Vec2},
+    soldier::Soldier,
+};
+
+struct FlagTouch {
+    entity: Entity,
+    team: Team,
+    pos: Vec2,
+    aim: Vec2,
+    velocity: Vec2,
+    throw: bool,
+    alive: bool,
+}
+
+/// Spawns flags at map flag spawnpoints
+pub fn spawn_flags(world: &mut World, map: &MapFile, config: &Config) {
+    for (team, spawn_type) in [
+        (Team::Alpha, SpawnType::AlphaFlag),
+        (Team::Bravo, SpawnType::BravoFlag),
+    ] {
+        match map.spawnpoints_of(spawn_type).next() {
+            Some(spawn) => {
+                let base = vec2(spawn.x as f32, spawn.y as f32);
+                let flag = Flag::new(team, base, config.phys.gravity);
+                world.spawn((components::Flag, team, components::Position(base), flag));
+            }
+            None => log::warn!("Map {} has no {:?} flag spawnpoint", map.mapname, team),
+        }
+    }
+}
+
+/// Moves flags along with carriers, throws and drops them, and applies
+/// pickup, return and capture rules to soldiers touching them.
+/// Returns soldiers which captured a flag.
+pub fn update_flags(world: &mut World, map: &MapFile, config: &Config, tick: usize) -> Vec<Entity> {
+    let return_ticks = (config.game.flag_return as f64 / 1000. / TIMESTEP_RATE) as usize;
+
+    let soldiers: Vec<FlagTouch> = world
+        .query::<(&Soldier, &Team)>()
+        .iter()
+        .map(|(entity, (soldier, &team))| FlagTouch {
+            entity,
+            team,
+            pos: soldier.particle.pos,
+            aim: vec2(
+                soldier.control.mouse_aim_x as f32,
+                soldier.control.mouse_aim_y as f32,
+            ),
+            velocity: soldier.particle.velocity,
+            throw: soldier.control.flag_throw,
+            alive: !soldier.dead_meat,
+        })
+        .collect();
+
+    let mut events = Vec::new();
+    let mut carriers = Vec::new();
+    {
+        let mut query = world.query::<&mut Flag>();
+        let mut flags: Vec<&mut Flag> = query.iter().map(|(_entity, flag)| flag).collect();
+
+        for flag in flags.iter_mut() {
+            if let Some(carrier) = flag.carrier() {
+                match soldiers.iter().find(|s| s.entity == carrier && s.alive) {
+                    Some(s) if s.throw => events.extend(flag.throw(s.aim, s.velocity, tick)),
+                    Some(s) => flag.carry(s.pos),
+                    None => events.extend(flag.drop(Vec2::ZERO)),
+                }
+            }
+            events.extend(flag.update(map, return_ticks));
+        }
+
+        for s in soldiers.iter().filter(|s| s.alive) {
+            events.extend(flag::touch_flags(&mut flags, s.entity, s.team, s.pos, tick));
+        }
+
+        carriers.extend(flags.iter().filter_map(|flag| flag.carrier()));
+    }
+
+    for (_entity, (flag, position)) in world.query::<(&Flag, &mut components::Position)>().iter() {
+        **position = flag.pos();
+    }
+    for (entity, soldier) in world.query::<&mut Soldier>().iter() {
+        soldier.flagger = carriers.contains(&entity);
+    }
+
+    events
+        .into_iter()
+        .filter_map(|event| log_event(world, event))
+        .collect()
+}
+
+/// Removes flags, soldiers carry none afterwards
+pub fn despawn_flags(world: &mut World) {
+    let flags: Vec<Entity> = world
+        .query::<&Flag>()
+        .iter()
+        .map(|(entity, _flag)| entity)
+        .collect();
+    for entity in flags {
+        let _ = world.despawn(entity);
+    }
+    for (_entity, soldier) in world.query::<&mut Soldier>().iter() {
+        soldier.flagger = false;
+    }
+}
+
+/// Logs flag `event`, returning the soldier if it captured a flag
+fn log_event(world: &World, event: FlagEvent) -> Option<Entity> {
+    match event {
+        FlagEvent::Taken { team, by } => {
+            log::info!("{:?} flag taken by {:?}", team, nick(world, by));
+        }
+        FlagEvent::Dropped { team, by } => {
+            log::info!("{:?} flag dropped by {:?}", team, nick(world, by));
+        }
+        FlagEvent::Returned { team, by: Some(by) } => {
+            log::info!("{:?} flag returned by {:?}", team, nick(world, by));
+        }
+        FlagEvent::Returned { team, by: None } => {
+            log::info!("{:?} flag returned", team);
+        }
+        FlagEvent::Captured { team, by } => {
+            log::info!("{:?} flag captured by {:?}", team, nick(world, by));
+            return Some(by);
+        }
+    }
+    None
+}
@@ -1,23 +1,55 @@
+use crate::constants::*;
 use cvar::{INode, IVisit};
 pub use soldank_shared::cvars::*;
+use std::{error::Error, fmt, str::FromStr};
 
 #[derive(Default)]
 pub struct Config {
     pub server: ServerInfo,
+    pub game: GameConfig,
     pub net: NetConfig,
     pub phys: Physics,
+    pub cheat: CheatConfig,
 }
 
 impl IVisit for Config {
     fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
         f(&mut cvar::List("server", &mut self.server));
+        f(&mut cvar::List("game", &mut self.game));
         f(&mut cvar::List("net", &mut self.net));
         f(&mut cvar::List("phys", &mut self.phys));
+        f(&mut cvar::List("cheat", &mut self.cheat));
     }
 }
 
+/// Cvars published to anyone querying the server - anti-cheat thresholds,
+/// network simulation and admin password are kept private
+pub fn public_cvars(config: &mut Config) -> Vec<(String, String)> {
+    let mut cvars = Vec::new();
+    cvar::console::walk(config, |path, node| {
+        if path.starts_with("cheat.")
+            || path.starts_with("net.sim_")
+            || path == "server.admin_password"
+        {
+            return;
+        }
+        if let cvar::Node::Prop(prop) = node.as_node() {
+            cvars.push((path.to_owned(), prop.get()));
+        }
+    });
+    cvars
+}
+
 pub struct ServerInfo {
+    pub name: String,
     pub motd: String,
+    pub max_players: u32,
+    pub warmup: u32,       // seconds, 0 waits for all players to get ready
+    pub countdown: u32,    // seconds
+    pub intermission: u32, // seconds
+    pub maplist: String,   // maps played in rotation, separated with commas
+    /// grants admin rights with `login` command, empty disables it
+    pub admin_password: String,
 }
 
 fn default_motd() -> String {
@@ -32,13 +64,200 @@ fn default_motd() -> String {
 impl Default for ServerInfo {
     fn default() -> Self {
         Self {
+            name: DEFAULT_SERVER_NAME.to_string(),
             motd: default_motd(),
+            max_players: MAX_PLAYERS,
+            warmup: WARMUP_TIME,
+            countdown: COUNTDOWN_TIME,
+            intermission: INTERMISSION_TIME,
+            maplist: String::new(),
+            admin_password: String::new(),
         }
     }
 }
 
 impl IVisit for ServerInfo {
     fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
+        f(&mut cvar::Property(
+            "name",
+            &mut self.name,
+            DEFAULT_SERVER_NAME.to_string(),
+        ));
         f(&mut cvar::Property("motd", &mut self.motd, default_motd()));
+        f(&mut cvar::Property(
+            "max_players",
+            &mut self.max_players,
+            MAX_PLAYERS,
+        ));
+        f(&mut cvar::Property("warmup", &mut self.warmup, WARMUP_TIME));
+        f(&mut cvar::Property(
+            "countdown",
+            &mut self.countdown,
+            COUNTDOWN_TIME,
+        ));
+        f(&mut cvar::Property(
+            "intermission",
+            &mut self.intermission,
+            INTERMISSION_TIME,
+        ));
+        f(&mut cvar::Property(
+            "maplist",
+            &mut self.maplist,
+            String::new(),
+        ));
+        f(&mut cvar::Property(
+            "admin_password",
+            &mut self.admin_password,
+            String::new(),
+        ));
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameMode {
+    Deathmatch,
+    TeamDeathmatch,
+    Ctf,
+}
+
+impl GameMode {
+    pub fn has_teams(&self) -> bool {
+        *self != GameMode::Deathmatch
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownGameMode(String);
+
+impl fmt::Display for UnknownGameMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown game mode `{}`", self.0)
+    }
+}
+
+impl Error for UnknownGameMode {}
+
+impl FromStr for GameMode {
+    type Err = UnknownGameMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dm" | "deathmatch" => Ok(GameMode::Deathmatch),
+            "tdm" | "teammatch" => Ok(GameMode::TeamDeathmatch),
+            "ctf" => Ok(GameMode::Ctf),
+            _ => Err(UnknownGameMode(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameMode::Deathmatch => write!(f, "dm"),
+            GameMode::TeamDeathmatch => write!(f, "tdm"),
+            GameMode::Ctf => write!(f, "ctf"),
+        }
+    }
+}
+
+pub struct GameConfig {
+    pub mode: GameMode,
+    pub score_limit: u32,    // captures, 0 disables
+    pub frag_limit: u32,     // kills of a player, or a team in team modes, 0 disables
+    pub time_limit: u32,     // seconds, 0 disables
+    pub flag_return: u32,    // millis dropped flag lies before returning to base, 0 disables
+    pub respawn_time: u32,   // millis
+    pub pickup_respawn: u32, // millis taken kit is gone for
+    pub friendly_fire: bool,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            mode: DEFAULT_GAME_MODE,
+            score_limit: SCORE_LIMIT,
+            frag_limit: FRAG_LIMIT,
+            time_limit: TIME_LIMIT,
+            flag_return: FLAG_RETURN,
+            respawn_time: RESPAWN_TIME,
+            pickup_respawn: PICKUP_RESPAWN,
+            friendly_fire: false,
+        }
+    }
+}
+
+impl IVisit for GameConfig {
+    fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
+        f(&mut cvar::Property(
+            "mode",
+            &mut self.mode,
+            DEFAULT_GAME_MODE,
+        ));
+        f(&mut cvar::Property(
+            "score_limit",
+            &mut self.score_limit,
+            SCORE_LIMIT,
+        ));
+        f(&mut cvar::Property(
+            "frag_limit",
+            &mut self.frag_limit,
+            FRAG_LIMIT,
+        ));
+        f(&mut cvar::Property(
+            "time_limit",
+            &mut self.time_limit,
+            TIME_LIMIT,
+        ));
+        f(&mut cvar::Property(
+            "flag_return",
+            &mut self.flag_return,
+            FLAG_RETURN,
+        ));
+        f(&mut cvar::Property(
+            "respawn_time",
+            &mut self.respawn_time,
+            RESPAWN_TIME,
+        ));
+        f(&mut cvar::Property(
+            "pickup_respawn",
+            &mut self.pickup_respawn,
+            PICKUP_RESPAWN,
+        ));
+        f(&mut cvar::Property(
+            "friendly_fire",
+            &mut self.friendly_fire,
+            false,
+        ));
+    }
+}
+
+pub struct CheatConfig {
+    pub warn: u32,
+    pub kick: u32,
+    pub ban: u32,
+    pub window: u32, // seconds violations are counted over
+}
+
+impl Default for CheatConfig {
+    fn default() -> Self {
+        Self {
+            warn: CHEAT_WARN,
+            kick: CHEAT_KICK,
+            ban: CHEAT_BAN,
+            window: CHEAT_WINDOW,
+        }
+    }
+}
+
+impl IVisit for CheatConfig {
+    fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
+        f(&mut cvar::Property("warn", &mut self.warn, CHEAT_WARN));
+        f(&mut cvar::Property("kick", &mut self.kick, CHEAT_KICK));
+        f(&mut cvar::Property("ban", &mut self.ban, CHEAT_BAN));
+        f(&mut cvar::Property(
+            "window",
+            &mut self.window,
+            CHEAT_WINDOW,
+        ));
     }
 }
@@ -2,36 +2,39 @@
 extern crate clap;
 
 use color_eyre::eyre::Result;
+use gvfs::filesystem::Filesystem;
 use hecs::World;
 use smol::future;
 use std::{
     collections::VecDeque,
     net::SocketAddr,
-    sync::{Arc, RwLock},
     time::{Duration, Instant},
 };
 
 use crate::{
     constants::*,
-    cvars::{set_cli_cvars, Config, NetConfig},
+    cvars::{set_cli_cvars, Config, GameMode},
     networking::Networking,
 };
-use soldank_shared::{messages::NetworkMessage, networking::GameWorld};
+use soldank_shared::{
+    anims::AnimData, lag_compensation::HitboxHistory, mapfile::MapFile, messages::NetworkMessage,
+    soldier::Soldier, vfs,
+};
 
 mod cheat;
 mod cli;
+mod commands;
 mod constants;
+mod ctf;
 mod cvars;
+mod maps;
 mod networking;
+mod pickups;
+mod round;
+mod scores;
 mod state;
 mod systems;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum GameState {
-    Lobby,
-    InGame,
-}
-
 fn main() -> Result<()> {
     color_eyre::install()?;
     env_logger::init();
@@ -39,104 +42,156 @@ fn main() -> Result<()> {
     smol::block_on(async {
         let cmd = cli::parse_cli_args();
 
-        let mut map_name = cmd
+        let mut filesystem = Filesystem::new(env!("CARGO_PKG_NAME"), "Soldat2k").unwrap();
+        vfs::mount_resources(&mut filesystem);
+
+        let map_name = cmd
             .get_one::<String>("map")
             .map_or(DEFAULT_MAP, |s| s.as_ref())
             .to_owned();
-        map_name.push_str(".pms");
-        log::info!("Using map: {}", map_name);
-
-        let mut config = Config {
-            net: NetConfig {
-                orb: Arc::new(RwLock::new(orb::Config {
-                    timestep_seconds: TIMESTEP_RATE,
-                    ..Default::default()
-                })),
-                ..Default::default()
-            },
-            ..Default::default()
-        };
+
+        let mut map = MapFile::load_map_file(&mut filesystem, &format!("{}.pms", map_name));
+        log::info!("Using map: {}", map.mapname);
+        let mut rotation = maps::MapRotation::new(&map_name);
+
+        let mut config = Config::default();
         set_cli_cvars(&mut config, &cmd);
 
+        AnimData::initialize(&mut filesystem);
+        Soldier::initialize(&mut filesystem, config.phys.gravity);
+
         let mut networking =
-            Networking::new(cmd.get_one::<String>("bind").map(|s| s.as_ref())).await;
+            Networking::new(cmd.get_one::<String>("bind").map(|s| s.as_ref()), &config).await;
         if let Some(key) = cmd.get_one::<String>("key") {
             networking.connection_key = key.to_string();
         }
+        networking.map_name = map_name;
 
         let mut messages: VecDeque<(SocketAddr, NetworkMessage)> = VecDeque::new();
 
         let mut world = World::new();
+        pickups::spawn_pickups(&mut world, &map);
 
-        let mut game_state = GameState::Lobby;
-
-        let mut server = orb::server::Server::<GameWorld>::new(config.net.orb.clone(), 0.0);
+        let mut round = round::Round::default();
+        let mut hitboxes = HitboxHistory::default();
+        let mut scoreboard = scores::Scoreboard::default();
 
         let startup_time = Instant::now();
-        let mut previous_time = Instant::now();
+        // last simulated tick
+        let mut tick = 0;
 
-        let mut running = true;
-        while running {
-            let timeout = Duration::from_millis(
-                (config.net.orb.read().unwrap().snapshot_send_period * 1000.) as _,
-            );
+        loop {
+            let next_tick =
+                startup_time + Duration::from_secs_f64((tick + 1) as f64 * TIMESTEP_RATE);
             future::race(
                 // loop is driven by incoming packets
-                networking.process(&mut world, &mut config, &mut messages),
-                // or timeout
+                networking.process(&mut world, &mut config, round.state, &mut messages),
+                // or next simulation tick
                 async {
-                    smol::Timer::after(timeout).await; // drop Timer result
+                    smol::Timer::at(next_tick).await; // drop Timer result
                 },
             )
             .await;
 
             let current_time = Instant::now();
-            let delta_seconds = current_time.duration_since(previous_time).as_secs_f64();
             let seconds_since_startup = current_time.duration_since(startup_time).as_secs_f64();
 
+            let time = systems::Time {
+                time: current_time,
+                tick: (seconds_since_startup / TIMESTEP_RATE) as usize,
+                frame_percent: 1.,
+            };
+
             systems::process_network_messages(
                 &mut world,
+                &config,
+                &time,
+                round.state,
                 &mut messages,
                 &mut networking.connections,
             );
+            cheat::enforce(&mut world, &mut networking, &config);
+            commands::process_commands(
+                &mut world,
+                &mut config,
+                &time,
+                &mut networking,
+                &mut rotation,
+                &filesystem,
+                &mut messages,
+            );
             systems::message_dump(&mut messages);
 
-            match game_state {
-                GameState::Lobby => {
-                    systems::lobby(&mut world, &mut game_state, &networking);
-                }
-                GameState::InGame => {
-                    server.update(delta_seconds, seconds_since_startup);
-                    let server_display_state = server.display_state();
-                    log::trace!(
-                        "server_display_state: {}",
-                        server_display_state.inner().len()
-                    );
-                    networking.process_simulation(&mut server); // push above server's results in the wild
-
-                    // let time = systems::Time {
-                    //     time: current_time,
-                    //     tick: (server
-                    //         .last_completed_timestamp()
-                    //         .as_seconds(config.net.orb.read().unwrap().timestep_seconds)
-                    //         * 1000.) as usize,
-                    //     frame_percent: 1.,
-                    // };
-                    // systems::tick_debug(&world, &time);
-
-                    if networking.connections.is_empty() {
-                        log::info!("No connections left - exiting");
-                        running = false;
+            if let Some(name) = rotation.take_change() {
+                if name != rotation.current {
+                    if let Some(next) = maps::load(&mut filesystem, &name) {
+                        map = next;
+                        networking.change_map(&name);
                     }
                 }
+                rotation.changed(&networking.map_name);
+                hitboxes = HitboxHistory::default();
+                round.restart(&mut world, &map, &config, &mut networking, time.tick);
             }
 
-            previous_time = current_time;
+            for address in systems::spawn_players(&mut world, &networking, &map, &config) {
+                round.send(&mut networking, address);
+            }
 
-            networking.post_process(&config);
-        }
+            // step simulation in fixed increments, catching up on all ticks passed
+            while tick < time.tick {
+                tick += 1;
+                let step = systems::Time {
+                    time: current_time,
+                    tick,
+                    frame_percent: 1.,
+                };
+                systems::apply_input(
+                    &mut world,
+                    &map,
+                    &config,
+                    &step,
+                    &mut networking.connections,
+                    round.frozen(),
+                );
+                if config.game.mode == GameMode::Ctf {
+                    for scorer in ctf::update_flags(&mut world, &map, &config, tick) {
+                        if round.live() {
+                            scoreboard.capture(&world, scorer);
+                        }
+                    }
+                }
+                let kills = systems::update_bullets(&mut world, &map, &config, &hitboxes, tick);
+                for (killer, victim) in kills {
+                    if round.live() {
+                        scoreboard.kill(&world, &config, Some(killer), victim);
+                    }
+                }
+                systems::respawn_soldiers(&mut world, &map, &config, tick);
+                pickups::update_pickups(&mut world, &config, tick);
+                // hit detection rewinds to these, see `lag_compensation::rewind_tick`
+                hitboxes.record(&world, tick);
+
+                let match_over = round.update(
+                    &mut world,
+                    &map,
+                    &config,
+                    &mut networking,
+                    &mut scoreboard,
+                    tick,
+                );
+                if match_over {
+                    let voters = networking.players();
+                    rotation.retain_votes(&voters);
+                    let next = rotation.next(&config, voters.len());
+                    rotation.change(&next);
+                }
+            }
+            systems::update_soldier_states(&mut world);
+            networking.broadcast_state(&world, &config, &time);
 
-        log::info!("Exiting server");
-        Ok(())
+            networking.post_process(&mut world, &config);
+            networking.flush().await;
+        }
     })
 }
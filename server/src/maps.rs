@@ -0,0 +1,165 @@
+use gvfs::filesystem::Filesystem;
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+};
+
+use crate::cvars::Config;
+use soldank_shared::mapfile::MapFile;
+
+/// Map currently played, and the one to play next
+pub struct MapRotation {
+    /// name of map file played, without `.pms` extension
+    pub current: String,
+    /// map chosen by admin, changed to right away
+    change: Option<String>,
+    /// next map players voted for
+    votes: HashMap<SocketAddr, String>,
+}
+
+impl MapRotation {
+    pub fn new(current: &str) -> MapRotation {
+        MapRotation {
+            current: current.to_string(),
+            change: None,
+            votes: HashMap::new(),
+        }
+    }
+
+    /// Requests changing map immediately
+    pub fn change(&mut self, name: &str) {
+        self.change = Some(name.to_string());
+    }
+
+    pub fn take_change(&mut self) -> Option<String> {
+        self.change.take()
+    }
+
+    /// Counts vote of player at `address` for the next map, replacing its previous vote
+    pub fn vote(&mut self, address: SocketAddr, name: &str) {
+        self.votes.insert(address, name.to_string());
+    }
+
+    /// Drops votes of players which are gone
+    pub fn retain_votes(&mut self, voters: &HashSet<SocketAddr>) {
+        self.votes.retain(|address, _| voters.contains(address));
+    }
+
+    /// Map voted by majority of `players`, if any
+    pub fn voted(&self, players: usize) -> Option<&str> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for name in self.votes.values() {
+            *counts.entry(name.as_str()).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .find(|&(_, count)| count * 2 > players)
+            .map(|(name, _)| name)
+    }
+
+    /// Map to play after the current match - voted one, or the next one from `server.maplist`
+    pub fn next(&self, config: &Config, players: usize) -> String {
+        if let Some(name) = self.voted(players) {
+            return name.to_string();
+        }
+
+        let maps = maplist(config);
+        let next = match maps.iter().position(|name| *name == self.current) {
+            Some(index) => maps.get(index + 1).or_else(|| maps.first()),
+            None => maps.first(),
+        };
+        next.unwrap_or(&self.current).to_string()
+    }
+
+    /// Starts playing map `name`, forgetting votes for the previous one
+    pub fn changed(&mut self, name: &str) {
+        self.current = name.to_string();
+        self.votes.clear();
+    }
+}
+
+/// Maps listed in `server.maplist`
+pub fn maplist(config: &Config) -> Vec<String> {
+    config
+        .server
+        .maplist
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether map file `name`, without `.pms` extension, is available
+pub fn exists(fs: &Filesystem, name: &str) -> bool {
+    MapFile::exists(fs, &format!("{}.pms", name))
+}
+
+pub fn load(fs: &mut Filesystem, name: &str) -> Option<MapFile> {
+    if !exists(fs, name) {
+        log::error!("Map {} not found", name);
+        return None;
+    }
+    let map = MapFile::load_map_file(fs, &format!("{}.pms", name));
+    log::info!("Using map: {}", map.mapname);
+    Some(map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn next_map() {
+        let mut config = Config::default();
+        config.server.maplist = "ctf_Ash, ctf_Laos,,ctf_Run ".to_string();
+        assert_eq!(maplist(&config), vec!["ctf_Ash", "ctf_Laos", "ctf_Run"]);
+
+        let mut rotation = MapRotation::new("ctf_Ash");
+        assert_eq!(rotation.next(&config, 0), "ctf_Laos");
+        rotation.changed("ctf_Run");
+        assert_eq!(rotation.next(&config, 0), "ctf_Ash");
+        rotation.changed("dm_Other");
+        assert_eq!(rotation.next(&config, 0), "ctf_Ash");
+
+        config.server.maplist = String::new();
+        assert_eq!(rotation.next(&config, 0), "dm_Other");
+
+        rotation.change("ctf_Laos");
+        assert_eq!(rotation.take_change(), Some("ctf_Laos".to_string()));
+        assert_eq!(rotation.take_change(), None);
+    }
+
+    #[test]
+    fn vote() {
+        let mut config = Config::default();
+        config.server.maplist = "ctf_Ash,ctf_Laos".to_string();
+        let mut rotation = MapRotation::new("ctf_Ash");
+
+        rotation.vote(address(1), "ctf_Run");
+        assert_eq!(rotation.voted(3), None);
+        rotation.vote(address(2), "ctf_Run");
+        assert_eq!(rotation.voted(3), Some("ctf_Run"));
+        assert_eq!(rotation.next(&config, 3), "ctf_Run");
+
+        // changing vote replaces the previous one
+        rotation.vote(address(2), "ctf_Laos");
+        assert_eq!(rotation.voted(3), None);
+        rotation.vote(address(2), "ctf_Run");
+
+        // votes of players which left do not count
+        let voters: HashSet<SocketAddr> = [address(1), address(3)].iter().copied().collect();
+        rotation.retain_votes(&voters);
+        assert_eq!(rotation.voted(2), None);
+        rotation.vote(address(3), "ctf_Run");
+        assert_eq!(rotation.voted(2), Some("ctf_Run"));
+
+        rotation.changed("ctf_Run");
+        assert_eq!(rotation.voted(2), None);
+        assert_eq!(rotation.next(&config, 2), "ctf_Ash");
+    }
+}
@@ -1,38 +1,48 @@
 use hecs::{Entity, World};
-use laminar::{
-    Config as LaminarConfig, ConnectionManager, DatagramSocket, Packet as LaminarPacket,
-    SocketEvent, VirtualConnection,
-};
-use naia_server_socket::{
-    find_my_ip_address, LinkConditionerConfig, MessageSender, NaiaServerSocketError,
-    Packet as NaiaPacket, ServerSocket, ServerSocketTrait,
-};
-use smol::channel::{unbounded, Receiver, Sender, TryRecvError};
+use smol::net::UdpSocket;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     convert::TryFrom,
-    io,
-    net::SocketAddr,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     time::{Duration, Instant},
 };
 
-use crate::{cheat::Cheats, constants::*, cvars::Config, state::build_state_message, systems};
-use orb::server::Server;
+use crate::{
+    cheat::{Cheats, Violations},
+    constants::*,
+    cvars::{public_cvars, Config},
+    state::{build_state_message, collect_snapshot, InterestScope},
+    systems,
+};
 use soldank_shared::{
+    auth::{auth_verify, Nonce, RejectReason},
+    components,
     constants::SERVER_PORT,
-    messages::{self, encode_message, NetworkMessage},
-    networking::{GameWorld, PacketStats},
+    messages::{
+        self, encode_message, DisconnectReason, GameState, NetworkMessage, PlayerInfo, QueryInfo,
+        ServerStatus, VersionInfo,
+    },
+    networking::{
+        decode_out_of_band, out_of_band, resolve_master, Channels, NetCommand, NetSnapshot,
+        NetworkSimulator, Packet, PacketStats, RateLimiter,
+    },
+    replication::{ReplicationRegistry, SnapshotHistory},
     trace_dump_packet,
 };
 
 pub struct Networking {
-    server_socket: Box<dyn ServerSocketTrait>,
-    sender: MessageSender,
-    packet_sender: Sender<NaiaPacket>,
-    payload_receiver: Receiver<NaiaPacket>,
-    handler: ConnectionManager<PacketSocket, VirtualConnection>,
+    socket: UdpSocket,
+    channels: Channels,
+    simulator: NetworkSimulator<(SocketAddr, Vec<u8>)>,
     pub connection_key: String,
+    pub map_name: String,
     pub stats: PacketStats,
+    pub registry: ReplicationRegistry,
+    pub bans: HashSet<IpAddr>,
+    last_heartbeat: Option<Instant>,
+    query_limiter: RateLimiter<IpAddr>,
+    query_global_limiter: RateLimiter<()>,
+    handshake_limiter: RateLimiter<IpAddr>,
 
     pub connections: HashMap<SocketAddr, Connection>,
 }
@@ -43,11 +53,25 @@ pub struct Connection {
     pub ack_tick: usize,
     pub last_processed_tick: usize,
     pub last_broadcast: Instant,
+    /// accepted at, connection not authorized in `HANDSHAKE_TIMEOUT` is dropped
+    pub accepted: Instant,
     pub authorized: bool,
     pub ready: bool,
     pub nick: String,
     pub cheats: Cheats,
+    /// cheat violations detected recently
+    pub violations: Violations,
+    pub admin: bool,
+    /// challenge sent in `CCREP_ACCEPT`
+    pub nonce: Option<Nonce>,
+    /// negotiated in `CCREQ_CONNECT`
+    pub protocol_version: u8,
     pub entity: Option<Entity>,
+    pub snapshots: SnapshotHistory,
+    pub scope: InterestScope,
+    pub last_resync: Option<Instant>,
+    /// last admin login attempt
+    pub last_login: Option<Instant>,
 }
 
 impl Connection {
@@ -57,115 +81,219 @@ impl Connection {
             ack_tick: 0,
             last_processed_tick: 0,
             last_broadcast: Instant::now(),
+            accepted: Instant::now(),
             authorized: false,
             ready: false,
             nick: Default::default(),
             cheats: Default::default(),
+            violations: Default::default(),
+            admin: false,
+            nonce: None,
+            protocol_version: 0,
             entity: None,
+            snapshots: Default::default(),
+            scope: Default::default(),
+            last_resync: None,
+            last_login: None,
         }
     }
-}
 
-#[derive(Debug)]
-struct PacketSocket {
-    bind_address: SocketAddr,
-    packet_receiver: Receiver<NaiaPacket>,
-    payload_sender: Sender<NaiaPacket>,
+    /// Whether `RESYNC_RATE` passed since last snapshot sent to resynchronize this connection
+    pub fn resync_due(&self, now: Instant) -> bool {
+        self.last_resync.map_or(true, |last| {
+            now.duration_since(last).as_secs_f64() > RESYNC_RATE
+        })
+    }
 }
 
-impl DatagramSocket for PacketSocket {
-    fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8]) -> io::Result<usize> {
-        match smol::block_on(
-            self.payload_sender
-                .send(NaiaPacket::new(*addr, payload.to_vec())),
-        ) {
-            Ok(()) => Ok(payload.len()),
-            Err(error) => {
-                panic!("Error sending via payload channel: {}", error);
-            }
+impl Networking {
+    pub async fn new(bind: Option<&str>, config: &Config) -> Networking {
+        let bind_address = if let Some(addr) = bind {
+            addr.parse().expect("cannot parse bind address")
+        } else {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), SERVER_PORT)
+        };
+
+        let socket = UdpSocket::bind(bind_address)
+            .await
+            .expect("cannot bind listener socket");
+
+        log::info!("Bound listener socket: [{}]", bind_address);
+
+        Networking {
+            socket,
+            channels: Channels::default(),
+            simulator: NetworkSimulator::new(config.net.sim.clone()),
+            connection_key: "1337".to_string(),
+            map_name: String::new(),
+            stats: Default::default(),
+            registry: components::replication_registry(),
+            bans: HashSet::new(),
+            last_heartbeat: None,
+            query_limiter: RateLimiter::new(QUERY_RATE, QUERY_BURST),
+            query_global_limiter: RateLimiter::new(QUERY_GLOBAL_RATE, QUERY_GLOBAL_BURST),
+            handshake_limiter: RateLimiter::new(HANDSHAKE_RATE, HANDSHAKE_BURST),
+
+            connections: HashMap::new(),
         }
     }
 
-    fn receive_packet<'a>(&mut self, buffer: &'a mut [u8]) -> io::Result<(&'a [u8], SocketAddr)> {
-        match self.packet_receiver.try_recv() {
-            Ok(packet) => {
-                let payload = packet.payload();
-                buffer[..payload.len()].clone_from_slice(payload);
-                Ok((&buffer[..payload.len()], packet.address()))
-            }
-            Err(error) => match error {
-                TryRecvError::Empty => Err(io::Error::new(io::ErrorKind::WouldBlock, error)),
-                TryRecvError::Closed => {
-                    panic!("Error receiving from packet channel: {}", error);
-                }
-            },
+    pub fn send(&mut self, packet: Packet) {
+        let address = packet.addr();
+        if let Some(connection) = self.connections.get_mut(&address) {
+            connection.stats.add_tx(packet.payload().len());
         }
+        self.channels.send(packet);
+        self.queue();
     }
 
-    fn local_addr(&self) -> io::Result<SocketAddr> {
-        Ok(self.bind_address)
+    /// Passes datagrams released by channels to network simulator, until next `flush`
+    fn queue(&mut self) {
+        let now = Instant::now();
+        for datagram in self.channels.poll(now) {
+            self.simulator.send(now, datagram);
+        }
     }
 
-    fn is_blocking_mode(&self) -> bool {
-        false
+    /// Sends datagrams released by network simulator to the socket, once per server loop
+    pub async fn flush(&mut self) {
+        self.queue();
+        for (address, data) in self.simulator.poll(Instant::now()) {
+            log::debug!("--> Sending {} bytes to [{}]", data.len(), address);
+            self.stats.add_tx(data.len());
+            trace_dump_packet(&data);
+
+            if let Err(error) = self.socket.send_to(&data, address).await {
+                log::error!("Error sending payload to [{}]: {}", address, error);
+            }
+        }
     }
-}
 
-impl Networking {
-    pub async fn new(bind: Option<&str>) -> Networking {
-        let bind_address = if let Some(addr) = bind {
-            addr.parse().expect("cannot parse bind address")
-        } else {
-            let addr = find_my_ip_address().expect("can't find local ip address");
-            SocketAddr::new(addr, SERVER_PORT)
-        };
+    /// Sends datagram outside of `Channels`, to peers not connected to the server
+    fn send_out_of_band(&mut self, address: SocketAddr, payload: &[u8]) {
+        self.simulator
+            .send(Instant::now(), (address, out_of_band(payload)));
+    }
 
-        let mut webrtc_listen_address = bind_address;
-        webrtc_listen_address.set_port(webrtc_listen_address.port() + 1);
-        let public_webrtc_address = webrtc_listen_address;
+    /// Addresses of players in game - authorized, with a soldier spawned
+    pub fn players(&self) -> HashSet<SocketAddr> {
+        self.connections
+            .iter()
+            .filter(|(_, conn)| conn.authorized && conn.entity.is_some())
+            .map(|(&address, _)| address)
+            .collect()
+    }
 
-        let mut server_socket =
-            ServerSocket::listen(bind_address, webrtc_listen_address, public_webrtc_address).await;
-        if cfg!(debug_assertions) {
-            server_socket =
-                server_socket.with_link_conditioner(&LinkConditionerConfig::good_condition());
+    /// Server description for server browsers
+    pub fn status(&self, config: &Config) -> ServerStatus {
+        let players = self
+            .connections
+            .values()
+            .filter(|conn| conn.authorized)
+            .count();
+        ServerStatus {
+            name: config.server.name.clone(),
+            map: self.map_name.clone(),
+            players: u8::try_from(players).unwrap_or(u8::MAX),
+            max_players: u8::try_from(config.server.max_players).unwrap_or(u8::MAX),
+            version: VersionInfo::current(),
         }
+    }
 
-        log::info!("Bound listener socket: [{}]", bind_address);
-
-        let sender = server_socket.get_sender();
-
-        let (packet_sender, packet_receiver) = unbounded();
-        let (payload_sender, payload_receiver) = unbounded();
+    /// Detailed server state for monitoring tools
+    fn query(&self, world: &World, config: &mut Config, game_state: GameState) -> QueryInfo {
+        let status = self.status(config);
+        QueryInfo {
+            name: status.name,
+            motd: config.server.motd.clone(),
+            map: status.map,
+            game_state,
+            max_players: status.max_players,
+            version: status.version,
+            players: self
+                .connections
+                .values()
+                .filter(|conn| conn.authorized)
+                .map(|conn| PlayerInfo {
+                    nick: conn.nick.clone(),
+                    score: conn
+                        .entity
+                        .and_then(|entity| world.get::<components::Score>(entity).ok())
+                        .map_or(0, |score| score.score),
+                    ping: conn.stats.rtt as u16,
+                })
+                .collect(),
+            rules: public_cvars(config),
+        }
+    }
 
-        let handler = ConnectionManager::new(
-            PacketSocket {
-                bind_address,
-                packet_receiver,
-                payload_sender,
-            },
-            LaminarConfig::default(),
-        );
+    /// Answers connectionless queries. Replies are rate limited, so spoofed
+    /// queries cannot turn the server into a traffic amplifier.
+    fn process_out_of_band(
+        &mut self,
+        address: SocketAddr,
+        data: &[u8],
+        world: &World,
+        config: &mut Config,
+        game_state: GameState,
+    ) {
+        let code = match data.first() {
+            Some(&code) => code,
+            None => return,
+        };
 
-        Networking {
-            server_socket,
-            sender,
-            packet_sender,
-            payload_receiver,
-            handler,
-            connection_key: "1337".to_string(),
-            stats: Default::default(),
+        let now = Instant::now();
+        if !self.query_limiter.allow(now, address.ip()) || !self.query_global_limiter.allow(now, ())
+        {
+            log::debug!("Throttling out-of-band packet from [{}]", address);
+            return;
+        }
 
-            connections: HashMap::new(),
+        match messages::OperationCode::try_from(code) {
+            Ok(messages::OperationCode::SVREQ_INFO) if messages::packet_verify(data) => {
+                log::debug!("--> Sending server info to [{}]", address);
+                let msg = messages::server_info(&self.status(config));
+                self.send_out_of_band(address, &msg);
+            }
+            Ok(messages::OperationCode::SVREQ_QUERY) if messages::packet_verify(data) => {
+                log::debug!("--> Sending query reply to [{}]", address);
+                let msg = messages::server_query(&self.query(world, config, game_state));
+                self.send_out_of_band(address, &msg);
+            }
+            Ok(op_code) => {
+                log::error!(
+                    "Unhandled out-of-band packet from [{}]: {:?}",
+                    address,
+                    op_code
+                );
+            }
+            Err(_) => {
+                log::error!(
+                    "Unknown out-of-band packet from [{}]: 0x{:x}",
+                    address,
+                    code
+                );
+            }
         }
     }
 
-    pub fn send(&mut self, packet: LaminarPacket) {
-        if let Some(connection) = self.connections.get_mut(&packet.addr()) {
-            connection.stats.add_tx(packet.payload().len());
+    /// Registers server with `net.master` master server
+    fn heartbeat(&mut self, config: &Config) {
+        let master = &config.net.master;
+        if master.is_empty() {
+            return;
         }
-        if let Err(error) = self.handler.event_sender().send(packet) {
-            panic!("Error sending via event channel: {}", error);
+        if let Some(last) = self.last_heartbeat {
+            if last.elapsed().as_millis() < MASTER_HEARTBEAT as u128 {
+                return;
+            }
+        }
+        self.last_heartbeat = Some(Instant::now());
+
+        if let Some(address) = resolve_master(master) {
+            log::debug!("--> Sending heartbeat to master [{}]", address);
+            let msg = messages::master_heartbeat(&self.status(config));
+            self.send_out_of_band(address, &msg);
         }
     }
 
@@ -173,13 +301,13 @@ impl Networking {
         &mut self,
         world: &mut World,
         config: &mut Config,
+        game_state: GameState,
         messages: &mut VecDeque<(SocketAddr, NetworkMessage)>,
     ) {
-        match self.server_socket.receive().await {
-            Ok(packet) => {
-                let address = packet.address();
-                let data = packet.payload();
-                let len = data.len();
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        match self.socket.recv_from(&mut buffer).await {
+            Ok((len, address)) => {
+                let data = &buffer[..len];
                 log::debug!("<-- Received {} bytes from [{}]", len, address);
                 self.stats.add_rx(len);
                 if let Some(connection) = self.connections.get_mut(&address) {
@@ -187,75 +315,30 @@ impl Networking {
                 }
                 trace_dump_packet(data);
 
-                if len > 0 {
-                    if let Err(error) = self.packet_sender.send(packet).await {
-                        log::error!("Error processing packet from [{}]: {}", address, error);
+                if let Some(data) = decode_out_of_band(data) {
+                    self.process_out_of_band(address, data, world, config, game_state);
+                } else if len > 0 {
+                    for payload in self.channels.receive(address, data) {
+                        if let Some(message) = self.process_packet(address, &payload, world, config)
+                        {
+                            messages.push_back((address, message));
+                        }
                     }
                 }
             }
             Err(error) => {
-                match error {
-                    NaiaServerSocketError::Wrapped(error) => {
-                        log::error!("Naia socket error: {}", error);
-                    }
-                    NaiaServerSocketError::SendError(addr) => {
-                        log::info!("Error sending packet to [{}] - disconnecting", addr);
-                        self.connections.remove(&addr);
-                    }
-                };
-            }
-        }
-
-        self.handler.manual_poll(Instant::now());
-
-        while let Ok(event) = self.handler.event_receiver().try_recv() {
-            match event {
-                SocketEvent::Packet(packet) => {
-                    let addr = packet.addr();
-                    if let Some(message) = self.process_packet(packet, world, config) {
-                        messages.push_back((addr, message));
-                    }
-                }
-                SocketEvent::Connect(addr) => {
-                    log::info!("!! Connect {}", addr);
-                    self.connections.entry(addr).or_insert_with(Connection::new);
-                }
-                SocketEvent::Timeout(addr) | SocketEvent::Disconnect(addr) => {
-                    log::info!("!! Disconnect {}", addr);
-                    self.connections.remove(&addr);
-                }
-            }
-        }
-
-        match self.payload_receiver.try_recv() {
-            Ok(packet) => {
-                let address = packet.address();
-                let data = packet.payload();
-                log::debug!("--> Sending {} bytes to [{}]", data.len(), address);
-                self.stats.add_tx(data.len());
-                trace_dump_packet(data);
-
-                if let Err(error) = self.sender.send(packet).await {
-                    log::error!("Error sending payload to [{}]: {}", address, error);
-                }
+                log::error!("Socket error: {}", error);
             }
-            Err(error) => match error {
-                TryRecvError::Empty => {}
-                TryRecvError::Closed => {
-                    panic!("Error receiving from payload channel: {}", error);
-                }
-            },
         }
     }
 
     fn process_packet(
         &mut self,
-        packet: LaminarPacket,
+        address: SocketAddr,
+        data: &[u8],
         world: &mut World,
         config: &mut Config,
     ) -> Option<NetworkMessage> {
-        let address = packet.addr();
-        let data = packet.payload();
         if data.is_empty() {
             return None;
         }
@@ -264,14 +347,111 @@ impl Networking {
         match messages::OperationCode::try_from(code) {
             Ok(op_code) => match op_code {
                 messages::OperationCode::CCREQ_CONNECT => {
-                    let msg = if messages::packet_verify(data) {
-                        log::info!("<-> ACCEPT connection from [{:?}]", address);
-                        messages::connection_accept()
-                    } else {
-                        log::info!("<-> REJECT connection from [{:?}]", address);
-                        messages::connection_reject()
+                    if !self.handshake_limiter.allow(Instant::now(), address.ip()) {
+                        log::debug!("Throttling connection request from [{}]", address);
+                        return None;
+                    }
+                    let pending = self
+                        .connections
+                        .iter()
+                        .filter(|(&addr, conn)| addr != address && !conn.authorized)
+                        .count();
+                    let players = self
+                        .connections
+                        .iter()
+                        .filter(|(&addr, conn)| addr != address && conn.authorized)
+                        .count();
+                    let version = match messages::decode_request(data) {
+                        Ok(client) => {
+                            let version = VersionInfo::current().negotiate(&client);
+                            if version.is_none() {
+                                log::info!(
+                                    "<-> Client [{:?}] speaks {}, we {}",
+                                    address,
+                                    client,
+                                    VersionInfo::current()
+                                );
+                            }
+                            version
+                        }
+                        Err(error) if messages::packet_verify(data) => {
+                            log::info!(
+                                "<-> Client [{:?}] speaks unknown protocol version: {}",
+                                address,
+                                error
+                            );
+                            None
+                        }
+                        Err(error) => {
+                            log::error!("Malformed connect packet from [{:?}]: {}", address, error);
+                            return None;
+                        }
+                    };
+
+                    let result = match version {
+                        None => Err(RejectReason::VersionMismatch),
+                        Some(_) if self.bans.contains(&address.ip()) => Err(RejectReason::Banned),
+                        Some(_) if players >= config.server.max_players as usize => {
+                            Err(RejectReason::ServerFull)
+                        }
+                        Some(_)
+                            if pending >= MAX_PENDING_HANDSHAKES
+                                && !self.connections.contains_key(&address) =>
+                        {
+                            Err(RejectReason::ServerFull)
+                        }
+                        Some(version) => Ok(version),
                     };
-                    self.send(LaminarPacket::unreliable(address, msg.to_vec()));
+
+                    let msg = match result {
+                        Ok(version) => {
+                            log::info!(
+                                "<-> ACCEPT connection from [{:?}] (protocol v{})",
+                                address,
+                                version
+                            );
+                            let nonce: Nonce = rand::random();
+                            let connection = self
+                                .connections
+                                .entry(address)
+                                .or_insert_with(Connection::new);
+                            connection.nonce = Some(nonce);
+                            connection.protocol_version = version;
+                            messages::connection_accept(version, &nonce)
+                        }
+                        Err(reason) => {
+                            log::info!("<-> REJECT connection from [{:?}]: {}", address, reason);
+                            messages::connection_reject(reason)
+                        }
+                    };
+                    self.send(Packet::unreliable(address, msg.to_vec()));
+                }
+                messages::OperationCode::CCREQ_DISCONNECT => {
+                    match messages::decode_disconnect(data) {
+                        Ok((reason, _)) => self.disconnect(world, address, reason),
+                        Err(error) => {
+                            log::error!("Malformed disconnect from [{:?}]: {}", address, error);
+                            self.disconnect(world, address, DisconnectReason::Quit);
+                        }
+                    }
+                }
+                messages::OperationCode::STT_PING | messages::OperationCode::STT_PONG => {
+                    match messages::decode_ping(data) {
+                        Ok((messages::OperationCode::STT_PING, sequence))
+                            if self.connections.contains_key(&address) =>
+                        {
+                            let msg = messages::pong(sequence);
+                            self.send(Packet::unreliable(address, msg.to_vec()));
+                        }
+                        Ok((_, sequence)) => {
+                            if let Some(connection) = self.connections.get_mut(&address) {
+                                connection.stats.pong(sequence);
+                            }
+                        }
+                        Err(error) => {
+                            log::error!("Malformed ping from [{:?}]: {}", address, error);
+                        }
+                    }
                 }
                 _ => match self.connections.get_mut(&address) {
                     Some(connection) => {
@@ -280,30 +460,45 @@ impl Networking {
                             connection.ready = true;
                         } else {
                             match messages::decode_message(data) {
-                                Some(message) => match message {
-                                    NetworkMessage::ConnectionAuthorize { nick, key } => {
-                                        let msg = if key == self.connection_key {
-                                            if !connection.authorized {
-                                                connection.authorized = true;
-                                                connection.nick = nick;
-                                                log::info!(
-                                                    "<-> AUTH connection from [{:?}]",
-                                                    address
-                                                );
-
-                                                connection.entity.replace(world.reserve_entity());
-                                            }
-                                            messages::connection_authorized(
-                                                config.server.motd.clone(),
-                                            )
-                                        } else {
+                                Ok(message) => match message {
+                                    NetworkMessage::ConnectionAuthorize { nick, mac } => {
+                                        let key = &self.connection_key;
+                                        let verified = connection.nonce.map_or(false, |nonce| {
+                                            auth_verify(key, &nonce, &nick, &mac)
+                                        });
+                                        if !verified {
                                             log::info!(
-                                                "<-> REJECT connection from [{:?}]",
-                                                address
+                                                "<-> REJECT connection from [{:?}]: {}",
+                                                address,
+                                                RejectReason::BadKey
                                             );
-                                            messages::connection_reject()
-                                        };
-                                        self.send(LaminarPacket::reliable_unordered(
+                                            // nonce is not reused for another guess, pending
+                                            // connection has to handshake again
+                                            connection.nonce = Some(rand::random());
+                                            let authorized = connection.authorized;
+                                            self.send(Packet::reliable_unordered(
+                                                address,
+                                                messages::connection_reject(RejectReason::BadKey)
+                                                    .to_vec(),
+                                            ));
+                                            if !authorized {
+                                                self.connections.remove(&address);
+                                            }
+                                            return None;
+                                        }
+
+                                        if !connection.authorized {
+                                            connection.authorized = true;
+                                            connection.nick = nick;
+                                            log::info!("<-> AUTH connection from [{:?}]", address);
+
+                                            connection.entity.replace(world.reserve_entity());
+                                        }
+                                        let msg = messages::connection_authorized(
+                                            config.server.motd.clone(),
+                                            connection.entity.unwrap(),
+                                        );
+                                        self.send(Packet::reliable_unordered(
                                             address,
                                             msg.to_vec(),
                                         ));
@@ -316,10 +511,16 @@ impl Networking {
                                                 }
                                             }
                                         });
-                                        self.send(LaminarPacket::reliable_unordered(
+                                        self.send(Packet::reliable_unordered(
                                             address,
                                             encode_message(NetworkMessage::Cvars(cvars)).to_vec(),
-                                        ))
+                                        ));
+
+                                        let msg = NetworkMessage::MapChange(self.map_name.clone());
+                                        self.send(Packet::reliable_ordered(
+                                            address,
+                                            encode_message(msg).to_vec(),
+                                        ));
                                     }
                                     msg => {
                                         if connection.authorized {
@@ -327,12 +528,13 @@ impl Networking {
                                         }
                                     }
                                 },
-                                None => {
+                                Err(error) => {
                                     log::error!(
-                                        "Unhandled packet: 0x{:x} ({:?}) {} bytes",
+                                        "Unhandled packet: 0x{:x} ({:?}) {} bytes: {}",
                                         code,
                                         op_code,
-                                        data.len()
+                                        data.len(),
+                                        error
                                     );
                                     trace_dump_packet(data);
                                 }
@@ -352,96 +554,169 @@ impl Networking {
         None
     }
 
-    pub fn process_simulation(&mut self, server: &mut Server<GameWorld>) {
-        for snapshot in server.take_outgoing_snapshots().drain(..) {
-            log::trace!("outgoing snapshot: {:?}", snapshot);
-            let mut packets = Vec::new();
-            for (&address, connection) in self.connections.iter_mut() {
-                // FIXME: state snapshot should be localized to entity
-                if let Some(_entity) = connection.entity {
-                    // TODO: snapshotting can be distributed, does not need to be all at the same time
-                    // let next_broadcast = connection.last_broadcast + BROADCAST_RATE;
-                    // if next_broadcast < time.time {
-                    // connection.last_broadcast = next_broadcast;
-                    connection.last_broadcast = Instant::now();
-                    packets.push(LaminarPacket::unreliable(
-                        address,
-                        encode_message(NetworkMessage::Snapshot(snapshot.clone())).to_vec(),
-                    ));
-                }
-            }
-            for packet in packets.drain(..) {
-                self.send(packet);
-            }
-        }
+    /// Sends result of command execution back to the client that asked for it
+    pub fn send_command_output(&mut self, address: SocketAddr, tick: usize, output: String) {
+        let msg = encode_message(NetworkMessage::Command {
+            tick,
+            command: NetCommand::Output(output),
+        });
+        self.send(Packet::reliable_ordered(address, msg.to_vec()));
+    }
 
-        for (from, to, command) in server.take_outgoing_commands().drain(..) {
-            log::trace!("outgoing command: {:?} -> {:?}: {:?}", from, to, command);
-            let destinations = self.connections.iter().map(|(addr, _conn)| *addr);
-            let destinations: Vec<SocketAddr> = if let Some(to) = to {
-                // find if 'to' is connected
-                destinations.filter(|addr| *addr == to).collect()
-            } else if let Some(from) = from {
-                // do not broadcast to oneself
-                destinations.filter(|addr| *addr != from).collect()
-            } else {
-                // broadcast to all
-                destinations.collect()
-            };
-            let msg = encode_message(NetworkMessage::Command(command));
-            for address in destinations {
-                self.send(LaminarPacket::reliable_unordered(address, msg.to_vec()));
-            }
-        }
+    /// Sends full state reliably, to resynchronize client which lost its delta base
+    pub fn send_snapshot(&mut self, world: &World, time: &systems::Time, address: SocketAddr) {
+        let snapshot = collect_snapshot(world, &self.registry);
+        let connection = match self.connections.get_mut(&address) {
+            Some(connection) => connection,
+            None => return,
+        };
+        connection.last_resync = Some(time.time);
+        connection.snapshots.push(time.tick, snapshot.clone());
+
+        let msg = encode_message(NetworkMessage::Snapshot {
+            tick: time.tick,
+            snapshot: NetSnapshot { entities: snapshot },
+        });
+        self.send(Packet::reliable_unordered(address, msg.to_vec()));
     }
 
-    pub fn broadcast_state(&mut self, world: &World, time: &systems::Time) {
+    pub fn broadcast_state(&mut self, world: &World, config: &Config, time: &systems::Time) {
         let mut packets = Vec::new();
+        let mut resync = Vec::new();
 
         for (&address, connection) in self.connections.iter_mut() {
-            if let Some(entity) = connection.entity {
+            if connection.entity.is_some() {
+                let base_lost = connection.ack_tick != 0
+                    && connection.snapshots.get(connection.ack_tick).is_none();
+                if base_lost {
+                    if connection.resync_due(time.time) {
+                        resync.push(address);
+                    }
+                    continue;
+                }
+
                 let next_broadcast = connection.last_broadcast
                     + Duration::from_millis((BROADCAST_RATE * 1000.) as u64);
                 if next_broadcast < time.time {
                     connection.last_broadcast = next_broadcast;
 
-                    let msg = build_state_message(world, entity, time);
-                    packets.push(LaminarPacket::unreliable(address, msg.to_vec()));
+                    for msg in build_state_message(
+                        world,
+                        &self.registry,
+                        time,
+                        connection,
+                        config.net.view_radius,
+                    ) {
+                        packets.push(Packet::unreliable(address, msg.to_vec()));
+                    }
                 }
             }
         }
+
+        for packet in packets.drain(..) {
+            self.send(packet);
+        }
+        for address in resync {
+            log::info!("Client [{}] lost delta base - resyncing", address);
+            self.send_snapshot(world, time, address);
+        }
     }
 
-    pub fn post_process(&mut self, config: &Config) {
+    /// Tells all players to load map `name`
+    pub fn change_map(&mut self, name: &str) {
+        self.map_name = name.to_string();
+
+        let msg = encode_message(NetworkMessage::MapChange(self.map_name.clone()));
+        let addresses: Vec<SocketAddr> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.authorized)
+            .map(|(&address, _)| address)
+            .collect();
+        for address in addresses {
+            self.send(Packet::reliable_ordered(address, msg.to_vec()));
+        }
+    }
+
+    /// Removes connection and despawns its player from the world
+    pub fn disconnect(&mut self, world: &mut World, address: SocketAddr, reason: DisconnectReason) {
+        if let Some(connection) = self.connections.remove(&address) {
+            log::info!(
+                "<-> DISCONNECT [{}] {:?}: {}",
+                address,
+                connection.nick,
+                reason
+            );
+            if let Some(entity) = connection.entity {
+                let _ = world.despawn(entity);
+            }
+        }
+    }
+
+    /// Drops connection, telling client why
+    pub fn kick(
+        &mut self,
+        world: &mut World,
+        address: SocketAddr,
+        reason: DisconnectReason,
+        message: &str,
+    ) {
+        if self.connections.contains_key(&address) {
+            let msg = messages::connection_kick(reason, message);
+            self.send(Packet::unreliable(address, msg.to_vec()));
+        }
+        self.disconnect(world, address, reason);
+    }
+
+    pub fn post_process(&mut self, world: &mut World, config: &Config) {
         let mut to_disconnect = Vec::new();
+        let mut packets = Vec::new();
 
-        for (address, connection) in &self.connections {
-            if connection.ready {
-                if config.net.send_keepalive > 0
-                    && connection.stats.last_tx.elapsed().as_secs_f32()
-                        > config.net.send_keepalive as f32
-                {
-                    log::warn!("Sending keepalive to [{}]", address);
-                    let packet = NaiaPacket::new(*address, Vec::new());
-                    let sender = &mut self.sender;
-                    smol::block_on(async {
-                        if let Err(error) = sender.send(packet).await {
-                            log::error!("Error sending keepalive to [{}]: {}", address, error);
-                        }
-                    });
-                }
-                if config.net.keepalive_timeout > 0
-                    && connection.stats.last_rx.elapsed().as_secs_f32()
-                        > config.net.keepalive_timeout as f32
-                {
-                    log::error!("Client [{}] connection timeout - dropping", address);
-                    to_disconnect.push(*address);
+        for (address, connection) in &mut self.connections {
+            if connection.authorized {
+                if let Some(sequence) = connection.stats.ping(config.net.ping_interval) {
+                    packets.push(Packet::unreliable(
+                        *address,
+                        messages::ping(sequence).to_vec(),
+                    ));
                 }
             }
+            if config.net.send_keepalive > 0
+                && connection.stats.last_tx.elapsed().as_millis()
+                    > config.net.send_keepalive as u128
+            {
+                log::debug!("Sending keepalive to [{}]", address);
+                packets.push(Packet::unreliable(*address, Vec::new()));
+            }
+            if config.net.keepalive_timeout > 0
+                && connection.stats.last_rx.elapsed().as_millis()
+                    > config.net.keepalive_timeout as u128
+            {
+                log::error!("Client [{}] connection timeout - dropping", address);
+                to_disconnect.push(*address);
+            } else if !connection.authorized
+                && connection.accepted.elapsed().as_millis() > HANDSHAKE_TIMEOUT
+            {
+                log::info!("Client [{}] handshake timeout - dropping", address);
+                to_disconnect.push(*address);
+            }
         }
 
+        for packet in packets {
+            self.send(packet);
+        }
         for address in to_disconnect.drain(..) {
-            self.connections.remove(&address);
+            self.kick(world, address, DisconnectReason::Timeout, "");
         }
+
+        self.heartbeat(config);
+        let now = Instant::now();
+        self.query_limiter.expire(now);
+        self.query_global_limiter.expire(now);
+        self.handshake_limiter.expire(now);
+
+        let connections = &self.connections;
+        self.channels
+            .retain(|address| connections.contains_key(address));
     }
 }
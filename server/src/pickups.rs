@@ -0,0 +1,187 @@
+use hecs::{Entity, World};
+
+use crate::{constants::*, cvars::Config, systems::nick};
+use soldank_shared::{
+    components,
+    mapfile::MapFile,
+    math::vec2,
+    pickup::{Pickup, PickupKind},
+    soldier::Soldier,
+};
+
+/// Spawns kits at map kit spawnpoints. Medikits and grenade packs are limited
+/// to the number the map asks for, other kits lie at all their spawnpoints.
+pub fn spawn_pickups(world: &mut World, map: &MapFile) {
+    for kind in PickupKind::ALL {
+        let limit = match kind {
+            PickupKind::Medikit => map.medikits as usize,
+            PickupKind::Grenades => map.grenade_packs as usize,
+            _ => usize::MAX,
+        };
+        for spawn in map.spawnpoints_of(kind.spawn_type()).take(limit) {
+            let pos = vec2(spawn.x as f32, spawn.y as f32);
+            world.spawn((kind, components::Position(pos), Pickup::new(kind, pos)));
+        }
+    }
+}
+
+/// Gives kits to soldiers touching them and brings taken kits back after `pickup_respawn`.
+/// Taken kit loses its `PickupKind` component, so clients stop showing it.
+pub fn update_pickups(world: &mut World, config: &Config, tick: usize) {
+    let respawn_ticks = (config.game.pickup_respawn as f64 / 1000. / TIMESTEP_RATE) as usize;
+    let mut taken = Vec::new();
+    let mut respawned = Vec::new();
+
+    {
+        let mut query = world.query::<&mut Pickup>();
+        let mut pickups: Vec<(Entity, &mut Pickup)> = query.iter().collect();
+
+        for (entity, pickup) in pickups.iter_mut() {
+            if pickup.update(tick) {
+                respawned.push((*entity, pickup.kind));
+            }
+        }
+
+        for (soldier_entity, soldier) in world.query::<&mut Soldier>().iter() {
+            for (entity, pickup) in pickups.iter_mut() {
+                if pickup.touches(soldier.particle.pos) && pickup.take(soldier, tick, respawn_ticks)
+                {
+                    taken.push((*entity, pickup.kind, soldier_entity));
+                }
+            }
+        }
+    }
+
+    for (entity, kind) in respawned {
+        let _ = world.insert_one(entity, kind);
+    }
+    for (entity, kind, by) in taken {
+        log::debug!("{:?} taken by {:?}", kind, nick(world, by));
+        let _ = world.remove_one::<PickupKind>(entity);
+    }
+}
+
+/// Removes all kits, before spawning them for another map or match
+pub fn despawn_pickups(world: &mut World) {
+    let pickups: Vec<Entity> = world
+        .query::<&Pickup>()
+        .iter()
+        .map(|(entity, _pickup)| entity)
+        .collect();
+    for entity in pickups {
+        let _ = world.despawn(entity);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soldank_shared::{
+        anims::{Anim, AnimState},
+        components::{Position, Team},
+        math::Vec2,
+        particles::{Particle, ParticleSystem},
+        pickup::*,
+        weapons::{Weapon, WeaponKind},
+    };
+
+    /// Soldier at `pos`, without skeleton and animations loaded from game files
+    fn soldier(pos: Vec2) -> Soldier {
+        let anim = AnimState {
+            id: Anim::Stand,
+            looped: false,
+            speed: 1,
+            count: 0,
+            frame: 1,
+        };
+        Soldier {
+            active: true,
+            dead_meat: false,
+            style: 0,
+            num: 1,
+            visible: 1,
+            on_ground: false,
+            on_ground_for_law: false,
+            on_ground_last_frame: false,
+            on_ground_permanent: false,
+            direction: 1,
+            old_direction: 1,
+            health: 50.,
+            alpha: 255,
+            jets_count: 0,
+            jets_count_prev: 0,
+            wear_helmet: 0,
+            has_cigar: 0,
+            vest: 0.,
+            idle_time: 0,
+            idle_random: 0,
+            position: 0,
+            on_fire: 0,
+            collider_distance: 255,
+            half_dead: false,
+            skeleton: ParticleSystem::new(),
+            legs_animation: anim,
+            body_animation: anim,
+            control: Default::default(),
+            active_weapon: 0,
+            weapons: [
+                Weapon::new(WeaponKind::DesertEagles, false),
+                Weapon::new(WeaponKind::Chainsaw, false),
+                Weapon::new(WeaponKind::FragGrenade, false),
+            ],
+            fired: 0,
+            particle: Particle {
+                pos,
+                ..Default::default()
+            },
+            team: Team::None,
+            flagger: false,
+            berserker: 0,
+            predator: 0,
+        }
+    }
+
+    #[test]
+    fn take_and_respawn() {
+        let mut config = Config::default();
+        config.game.pickup_respawn = 1000;
+        let respawn_ticks = (1. / TIMESTEP_RATE) as usize;
+
+        for (n, &kind) in PickupKind::ALL.iter().enumerate() {
+            let mut world = World::new();
+            let pos = vec2(100. * n as f32, 0.);
+            let pickup = world.spawn((kind, Position(pos), Pickup::new(kind, pos)));
+            let mut soldier = soldier(pos + vec2(5., 5.));
+            soldier.weapons[2].ammo_count = 0;
+            let taker = world.spawn((soldier,));
+
+            update_pickups(&mut world, &config, 10);
+            assert!(world.get::<PickupKind>(pickup).is_err(), "{:?}", kind);
+
+            {
+                let soldier = world.get::<Soldier>(taker).unwrap();
+                match kind {
+                    PickupKind::Medikit => assert_eq!(soldier.health, MAX_HEALTH),
+                    PickupKind::Grenades => {
+                        assert_eq!(soldier.weapons[2].kind, WeaponKind::FragGrenade);
+                        assert_eq!(soldier.weapons[2].ammo_count, MAX_GRENADES);
+                    }
+                    PickupKind::Clusters => {
+                        assert_eq!(soldier.weapons[2].kind, WeaponKind::ClusterGrenade);
+                        assert_eq!(soldier.weapons[2].ammo_count, MAX_CLUSTERS);
+                    }
+                    PickupKind::Vest => assert_eq!(soldier.vest, MAX_VEST),
+                    PickupKind::Berserker => assert_eq!(soldier.berserker, BERSERKER_TIME),
+                    PickupKind::Predator => assert_eq!(soldier.predator, PREDATOR_TIME),
+                }
+            }
+
+            // soldier walks away, kit comes back after `pickup_respawn`
+            world.get_mut::<Soldier>(taker).unwrap().particle.pos = vec2(-1000., -1000.);
+            update_pickups(&mut world, &config, 10 + respawn_ticks - 1);
+            assert!(world.get::<PickupKind>(pickup).is_err());
+            update_pickups(&mut world, &config, 10 + respawn_ticks);
+            assert_eq!(*world.get::<PickupKind>(pickup).unwrap(), kind);
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use hecs::World;
+use std::net::SocketAddr;
+
+use crate::{
+    constants::*,
+    ctf,
+    cvars::{Config, GameMode},
+    networking::Networking,
+    pickups,
+    scores::{Scoreboard, Winner},
+    systems,
+};
+use soldank_shared::{
+    mapfile::MapFile,
+    messages::{encode_message, GameState, NetworkMessage},
+    networking::Packet,
+};
+
+/// Match lifecycle driving `GameState` transitions
+pub struct Round {
+    pub state: GameState,
+    /// tick current state was entered at
+    pub started: usize,
+    /// tick current state ends at, 0 when it waits for players
+    pub ends: usize,
+}
+
+impl Default for Round {
+    fn default() -> Self {
+        Round {
+            state: GameState::Warmup,
+            started: 0,
+            ends: 0,
+        }
+    }
+}
+
+/// Change of match state, for `Round::update` to carry out in the world
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Transition {
+    /// state or its end time changed, players need to know
+    Changed,
+    /// countdown is over - soldiers, scores and flags are reset for the match
+    Start,
+    /// match ended with a winner
+    End(Winner),
+    /// players left mid-match - back to warmup with a fresh world
+    Restart,
+    /// intermission is over - the next map should be played
+    NextMap,
+}
+
+impl Round {
+    /// Moves match to the next state when it is due, setting up the world for it.
+    /// Returns `true` when intermission is over and the next map should be played.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        map: &MapFile,
+        config: &Config,
+        networking: &mut Networking,
+        scoreboard: &mut Scoreboard,
+        tick: usize,
+    ) -> bool {
+        let players = networking.players().len();
+        let ready = networking
+            .connections
+            .values()
+            .all(|conn| conn.authorized && conn.entity.is_some() && conn.ready);
+        let winner = if self.live() {
+            scoreboard.winner(world, config, tick)
+        } else {
+            None
+        };
+
+        match self.next(config, players, ready, winner, tick) {
+            Some(Transition::Changed) => self.broadcast(networking),
+            Some(Transition::Start) => {
+                systems::reset_soldiers(world, map, config);
+                scoreboard.start(world, tick);
+                if config.game.mode == GameMode::Ctf {
+                    ctf::spawn_flags(world, map, config);
+                }
+                self.broadcast(networking);
+            }
+            Some(Transition::End(winner)) => {
+                scoreboard.announce(world, winner);
+                self.broadcast(networking);
+            }
+            Some(Transition::Restart) => self.restart(world, map, config, networking, tick),
+            Some(Transition::NextMap) => return true,
+            None => {}
+        }
+
+        false
+    }
+
+    /// Switches state when it is due, given number of `players` in game, whether
+    /// all connections are `ready` and match `winner` once a limit was reached.
+    /// World is left alone, returned transition tells what to do with it.
+    /// `Restart` and `NextMap` leave the state as is, see `restart`.
+    pub fn next(
+        &mut self,
+        config: &Config,
+        players: usize,
+        ready: bool,
+        winner: Option<Winner>,
+        tick: usize,
+    ) -> Option<Transition> {
+        // zero `ends` waits for players in warmup, but is zero duration otherwise
+        let timeout = match self.state {
+            GameState::Countdown | GameState::Intermission => tick >= self.ends,
+            _ => self.ends != 0 && tick >= self.ends,
+        };
+
+        match self.state {
+            GameState::Warmup => {
+                if players > 0 && (ready || timeout) {
+                    log::info!("Match starts in {}s", config.server.countdown);
+                    self.switch(GameState::Countdown, tick, config.server.countdown);
+                    Some(Transition::Changed)
+                } else if self.ends == 0 && players > 0 && config.server.warmup > 0 {
+                    // warmup time runs since the first player joined
+                    self.ends = tick + seconds(config.server.warmup);
+                    Some(Transition::Changed)
+                } else if players == 0 && self.ends != 0 {
+                    self.ends = 0;
+                    None
+                } else {
+                    None
+                }
+            }
+            GameState::Countdown => {
+                if players == 0 {
+                    log::info!("No players left - back to warmup");
+                    self.switch(GameState::Warmup, tick, 0);
+                    Some(Transition::Changed)
+                } else if timeout {
+                    log::info!("Match started");
+                    self.switch(GameState::InGame, tick, 0);
+                    Some(Transition::Start)
+                } else {
+                    None
+                }
+            }
+            GameState::InGame => {
+                if players == 0 {
+                    log::info!("No players left - back to warmup");
+                    Some(Transition::Restart)
+                } else if let Some(winner) = winner {
+                    self.switch(GameState::Intermission, tick, config.server.intermission);
+                    Some(Transition::End(winner))
+                } else {
+                    None
+                }
+            }
+            GameState::Intermission if timeout || players == 0 => Some(Transition::NextMap),
+            GameState::Intermission => None,
+        }
+    }
+
+    /// Soldiers play, but only live match counts the score
+    pub fn live(&self) -> bool {
+        self.state == GameState::InGame
+    }
+
+    /// Soldiers cannot move while waiting for the match to start or the next map
+    pub fn frozen(&self) -> bool {
+        matches!(self.state, GameState::Countdown | GameState::Intermission)
+    }
+
+    pub fn message(&self) -> NetworkMessage {
+        NetworkMessage::MatchState {
+            tick: self.started,
+            state: self.state,
+            ends: self.ends,
+        }
+    }
+
+    /// Tells newly connected player what the match is up to
+    pub fn send(&self, networking: &mut Networking, address: SocketAddr) {
+        let msg = encode_message(self.message());
+        networking.send(Packet::reliable_ordered(address, msg.to_vec()));
+    }
+
+    fn broadcast(&self, networking: &mut Networking) {
+        let msg = encode_message(self.message());
+        let addresses: Vec<_> = networking
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.authorized)
+            .map(|(&address, _)| address)
+            .collect();
+        for address in addresses {
+            networking.send(Packet::reliable_ordered(address, msg.to_vec()));
+        }
+    }
+
+    fn switch(&mut self, state: GameState, tick: usize, duration: u32) {
+        log::debug!("Game state {:?} -> {:?}", self.state, state);
+        self.state = state;
+        self.started = tick;
+        self.ends = if duration > 0 {
+            tick + seconds(duration)
+        } else {
+            0
+        };
+    }
+
+    /// Back to warmup on `map` with fresh kits, no flags and bullets, and soldiers at spawnpoints
+    pub fn restart(
+        &mut self,
+        world: &mut World,
+        map: &MapFile,
+        config: &Config,
+        networking: &mut Networking,
+        tick: usize,
+    ) {
+        ctf::despawn_flags(world);
+        systems::despawn_bullets(world);
+        pickups::despawn_pickups(world);
+        pickups::spawn_pickups(world, map);
+        systems::reset_soldiers(world, map, config);
+        self.switch(GameState::Warmup, tick, 0);
+        self.broadcast(networking);
+    }
+}
+
+fn seconds(seconds: u32) -> usize {
+    (seconds as f64 / TIMESTEP_RATE) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soldank_shared::components::{Score, Team};
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.server.warmup = 10;
+        config.server.countdown = 3;
+        config.server.intermission = 5;
+        config.game.mode = GameMode::Deathmatch;
+        config.game.frag_limit = 10;
+        config.game.time_limit = 60;
+        config
+    }
+
+    #[test]
+    fn warmup() {
+        let config = config();
+        let mut round = Round::default();
+
+        // nobody to play with
+        assert_eq!(round.next(&config, 0, true, None, 1), None);
+        assert_eq!(round.state, GameState::Warmup);
+        assert_eq!(round.ends, 0);
+
+        // first player starts warmup time, leaving stops it
+        assert_eq!(
+            round.next(&config, 1, false, None, 10),
+            Some(Transition::Changed)
+        );
+        assert_eq!(round.ends, 10 + seconds(10));
+        assert_eq!(round.next(&config, 0, false, None, 20), None);
+        assert_eq!(round.ends, 0);
+
+        // warmup time runs out before players get ready
+        round.next(&config, 2, false, None, 30);
+        assert_eq!(round.next(&config, 2, false, None, round.ends - 1), None);
+        let countdown = round.ends;
+        assert_eq!(
+            round.next(&config, 2, false, None, countdown),
+            Some(Transition::Changed)
+        );
+        assert_eq!(round.state, GameState::Countdown);
+        assert_eq!(round.ends, countdown + seconds(3));
+
+        // everybody is ready before warmup time runs out
+        let mut round = Round::default();
+        assert_eq!(
+            round.next(&config, 2, true, None, 5),
+            Some(Transition::Changed)
+        );
+        assert_eq!(round.state, GameState::Countdown);
+    }
+
+    #[test]
+    fn countdown() {
+        let config = config();
+        let mut round = Round::default();
+        round.switch(GameState::Countdown, 100, config.server.countdown);
+        assert_eq!(round.next(&config, 1, false, None, round.ends - 1), None);
+        assert_eq!(
+            round.next(&config, 1, false, None, round.ends),
+            Some(Transition::Start)
+        );
+        assert_eq!(round.state, GameState::InGame);
+        assert_eq!(round.ends, 0);
+
+        round.switch(GameState::Countdown, 200, config.server.countdown);
+        assert_eq!(
+            round.next(&config, 0, false, None, 201),
+            Some(Transition::Changed)
+        );
+        assert_eq!(round.state, GameState::Warmup);
+
+        // no countdown - match starts on next update
+        let mut config = config;
+        config.server.countdown = 0;
+        let mut round = Round::default();
+        assert_eq!(
+            round.next(&config, 1, true, None, 300),
+            Some(Transition::Changed)
+        );
+        assert_eq!(round.state, GameState::Countdown);
+        assert_eq!(
+            round.next(&config, 1, true, None, 301),
+            Some(Transition::Start)
+        );
+        assert_eq!(round.state, GameState::InGame);
+    }
+
+    #[test]
+    fn limits() {
+        let config = config();
+        let mut world = World::new();
+        let leader = world.spawn((Score::default(), Team::None));
+        world.spawn((Score::default(), Team::None));
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.start(&mut world, 100);
+        let mut round = Round::default();
+        round.switch(GameState::InGame, 100, 0);
+
+        let winner = scoreboard.winner(&world, &config, 101);
+        assert_eq!(round.next(&config, 2, false, winner, 101), None);
+
+        // time limit with equal scores
+        let time_up = 100 + seconds(config.game.time_limit);
+        let winner = scoreboard.winner(&world, &config, time_up - 1);
+        assert_eq!(winner, None);
+        let winner = scoreboard.winner(&world, &config, time_up);
+        assert_eq!(winner, Some(Winner::Draw));
+
+        // frag limit
+        world.get_mut::<Score>(leader).unwrap().score = 10;
+        let winner = scoreboard.winner(&world, &config, 102);
+        assert_eq!(winner, Some(Winner::Player(leader)));
+        let end = round.next(&config, 2, false, winner, 102);
+        assert_eq!(end, Some(Transition::End(Winner::Player(leader))));
+        assert_eq!(round.state, GameState::Intermission);
+        assert_eq!(round.ends, 102 + seconds(config.server.intermission));
+
+        // everybody left mid-match
+        let mut round = Round::default();
+        round.switch(GameState::InGame, 100, 0);
+        assert_eq!(
+            round.next(&config, 0, false, None, 101),
+            Some(Transition::Restart)
+        );
+    }
+
+    #[test]
+    fn intermission() {
+        let config = config();
+        let mut round = Round::default();
+        round.switch(GameState::Intermission, 100, config.server.intermission);
+        assert_eq!(round.next(&config, 1, true, None, round.ends - 1), None);
+        assert_eq!(
+            round.next(&config, 1, true, None, round.ends),
+            Some(Transition::NextMap)
+        );
+        assert_eq!(
+            round.next(&config, 0, true, None, 101),
+            Some(Transition::NextMap)
+        );
+
+        // no intermission - next map right after the match ends
+        let mut config = config;
+        config.server.intermission = 0;
+        let mut round = Round::default();
+        round.switch(GameState::InGame, 200, 0);
+        let end = round.next(&config, 1, true, Some(Winner::Draw), 201);
+        assert_eq!(end, Some(Transition::End(Winner::Draw)));
+        assert_eq!(round.state, GameState::Intermission);
+        assert_eq!(
+            round.next(&config, 1, true, None, 202),
+            Some(Transition::NextMap)
+        );
+    }
+}
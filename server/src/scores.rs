@@ -0,0 +1,226 @@
+use hecs::{Entity, World};
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{
+    constants::*,
+    cvars::{Config, GameMode},
+    systems::nick,
+};
+use soldank_shared::components::{Score, Team};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Winner {
+    Player(Entity),
+    Team(Team),
+    Draw,
+}
+
+/// Match scores and limits, common to all game modes
+#[derive(Default)]
+pub struct Scoreboard {
+    /// kills in team deathmatch, captures in capture the flag
+    pub teams: HashMap<Team, i32>,
+    /// tick the match started at
+    pub started: usize,
+}
+
+impl Scoreboard {
+    /// Zeroes all scores and starts counting match time
+    pub fn start(&mut self, world: &mut World, tick: usize) {
+        for (_entity, score) in world.query::<&mut Score>().iter() {
+            *score = Score::default();
+        }
+        self.teams.clear();
+        self.started = tick;
+    }
+
+    /// Counts `victim` killed by `killer`, or suicide when `killer` is `None`
+    pub fn kill(&mut self, world: &World, config: &Config, killer: Option<Entity>, victim: Entity) {
+        if let Ok(mut score) = world.get_mut::<Score>(victim) {
+            score.deaths += 1;
+        }
+
+        let killer = match killer {
+            Some(killer) if killer != victim => killer,
+            _ => {
+                if let Ok(mut score) = world.get_mut::<Score>(victim) {
+                    score.score -= 1;
+                }
+                log::info!("{:?} committed suicide", nick(world, victim));
+                return;
+            }
+        };
+
+        let killer_team = team(world, killer);
+        let teamkill = config.game.mode.has_teams() && killer_team == team(world, victim);
+        let points = if teamkill { -1 } else { 1 };
+        if let Ok(mut score) = world.get_mut::<Score>(killer) {
+            if !teamkill {
+                score.kills += 1;
+            }
+            score.score += points;
+        }
+        if config.game.mode == GameMode::TeamDeathmatch {
+            *self.teams.entry(killer_team).or_default() += points;
+        }
+
+        log::info!(
+            "{:?} {} {:?}",
+            nick(world, killer),
+            if teamkill { "teamkilled" } else { "killed" },
+            nick(world, victim)
+        );
+    }
+
+    /// Counts flag captured by `scorer`
+    pub fn capture(&mut self, world: &World, scorer: Entity) {
+        *self.teams.entry(team(world, scorer)).or_default() += 1;
+        if let Ok(mut score) = world.get_mut::<Score>(scorer) {
+            score.score += CAPTURE_SCORE;
+        }
+        log::info!("{}", self.team_score_line());
+    }
+
+    /// Winner of the match, when score or time limit was reached
+    pub fn winner(&self, world: &World, config: &Config, tick: usize) -> Option<Winner> {
+        let time_limit = (config.game.time_limit as f64 / TIMESTEP_RATE) as usize;
+        let time_up = time_limit > 0 && tick - self.started >= time_limit;
+        let reached = |score: i32, limit: u32| limit > 0 && score >= limit as i32;
+
+        if config.game.mode.has_teams() {
+            let alpha = self.team_score(Team::Alpha);
+            let bravo = self.team_score(Team::Bravo);
+            let limit = match config.game.mode {
+                GameMode::Ctf => config.game.score_limit,
+                _ => config.game.frag_limit,
+            };
+            if !time_up && !reached(alpha, limit) && !reached(bravo, limit) {
+                return None;
+            }
+            Some(match alpha.cmp(&bravo) {
+                Ordering::Greater => Winner::Team(Team::Alpha),
+                Ordering::Less => Winner::Team(Team::Bravo),
+                Ordering::Equal => Winner::Draw,
+            })
+        } else {
+            let mut scores: Vec<(Entity, i32)> = world
+                .query::<&Score>()
+                .iter()
+                .map(|(entity, score)| (entity, score.score))
+                .collect();
+            scores.sort_by_key(|&(_entity, score)| -score);
+
+            let leader = scores.first().copied();
+            if !time_up
+                && !leader.map_or(false, |(_, score)| reached(score, config.game.frag_limit))
+            {
+                return None;
+            }
+            match (leader, scores.get(1)) {
+                (Some((_, first)), Some(&(_, second))) if first == second => Some(Winner::Draw),
+                (Some((entity, _)), _) => Some(Winner::Player(entity)),
+                (None, _) => Some(Winner::Draw),
+            }
+        }
+    }
+
+    /// Logs match result
+    pub fn announce(&self, world: &World, winner: Winner) {
+        match winner {
+            Winner::Player(entity) => log::info!("{:?} wins the match", nick(world, entity)),
+            Winner::Team(team) => log::info!("{:?} wins - {}", team, self.team_score_line()),
+            Winner::Draw => log::info!("Match draw"),
+        }
+    }
+
+    pub fn team_score(&self, team: Team) -> i32 {
+        self.teams.get(&team).copied().unwrap_or_default()
+    }
+
+    fn team_score_line(&self) -> String {
+        format!(
+            "Alpha {} : {} Bravo",
+            self.team_score(Team::Alpha),
+            self.team_score(Team::Bravo)
+        )
+    }
+}
+
+fn team(world: &World, entity: Entity) -> Team {
+    world
+        .get::<Team>(entity)
+        .map(|team| *team)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn score(world: &World, entity: Entity) -> Score {
+        *world.get::<Score>(entity).unwrap()
+    }
+
+    #[test]
+    fn deathmatch_kill() {
+        let mut config = Config::default();
+        config.game.mode = GameMode::Deathmatch;
+        let mut world = World::new();
+        let killer = world.spawn((Score::default(), Team::None));
+        let victim = world.spawn((Score::default(), Team::None));
+        let mut scoreboard = Scoreboard::default();
+
+        scoreboard.kill(&world, &config, Some(killer), victim);
+        let expected = Score {
+            score: 1,
+            kills: 1,
+            deaths: 0,
+        };
+        assert_eq!(score(&world, killer), expected);
+        assert_eq!(score(&world, victim).deaths, 1);
+
+        // suicide, by own bullet or without killer
+        scoreboard.kill(&world, &config, Some(victim), victim);
+        scoreboard.kill(&world, &config, None, victim);
+        let expected = Score {
+            score: -2,
+            kills: 0,
+            deaths: 3,
+        };
+        assert_eq!(score(&world, victim), expected);
+        assert!(scoreboard.teams.is_empty());
+    }
+
+    #[test]
+    fn team_kill() {
+        let mut config = Config::default();
+        config.game.mode = GameMode::TeamDeathmatch;
+        let mut world = World::new();
+        let alpha = world.spawn((Score::default(), Team::Alpha));
+        let teammate = world.spawn((Score::default(), Team::Alpha));
+        let bravo = world.spawn((Score::default(), Team::Bravo));
+        let mut scoreboard = Scoreboard::default();
+
+        scoreboard.kill(&world, &config, Some(alpha), bravo);
+        scoreboard.kill(&world, &config, Some(alpha), bravo);
+        assert_eq!(scoreboard.team_score(Team::Alpha), 2);
+
+        scoreboard.kill(&world, &config, Some(alpha), teammate);
+        let expected = Score {
+            score: 1,
+            kills: 2,
+            deaths: 0,
+        };
+        assert_eq!(score(&world, alpha), expected);
+        assert_eq!(score(&world, teammate).deaths, 1);
+        assert_eq!(scoreboard.team_score(Team::Alpha), 1);
+        assert_eq!(scoreboard.team_score(Team::Bravo), 0);
+
+        // captures count for the team in CTF, kills only for players
+        config.game.mode = GameMode::Ctf;
+        scoreboard.teams.clear();
+        scoreboard.kill(&world, &config, Some(bravo), alpha);
+        assert_eq!(score(&world, bravo).score, 1);
+        assert!(scoreboard.teams.is_empty());
+    }
+}
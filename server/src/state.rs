@@ -2,29 +2,206 @@ use bytes::Bytes;
 use hecs::{Entity, World};
 use std::collections::HashMap;
 
-use crate::systems;
-use soldank_shared::{components, messages::*};
+use crate::{networking::Connection, systems};
+use soldank_shared::{
+    components::{Flag, Position},
+    math::Vec2,
+    messages::*,
+    replication::{EntitiesSnapshot, ReplicationRegistry, SnapshotDelta},
+};
 
-pub fn build_state_message(world: &World, _client_entity: Entity, time: &systems::Time) -> Bytes {
-    // TODO: scope updates to client_entity visibility range
-    // FIXME: send only entities changed since last client acknowledged tick
+/// Update priorities of entities outside of client view radius
+///
+/// Each broadcast an out of view entity accumulates `radius / distance` priority
+/// and is sent once it reaches 1, so far away entities are refreshed less often.
+#[derive(Debug, Default)]
+pub struct InterestScope {
+    priorities: HashMap<Entity, f32>,
+}
+
+impl InterestScope {
+    /// Whether `entity` at `position` should be updated for a client viewing from `origin`
+    pub fn is_relevant(
+        &mut self,
+        entity: Entity,
+        origin: Option<Vec2>,
+        position: Option<Vec2>,
+        radius: f32,
+    ) -> bool {
+        let distance = match (origin, position) {
+            (Some(origin), Some(position)) if radius > 0. => origin.distance(position),
+            _ => return true, // not spatial or scoping disabled
+        };
+        if distance <= radius {
+            self.priorities.remove(&entity);
+            return true;
+        }
+
+        let priority = self.priorities.entry(entity).or_default();
+        *priority += radius / distance;
+        if *priority >= 1. {
+            *priority = 0.;
+            true
+        } else {
+            false
+        }
+    }
 
+    pub fn retain(&mut self, snapshot: &EntitiesSnapshot) {
+        self.priorities
+            .retain(|entity, _| snapshot.contains_key(entity));
+    }
+}
+
+pub fn collect_snapshot(world: &World, registry: &ReplicationRegistry) -> EntitiesSnapshot {
     let mut entities = HashMap::new();
     for entity_ref in world.iter() {
-        let components = entities.entry(entity_ref.entity()).or_insert_with(Vec::new);
-        if let Some(soldier) = entity_ref.get::<components::Soldier>() {
-            components.push(ComponentValue::Soldier((*soldier).clone()));
-        }
-        if let Some(nick) = entity_ref.get::<components::Nick>() {
-            components.push(ComponentValue::Nick((*nick).clone()));
+        let components = registry.collect(&entity_ref);
+        if !components.is_empty() {
+            entities.insert(entity_ref.entity(), components);
         }
-        if let Some(pos) = entity_ref.get::<components::Position>() {
-            components.push(ComponentValue::Pos((*pos).clone()));
+    }
+    entities
+}
+
+/// Own soldier and flags are updated regardless of distance
+fn always_relevant(world: &World, entity: Entity, own: Option<Entity>) -> bool {
+    own == Some(entity) || world.get::<Flag>(entity).is_ok()
+}
+
+fn position_of(world: &World, entity: Entity) -> Option<Vec2> {
+    world
+        .get::<Position>(entity)
+        .ok()
+        .map(|position| **position)
+}
+
+pub fn build_state_message(
+    world: &World,
+    registry: &ReplicationRegistry,
+    time: &systems::Time,
+    connection: &mut Connection,
+    view_radius: f32,
+) -> Vec<Bytes> {
+    let ack_tick = connection.ack_tick;
+    let history = &mut connection.snapshots;
+    let scope = &mut connection.scope;
+
+    let world_state = collect_snapshot(world, registry);
+    history.acknowledge(ack_tick);
+    scope.retain(&world_state);
+
+    let empty = EntitiesSnapshot::new();
+    let (base_tick, base) = match history.get(ack_tick) {
+        Some(base) if ack_tick > 0 => (ack_tick, base),
+        _ => (0, &empty),
+    };
+
+    // state as known by the client - entities out of scope keep their last sent components
+    let origin = connection
+        .entity
+        .and_then(|entity| position_of(world, entity));
+    let mut current = EntitiesSnapshot::new();
+    for (&entity, components) in world_state.iter() {
+        if always_relevant(world, entity, connection.entity)
+            || scope.is_relevant(entity, origin, position_of(world, entity), view_radius)
+        {
+            current.insert(entity, components.clone());
+        } else if let Some(base_components) = base.get(&entity) {
+            current.insert(entity, base_components.clone());
         }
     }
 
-    encode_message(NetworkMessage::GameState {
+    let delta = SnapshotDelta::diff(base, &current);
+    match encode_message_fragmented(NetworkMessage::GameState {
         tick: time.tick,
-        entities,
-    })
+        base_tick,
+        ack_tick: connection.last_processed_tick,
+        entities: delta.entities,
+        removed: delta.removed,
+        despawned: delta.despawned,
+    }) {
+        Ok(fragments) => {
+            history.push(time.tick, current);
+            fragments
+        }
+        Err(error) => {
+            // not kept in history, so next state is a delta against the same base
+            log::error!("Cannot send state {}: {}", time.tick, error);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soldank_shared::{components, math::vec2};
+    use std::time::Instant;
+
+    #[test]
+    fn priorities() {
+        let mut world = World::new();
+        let entity = world.spawn(());
+        let origin = Some(Vec2::ZERO);
+        let mut scope = InterestScope::default();
+
+        // in view radius - every time
+        assert!((0..3).all(|_| scope.is_relevant(entity, origin, Some(vec2(100., 0.)), 100.)));
+
+        // twice the radius away - every other time
+        let far = Some(vec2(200., 0.));
+        let sent: Vec<bool> = (0..4)
+            .map(|_| scope.is_relevant(entity, origin, far, 100.))
+            .collect();
+        assert_eq!(sent, vec![false, true, false, true]);
+
+        // four times the radius away - eventually
+        let sent: Vec<bool> = (0..4)
+            .map(|_| scope.is_relevant(entity, origin, Some(vec2(0., 400.)), 100.))
+            .collect();
+        assert_eq!(sent, vec![false, false, false, true]);
+
+        // coming into view resets accumulated priority
+        assert!(!scope.is_relevant(entity, origin, far, 100.));
+        assert!(scope.is_relevant(entity, origin, Some(vec2(50., 0.)), 100.));
+        assert!(!scope.is_relevant(entity, origin, far, 100.));
+
+        // not spatial or scoping disabled
+        assert!(scope.is_relevant(entity, None, far, 100.));
+        assert!(scope.is_relevant(entity, origin, None, 100.));
+        assert!(scope.is_relevant(entity, origin, far, 0.));
+
+        scope.retain(&EntitiesSnapshot::new());
+        assert!(scope.priorities.is_empty());
+    }
+
+    #[test]
+    fn state_scope() {
+        let mut world = World::new();
+        let registry = components::replication_registry();
+        let own = world.spawn((Position(Vec2::ZERO),));
+        let near = world.spawn((Position(vec2(50., 0.)),));
+        let far = world.spawn((Position(vec2(10000., 0.)),));
+        let flag = world.spawn((Flag, Position(vec2(0., 10000.))));
+
+        let mut connection = Connection::new();
+        connection.entity = Some(own);
+        let time = systems::Time {
+            time: Instant::now(),
+            tick: 1,
+            frame_percent: 1.,
+        };
+
+        let fragments = build_state_message(&world, &registry, &time, &mut connection, 100.);
+        assert_eq!(fragments.len(), 1);
+        let entities = match decode_message(&fragments[0]) {
+            Ok(NetworkMessage::GameState { entities, .. }) => entities,
+            msg => panic!("Unexpected {:?}", msg),
+        };
+        assert!(entities.contains_key(&own));
+        assert!(entities.contains_key(&near));
+        assert!(entities.contains_key(&flag));
+        assert!(!entities.contains_key(&far));
+    }
 }
@@ -1,14 +1,25 @@
-use hecs::World;
+use hecs::{Entity, World};
+use rand::seq::SliceRandom;
 use std::{
     collections::{HashMap, VecDeque},
     net::SocketAddr,
 };
 
 use crate::{
+    cheat::{self, Cheats},
+    constants::*,
+    cvars::Config,
     networking::{Connection, Networking},
-    GameState,
 };
-use soldank_shared::{components, control::Control, messages::NetworkMessage, systems};
+use soldank_shared::{
+    bullet::{Bullet, EmitterItem},
+    components::{self, Team},
+    control::Control,
+    lag_compensation::{rewind_tick, HitboxHistory},
+    mapfile::{MapFile, MapSpawnpoint, SpawnType},
+    messages::{GameState, NetworkMessage},
+    soldier::Soldier,
+};
 pub use soldank_shared::{
     math::Vec2,
     systems::{Time, *},
@@ -16,8 +27,20 @@ pub use soldank_shared::{
 
 pub type ControlBuffer = HashMap<usize, (Control, Vec2)>;
 
+/// Soldier which fired a bullet, and how many ticks behind the server it saw the world
+pub struct BulletOwner {
+    pub shooter: Entity,
+    pub lag: usize,
+}
+
+/// Tick a dead soldier respawns at
+pub struct Respawn(pub usize);
+
 pub fn process_network_messages(
     world: &mut World,
+    config: &Config,
+    time: &Time,
+    state: GameState,
     messages: &mut VecDeque<(SocketAddr, NetworkMessage)>,
     connections: &mut HashMap<SocketAddr, Connection>,
 ) {
@@ -31,12 +54,29 @@ pub fn process_network_messages(
                 begin_tick,
                 control,
             } => {
-                if let Some(conn) = connections.get_mut(&addr) {
-                    conn.ack_tick = ack_tick;
-                } else {
-                    log::error!("Processing message from unknown connection: [{}]", addr);
+                let connection = match connections.get_mut(&addr) {
+                    Some(connection) => connection,
+                    None => {
+                        log::error!("Processing message from unknown connection: [{}]", addr);
+                        continue;
+                    }
+                };
+                match cheat::validate_control_state(time, ack_tick, begin_tick, control.len()) {
+                    Ok(()) => {
+                        connection.ack_tick = ack_tick;
+                        control_updates.insert(addr, (begin_tick, control));
+                    }
+                    Err(violation) => {
+                        log::debug!(
+                            "Dropping invalid control state from [{}]: ticks {}+{} ack {}",
+                            addr,
+                            begin_tick,
+                            control.len(),
+                            ack_tick
+                        );
+                        cheat::report(connection, addr, violation, config);
+                    }
                 }
-                control_updates.insert(addr, (begin_tick, control));
             }
             _ => {
                 unprocessed.push((addr, message));
@@ -44,17 +84,23 @@ pub fn process_network_messages(
         }
     }
 
-    for (_entity, (addr, mut control)) in world.query::<(&SocketAddr, &mut ControlBuffer)>().iter()
-    {
+    for (entity, (addr, mut control)) in world.query::<(&SocketAddr, &mut ControlBuffer)>().iter() {
         if let Some((tick, mut ctrl)) = control_updates.remove(addr) {
-            if let Some(connection) = connections.get(addr) {
+            if let Some(connection) = connections.get_mut(addr) {
+                let disallowed = cheat::disallowed_controls(world, entity, state);
+                let mut violations = Cheats::empty();
                 for (i, (c, v)) in ctrl.drain(..).enumerate() {
-                    // TODO: check constraints and update connection.cheats
-                    let v = v.normalize_or_zero();
                     let t = tick + i;
-                    if t > connection.last_processed_tick {
-                        control.insert(tick + i, (c, v));
+                    if t <= connection.last_processed_tick {
+                        continue;
+                    }
+                    if !cheat::valid_aim(v) {
+                        violations |= Cheats::AIM_RANGE;
                     }
+                    control.insert(t, (c - disallowed, v.normalize_or_zero()));
+                }
+                if !violations.is_empty() {
+                    cheat::report(connection, *addr, violations, config);
                 }
             }
         }
@@ -70,52 +116,314 @@ pub fn process_network_messages(
     messages.extend(unprocessed);
 }
 
-pub fn lobby(world: &mut World, game_state: &mut GameState, networking: &Networking) {
-    if *game_state != GameState::Lobby {
-        log::error!("Running lobby system outside Lobby GameState");
-    }
-
-    let ready = !networking.connections.is_empty()
-        && networking
-            .connections
-            .iter()
-            .all(|(_, conn)| conn.authorized && conn.entity.is_some() && conn.ready);
-
-    if ready {
-        log::info!("All players ready - switching to InGame state");
-        *game_state = GameState::InGame;
-
-        for (&addr, conn) in networking.connections.iter() {
-            let entity = conn.entity.unwrap();
-            world.spawn_at(
-                entity,
-                (
-                    components::Soldier {},
-                    components::Nick(conn.nick.clone()),
-                    addr,
-                    ControlBuffer::default(),
-                    components::Position::new(0., 0.), // FIXME: remove this
-                ),
-            );
+/// Spawns soldiers of players which joined and got ready, returning their addresses
+pub fn spawn_players(
+    world: &mut World,
+    networking: &Networking,
+    map: &MapFile,
+    config: &Config,
+) -> Vec<SocketAddr> {
+    let mut spawned = Vec::new();
+
+    for (&addr, conn) in networking.connections.iter() {
+        let entity = match conn.entity {
+            Some(entity) if conn.authorized && conn.ready => entity,
+            _ => continue,
+        };
+        if world.get::<Soldier>(entity).is_ok() {
+            continue;
         }
+
+        let team = if config.game.mode.has_teams() {
+            balanced_team(world)
+        } else {
+            Team::None
+        };
+        let mut soldier = Soldier::new(spawnpoint(map, team), config.phys.gravity);
+        soldier.team = team;
+        let position = components::Position(soldier.particle.pos);
+        let state = components::SoldierState::from(&soldier);
+        world.spawn_at(
+            entity,
+            (
+                components::Soldier {},
+                components::Nick(conn.nick.clone()),
+                addr,
+                ControlBuffer::default(),
+                soldier,
+                position,
+                team,
+                components::Score::default(),
+                state,
+            ),
+        );
+        log::info!("{:?} joined {:?}", conn.nick, team);
+        spawned.push(addr);
+    }
+
+    spawned
+}
+
+/// Team with fewer players, for a soldier joining the match
+pub fn balanced_team(world: &World) -> Team {
+    let mut alpha = 0;
+    let mut bravo = 0;
+    for (_entity, &team) in world.query::<&Team>().with::<components::Soldier>().iter() {
+        match team {
+            Team::Alpha => alpha += 1,
+            Team::Bravo => bravo += 1,
+            _ => {}
+        }
+    }
+
+    if bravo < alpha {
+        Team::Bravo
+    } else {
+        Team::Alpha
+    }
+}
+
+/// Random spawnpoint of `team`, falling back to general ones when map has none
+pub fn spawnpoint(map: &MapFile, team: Team) -> &MapSpawnpoint {
+    let spawn_type = match team {
+        Team::None => SpawnType::General,
+        Team::Alpha => SpawnType::Alpha,
+        Team::Bravo => SpawnType::Bravo,
+        Team::Charlie => SpawnType::Charlie,
+        Team::Delta => SpawnType::Delta,
+    };
+
+    let mut spawns: Vec<&MapSpawnpoint> = map.spawnpoints_of(spawn_type).collect();
+    if spawns.is_empty() {
+        spawns = map.spawnpoints_of(SpawnType::General).collect();
     }
+    spawns
+        .choose(&mut rand::thread_rng())
+        .copied()
+        .unwrap_or(&map.spawnpoints[0])
 }
 
-pub fn apply_input(world: &World, time: &Time) {
+/// Applies all queued inputs, in tick order, which were not applied yet,
+/// stepping soldier simulation once per input
+///
+/// Inputs arriving late are replayed on top of current state, so the client
+/// always gets every input applied exactly once and can reconcile its prediction
+/// against `Connection::last_processed_tick` sent back in `GameState`.
+pub fn apply_input(
+    world: &mut World,
+    map: &MapFile,
+    config: &Config,
+    time: &Time,
+    connections: &mut HashMap<SocketAddr, Connection>,
+    frozen: bool,
+) {
     let tick = time.tick;
+    let mut bullets = Vec::new();
 
-    for (entity, buffer) in world.query::<&mut ControlBuffer>().iter() {
-        // FIXME: apply all queued inputs in rollback manner
-        let max_tick = buffer.keys().max().unwrap();
-        if let Some((control, _)) = buffer.get(max_tick) {
-            systems::apply_input(world.entity(entity).unwrap(), *control);
-        } else {
+    for (entity, (addr, buffer, soldier, position)) in world
+        .query::<(
+            &SocketAddr,
+            &mut ControlBuffer,
+            &mut Soldier,
+            &mut components::Position,
+        )>()
+        .iter()
+    {
+        let connection = match connections.get_mut(addr) {
+            Some(connection) => connection,
+            None => continue,
+        };
+
+        let mut pending: Vec<usize> = buffer
+            .keys()
+            .copied()
+            .filter(|&t| t > connection.last_processed_tick && t <= tick)
+            .collect();
+        if pending.is_empty() {
             log::warn!(
                 "Missed input for tick {}({}) on entity {:?}",
                 tick,
-                max_tick,
+                connection.last_processed_tick,
                 entity
             );
+            continue;
+        }
+        pending.sort_unstable();
+
+        // bullets are hit tested against the world this client saw when firing
+        let lag = tick
+            - rewind_tick(
+                tick,
+                connection.stats.rtt / 2.,
+                config.net.interpolation_delay,
+                config.net.lag_compensation_latency,
+            );
+        let mut emitter = Vec::new();
+        for t in pending {
+            let (control, aim) = buffer.remove(&t).unwrap();
+            // dead soldiers only fall, waiting for respawn
+            let control = if frozen || soldier.dead_meat {
+                Control::empty()
+            } else {
+                control
+            };
+            soldier.step(control, aim, map, &mut emitter, config.phys.gravity);
+            connection.last_processed_tick = t;
+        }
+        buffer.retain(|&t, _| t > connection.last_processed_tick);
+        **position = soldier.particle.pos;
+
+        for item in emitter.drain(..) {
+            match item {
+                EmitterItem::Bullet(params) => bullets.push((
+                    Bullet::new(&params, config.phys.gravity),
+                    BulletOwner {
+                        shooter: entity,
+                        lag,
+                    },
+                )),
+            }
         }
     }
+
+    world.spawn_batch(bullets);
+}
+
+/// Advances all bullets by one simulation step, despawning the ones which hit
+/// the map, a soldier or timed out. Returns `(killer, victim)` of soldiers killed.
+pub fn update_bullets(
+    world: &mut World,
+    map: &MapFile,
+    config: &Config,
+    hitboxes: &HitboxHistory,
+    tick: usize,
+) -> Vec<(Entity, Entity)> {
+    let mut inactive = Vec::new();
+    let mut hits = Vec::new();
+
+    for (entity, (bullet, owner)) in world.query::<(&mut Bullet, Option<&BulletOwner>)>().iter() {
+        bullet.update(map);
+
+        if let Some(owner) = owner {
+            let from = bullet.particle.old_pos;
+            let to = bullet.particle.pos;
+            if let Some(hit) =
+                hitboxes.hit_test(tick.saturating_sub(owner.lag), from, to, owner.shooter)
+            {
+                bullet.active = false;
+                hits.push((owner.shooter, hit, bullet.team, bullet.damage(hit.region)));
+            }
+        }
+
+        if !bullet.active {
+            inactive.push(entity);
+        }
+    }
+
+    for entity in inactive {
+        let _ = world.despawn(entity);
+    }
+
+    let mut kills = Vec::new();
+    for (shooter, hit, team, damage) in hits {
+        let mut soldier = match world.get_mut::<Soldier>(hit.entity) {
+            Ok(soldier) => soldier,
+            Err(_) => continue,
+        };
+        let teammate = config.game.mode.has_teams() && soldier.team == team;
+        if teammate && !config.game.friendly_fire {
+            continue;
+        }
+        if soldier.hit(damage) {
+            kills.push((shooter, hit.entity));
+        }
+    }
+
+    kills
+}
+
+/// Copies soldier health, vest and weapons to replicated `SoldierState`
+pub fn update_soldier_states(world: &mut World) {
+    for (_entity, (soldier, state)) in world
+        .query::<(&Soldier, &mut components::SoldierState)>()
+        .iter()
+    {
+        *state = components::SoldierState::from(soldier);
+    }
+}
+
+pub fn despawn_bullets(world: &mut World) {
+    let bullets: Vec<Entity> = world
+        .query::<&Bullet>()
+        .iter()
+        .map(|(entity, _bullet)| entity)
+        .collect();
+    for entity in bullets {
+        let _ = world.despawn(entity);
+    }
+}
+
+/// Respawns dead soldiers at their team spawnpoints after `respawn_time`
+pub fn respawn_soldiers(world: &mut World, map: &MapFile, config: &Config, tick: usize) {
+    let respawn_ticks = (config.game.respawn_time as f64 / 1000. / TIMESTEP_RATE) as usize;
+    let mut dead = Vec::new();
+    let mut respawned = Vec::new();
+
+    for (entity, (soldier, position, respawn)) in world
+        .query::<(&mut Soldier, &mut components::Position, Option<&Respawn>)>()
+        .iter()
+    {
+        match respawn {
+            None if soldier.dead_meat => dead.push(entity),
+            Some(&Respawn(at)) if tick >= at => {
+                respawn(soldier, position, map, config);
+                respawned.push(entity);
+            }
+            _ => {}
+        }
+    }
+
+    for entity in dead {
+        let _ = world.insert_one(entity, Respawn(tick + respawn_ticks));
+    }
+    for entity in respawned {
+        let _ = world.remove_one::<Respawn>(entity);
+    }
+}
+
+/// Respawns all soldiers at once, dead or alive, for a new match
+pub fn reset_soldiers(world: &mut World, map: &MapFile, config: &Config) {
+    let mut respawned = Vec::new();
+
+    for (entity, (soldier, position)) in world
+        .query::<(&mut Soldier, &mut components::Position)>()
+        .iter()
+    {
+        respawn(soldier, position, map, config);
+        respawned.push(entity);
+    }
+
+    for entity in respawned {
+        let _ = world.remove_one::<Respawn>(entity);
+    }
+}
+
+fn respawn(
+    soldier: &mut Soldier,
+    position: &mut components::Position,
+    map: &MapFile,
+    config: &Config,
+) {
+    let team = soldier.team;
+    *soldier = Soldier::new(spawnpoint(map, team), config.phys.gravity);
+    soldier.team = team;
+    **position = soldier.particle.pos;
+}
+
+/// Player nick, for logging
+pub fn nick(world: &World, entity: Entity) -> String {
+    world
+        .get::<components::Nick>(entity)
+        .map(|nick| nick.0.clone())
+        .unwrap_or_default()
 }
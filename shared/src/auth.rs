@@ -0,0 +1,65 @@
+use enum_primitive_derive::Primitive;
+use hmac_sha256::HMAC;
+use std::fmt;
+
+pub const NONCE_SIZE: usize = 16;
+pub const MAC_SIZE: usize = 32;
+
+/// Random challenge sent by server in `CCREP_ACCEPT`
+pub type Nonce = [u8; NONCE_SIZE];
+/// Client response to the challenge, sent in `CCREQ_AUTHORIZE`
+pub type AuthMac = [u8; MAC_SIZE];
+
+/// Proves knowledge of connection `key` without sending it - HMAC-SHA256 over nonce and nick
+pub fn auth_mac(key: &str, nonce: &Nonce, nick: &str) -> AuthMac {
+    let mut hmac = HMAC::new(key.as_bytes());
+    hmac.update(nonce);
+    hmac.update(nick.as_bytes());
+    hmac.finalize()
+}
+
+pub fn auth_verify(key: &str, nonce: &Nonce, nick: &str, mac: &AuthMac) -> bool {
+    let expected = auth_mac(key, nonce, nick);
+    // constant time comparison - do not leak matching prefix length
+    expected
+        .iter()
+        .zip(mac.iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// Reason code sent in `CCREP_REJECT`
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Primitive)]
+pub enum RejectReason {
+    BadKey = 0x01,
+    ServerFull = 0x02,
+    VersionMismatch = 0x03,
+    Banned = 0x04,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::BadKey => write!(f, "invalid connection key"),
+            RejectReason::ServerFull => write!(f, "server is full"),
+            RejectReason::VersionMismatch => write!(f, "incompatible protocol version"),
+            RejectReason::Banned => write!(f, "you are banned from this server"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn challenge() {
+        let nonce = [7; NONCE_SIZE];
+        let mac = auth_mac("1337", &nonce, "Player");
+        assert!(auth_verify("1337", &nonce, "Player", &mac));
+        assert!(!auth_verify("1338", &nonce, "Player", &mac));
+        assert!(!auth_verify("1337", &[8; NONCE_SIZE], "Player", &mac));
+        assert!(!auth_verify("1337", &nonce, "Player2", &mac));
+    }
+}
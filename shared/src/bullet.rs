@@ -1,4 +1,11 @@
-use super::*;
+use crate::{
+    calc::lerp,
+    components::Team,
+    mapfile::MapFile,
+    math::{vec2, Vec2},
+    particles::Particle,
+    weapons::{HitRegion, Weapon, WeaponKind},
+};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum BulletStyle {
@@ -27,7 +34,6 @@ pub struct BulletParams {
     pub timeout: i16,
     pub hit_multiply: f32,
     pub team: Team,
-    pub sprite: Option<gfx::Weapon>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -45,7 +51,11 @@ pub struct Bullet {
     pub hit_multiply: f32,
     pub hit_multiply_prev: f32,
     pub degrade_count: usize,
-    pub sprite: Option<gfx::Weapon>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum EmitterItem {
+    Bullet(BulletParams),
 }
 
 impl Default for BulletStyle {
@@ -55,7 +65,7 @@ impl Default for BulletStyle {
 }
 
 impl Bullet {
-    pub fn new(params: &BulletParams, config: &Config) -> Bullet {
+    pub fn new(params: &BulletParams, gravity: f32) -> Bullet {
         let particle = Particle {
             active: true,
             pos: params.position,
@@ -63,7 +73,7 @@ impl Bullet {
             velocity: params.velocity,
             one_over_mass: 1.0,
             timestep: 1.0,
-            gravity: config.phys.gravity * 2.25,
+            gravity: gravity * 2.25,
             e_damping: 0.99,
             ..Default::default()
         };
@@ -82,13 +92,10 @@ impl Bullet {
             hit_multiply: params.hit_multiply,
             hit_multiply_prev: params.hit_multiply,
             degrade_count: 0,
-            sprite: params.sprite,
         }
     }
 
-    pub fn update(&mut self, resources: &Resources) {
-        let map = &*resources.get::<MapFile>().unwrap();
-
+    pub fn update(&mut self, map: &MapFile) {
         self.velocity_prev = self.particle.velocity;
         self.particle.euler();
 
@@ -129,6 +136,12 @@ impl Bullet {
         }
     }
 
+    /// Damage dealt by hitting soldier body `region`
+    pub fn damage(&self, region: HitRegion) -> f32 {
+        let modifier = Weapon::new(self.weapon, false).modifier(region);
+        self.particle.velocity.length() * self.hit_multiply * modifier
+    }
+
     pub fn map_collision(&self, map: &MapFile) -> Option<(Vec2, usize)> {
         let a = self.particle.old_pos;
         let b = self.particle.pos;
@@ -1,14 +1,75 @@
-use crate::math::{vec2, Vec2};
+use crate::{
+    math::{vec2, Vec2},
+    pickup::PickupKind,
+    replication::ReplicationRegistry,
+    soldier::Soldier as SoldierSimulation,
+    weapons::WeaponKind,
+};
 use derive_deref::{Deref, DerefMut};
 use nanoserde::{DeBin, DeBinErr, SerBin};
 
-#[derive(Debug, Clone, DeBin, SerBin, Deref, DerefMut)]
+#[derive(Debug, Clone, PartialEq, SerBin, Deref, DerefMut)]
 pub struct Nick(pub String);
 
-#[derive(Debug, Clone, DeBin, SerBin)]
+impl DeBin for Nick {
+    fn de_bin(offset: &mut usize, bytes: &[u8]) -> Result<Self, DeBinErr> {
+        // nanoserde's String does not guard `offset + len` overflow
+        let len = u64::de_bin(offset, bytes)? as usize;
+        let err = DeBinErr {
+            o: *offset,
+            l: len,
+            s: bytes.len(),
+        };
+        match offset.checked_add(len) {
+            Some(end) if end <= bytes.len() => {
+                let nick = String::from_utf8(bytes[*offset..end].to_vec())
+                    .map_err(|_| DeBinErr { l: 0, ..err })?;
+                *offset = end;
+                Ok(Nick(nick))
+            }
+            _ => Err(err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, DeBin, SerBin)]
 pub struct Soldier;
 
-#[derive(Default, Debug, Copy, Clone, Deref, DerefMut)]
+#[derive(Debug, Clone, PartialEq, DeBin, SerBin)]
+pub struct Flag;
+
+/// Player scoreboard entry
+#[derive(Default, Debug, Copy, Clone, PartialEq, DeBin, SerBin)]
+pub struct Score {
+    pub score: i32,
+    pub kills: u32,
+    pub deaths: u32,
+}
+
+/// Soldier condition and weapons, as seen by other players
+#[derive(Default, Debug, Copy, Clone, PartialEq, DeBin, SerBin)]
+pub struct SoldierState {
+    pub health: u8,
+    pub vest: u8,
+    pub active_weapon: WeaponKind,
+    pub secondary_weapon: WeaponKind,
+    /// 255 is opaque, lower while predator bonus lasts
+    pub alpha: u8,
+}
+
+impl From<&SoldierSimulation> for SoldierState {
+    fn from(soldier: &SoldierSimulation) -> Self {
+        SoldierState {
+            health: soldier.health.round() as u8,
+            vest: soldier.vest.round() as u8,
+            active_weapon: soldier.primary_weapon().kind,
+            secondary_weapon: soldier.secondary_weapon().kind,
+            alpha: soldier.alpha,
+        }
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Deref, DerefMut)]
 pub struct Position(pub Vec2);
 
 impl Position {
@@ -33,3 +94,55 @@ impl DeBin for Position {
         Ok(Position::new(val.0, val.1))
     }
 }
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Team {
+    None,
+    Alpha,
+    Bravo,
+    Charlie,
+    Delta,
+}
+
+impl Default for Team {
+    fn default() -> Team {
+        Team::None
+    }
+}
+
+impl SerBin for Team {
+    fn ser_bin(&self, output: &mut Vec<u8>) {
+        (*self as u8).ser_bin(output);
+    }
+}
+
+impl DeBin for Team {
+    fn de_bin(offset: &mut usize, bytes: &[u8]) -> Result<Self, DeBinErr> {
+        let start = *offset;
+        match u8::de_bin(offset, bytes)? {
+            0 => Ok(Team::None),
+            1 => Ok(Team::Alpha),
+            2 => Ok(Team::Bravo),
+            3 => Ok(Team::Charlie),
+            4 => Ok(Team::Delta),
+            _ => Err(DeBinErr {
+                o: start,
+                l: 1,
+                s: bytes.len(),
+            }),
+        }
+    }
+}
+
+/// Components replicated from server to clients. Ids are part of the wire protocol - do not reuse.
+pub fn replication_registry() -> ReplicationRegistry {
+    ReplicationRegistry::default()
+        .register::<Soldier>(1)
+        .register::<Nick>(2)
+        .register::<Position>(3)
+        .register::<Team>(4)
+        .register::<Flag>(5)
+        .register::<Score>(6)
+        .register::<PickupKind>(7)
+        .register::<SoldierState>(8)
+}
@@ -1,6 +1,25 @@
 pub const TIMESTEP_RATE: f64 = 1.0 / 60.0; // fixed frame rate
 
 pub const SERVER_PORT: u16 = 12351;
+pub const MASTER_PORT: u16 = 12350;
+pub const DEFAULT_MASTER: &str = ""; // master server address, empty disables
+pub const MASTER_HEARTBEAT: u32 = 30000; // millis between server registrations
+
+pub const MAX_PACKET_SIZE: usize = 1200; // keep datagrams below common path MTU
+
+pub const SEND_KEEPALIVE: u32 = 1000; // millis, 0 disables
+pub const KEEPALIVE_TIMEOUT: u32 = 10000; // millis, 0 disables
+pub const PING_INTERVAL: u32 = 1000; // millis, 0 disables
+
+pub const INTERPOLATION_DELAY: u32 = 400; // millis, should cover server broadcast period
+pub const EXTRAPOLATION_LIMIT: u32 = 250; // millis
+
+pub const LAG_COMPENSATION_LATENCY: u32 = 300; // millis, max latency rewound on top of interpolation delay, 0 disables
+
+pub const SOLDIER_HITBOX_RADIUS: f32 = 10.;
+pub const AIM_DISTANCE: f32 = 100.; // distance of soldier aim point along network aim vector
+
+pub const VIEW_RADIUS: f32 = 1000.; // entities further away are updated less often, 0 disables
 
 pub const DEFAULT_MAP: &str = "ctf_Ash";
 
@@ -1,6 +1,8 @@
 use bitflags::bitflags;
 use nanoserde::{DeBin, DeBinErr, SerBin};
 
+use crate::messages::GameState;
+
 bitflags! {
     #[derive(Default)]
     pub struct Control: u16 {
@@ -20,6 +22,23 @@ bitflags! {
     }
 }
 
+impl Control {
+    /// Controls soldier cannot use when `dead` or in match `state` - frozen soldiers
+    /// (during countdown and intermission) and dead ones cannot jump nor jet,
+    /// and nobody fires until the match starts.
+    pub fn disallowed(dead: bool, state: GameState) -> Control {
+        let frozen = matches!(state, GameState::Countdown | GameState::Intermission);
+        let mut disallowed = Control::empty();
+        if dead || frozen {
+            disallowed |= Control::UP | Control::JETS;
+        }
+        if frozen || state == GameState::Warmup {
+            disallowed |= Control::FIRE;
+        }
+        disallowed
+    }
+}
+
 impl SerBin for Control {
     fn ser_bin(&self, output: &mut Vec<u8>) {
         let val = self.bits();
@@ -40,3 +59,28 @@ impl DeBin for Control {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn disallowed() {
+        assert_eq!(
+            Control::disallowed(false, GameState::InGame),
+            Control::empty()
+        );
+        assert_eq!(
+            Control::disallowed(true, GameState::InGame),
+            Control::UP | Control::JETS
+        );
+        assert_eq!(Control::disallowed(false, GameState::Warmup), Control::FIRE);
+        for &state in &[GameState::Countdown, GameState::Intermission] {
+            assert_eq!(
+                Control::disallowed(false, state),
+                Control::UP | Control::JETS | Control::FIRE
+            );
+        }
+        assert!(!Control::disallowed(true, GameState::Intermission).contains(Control::LEFT));
+    }
+}
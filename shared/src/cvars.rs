@@ -1,4 +1,4 @@
-use crate::constants::*;
+use crate::{constants::*, networking::SimConditions};
 use cvar::{INode, IVisit};
 use std::sync::{Arc, RwLock};
 
@@ -55,10 +55,32 @@ impl IVisit for Physics {
     }
 }
 
-#[derive(Default)]
 pub struct NetConfig {
-    pub send_keepalive: u32,    // millis
-    pub keepalive_timeout: u32, // millis
+    pub send_keepalive: u32,      // millis
+    pub keepalive_timeout: u32,   // millis
+    pub ping_interval: u32,       // millis
+    pub interpolation_delay: u32, // millis
+    pub extrapolation_limit: u32, // millis
+    pub view_radius: f32,
+    pub lag_compensation_latency: u32, // millis
+    pub master: String,
+    pub sim: Arc<RwLock<SimConditions>>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            send_keepalive: SEND_KEEPALIVE,
+            keepalive_timeout: KEEPALIVE_TIMEOUT,
+            ping_interval: PING_INTERVAL,
+            interpolation_delay: INTERPOLATION_DELAY,
+            extrapolation_limit: EXTRAPOLATION_LIMIT,
+            view_radius: VIEW_RADIUS,
+            lag_compensation_latency: LAG_COMPENSATION_LATENCY,
+            master: DEFAULT_MASTER.to_string(),
+            sim: Default::default(),
+        }
+    }
 }
 
 impl IVisit for NetConfig {
@@ -66,17 +88,63 @@ impl IVisit for NetConfig {
         f(&mut cvar::Property(
             "send_keepalive",
             &mut self.send_keepalive,
-            0,
+            SEND_KEEPALIVE,
         ));
         f(&mut cvar::Property(
             "keepalive_timeout",
             &mut self.keepalive_timeout,
-            0,
+            KEEPALIVE_TIMEOUT,
+        ));
+        f(&mut cvar::Property(
+            "ping_interval",
+            &mut self.ping_interval,
+            PING_INTERVAL,
+        ));
+        f(&mut cvar::Property(
+            "interpolation_delay",
+            &mut self.interpolation_delay,
+            INTERPOLATION_DELAY,
+        ));
+        f(&mut cvar::Property(
+            "extrapolation_limit",
+            &mut self.extrapolation_limit,
+            EXTRAPOLATION_LIMIT,
         ));
+        f(&mut cvar::Property(
+            "view_radius",
+            &mut self.view_radius,
+            VIEW_RADIUS,
+        ));
+        f(&mut cvar::Property(
+            "lag_compensation_latency",
+            &mut self.lag_compensation_latency,
+            LAG_COMPENSATION_LATENCY,
+        ));
+        f(&mut cvar::Property(
+            "master",
+            &mut self.master,
+            DEFAULT_MASTER.to_string(),
+        ));
+        self.sim.write().unwrap().visit(f);
         // self.orb.write().unwrap().visit(f);
     }
 }
 
+impl IVisit for SimConditions {
+    fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
+        f(&mut cvar::Property("sim_latency", &mut self.latency, 0));
+        f(&mut cvar::Property("sim_jitter", &mut self.jitter, 0));
+        f(&mut cvar::Property("sim_loss", &mut self.loss, 0.));
+        f(&mut cvar::Property(
+            "sim_duplicate",
+            &mut self.duplicate,
+            0.,
+        ));
+        f(&mut cvar::Property("sim_reorder", &mut self.reorder, 0.));
+        f(&mut cvar::Property("sim_seed", &mut self.seed, 0));
+    }
+}
+
 // impl IVisit for OrbConfig {
 //     fn visit(&mut self, f: &mut dyn FnMut(&mut dyn INode)) {
 //         let default = Self::default();
@@ -0,0 +1,368 @@
+use crate::{
+    components::Team,
+    mapfile::MapFile,
+    math::{vec2, Vec2},
+    particles::Particle,
+};
+use hecs::Entity;
+
+const TOUCH_RADIUS: f32 = 15.0;
+const THROW_SPEED: f32 = 5.0;
+const THROW_PICKUP_DELAY: usize = 60; // ticks thrower cannot pick the flag up again
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlagStatus {
+    Base,
+    Carried(Entity),
+    Dropped,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlagEvent {
+    Taken {
+        team: Team,
+        by: Entity,
+    },
+    Dropped {
+        team: Team,
+        by: Entity,
+    },
+    /// flag returned to base, by a soldier of its team or after lying dropped too long
+    Returned {
+        team: Team,
+        by: Option<Entity>,
+    },
+    /// flag of `team` brought to the base of soldier `by`
+    Captured {
+        team: Team,
+        by: Entity,
+    },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Flag {
+    pub team: Team,
+    pub base: Vec2,
+    pub status: FlagStatus,
+    pub particle: Particle,
+    /// ticks since the flag was dropped
+    pub dropped_for: usize,
+    /// soldier which threw the flag and tick it may pick it up again
+    thrown_by: Option<(Entity, usize)>,
+}
+
+impl Flag {
+    pub fn new(team: Team, base: Vec2, gravity: f32) -> Flag {
+        let particle = Particle {
+            active: true,
+            pos: base,
+            old_pos: base,
+            one_over_mass: 1.0,
+            timestep: 1.0,
+            gravity,
+            e_damping: 0.99,
+            ..Default::default()
+        };
+
+        Flag {
+            team,
+            base,
+            status: FlagStatus::Base,
+            particle,
+            dropped_for: 0,
+            thrown_by: None,
+        }
+    }
+
+    pub fn pos(&self) -> Vec2 {
+        self.particle.pos
+    }
+
+    pub fn carrier(&self) -> Option<Entity> {
+        match self.status {
+            FlagStatus::Carried(entity) => Some(entity),
+            _ => None,
+        }
+    }
+
+    pub fn touches(&self, pos: Vec2) -> bool {
+        self.pos().distance(pos) <= TOUCH_RADIUS
+    }
+
+    /// Moves the flag back to its base
+    pub fn reset(&mut self) {
+        self.status = FlagStatus::Base;
+        self.particle.pos = self.base;
+        self.particle.old_pos = self.base;
+        self.particle.velocity = Vec2::ZERO;
+        self.dropped_for = 0;
+        self.thrown_by = None;
+    }
+
+    /// Follows the carrying soldier
+    pub fn carry(&mut self, pos: Vec2) {
+        self.particle.pos = pos;
+        self.particle.old_pos = pos;
+    }
+
+    /// Drops carried flag, giving it `velocity`
+    pub fn drop(&mut self, velocity: Vec2) -> Option<FlagEvent> {
+        let by = self.carrier()?;
+        self.status = FlagStatus::Dropped;
+        self.particle.velocity = velocity;
+        self.dropped_for = 0;
+        Some(FlagEvent::Dropped {
+            team: self.team,
+            by,
+        })
+    }
+
+    /// Throws carried flag towards `aim` point, so the carrier cannot grab it right back
+    pub fn throw(&mut self, aim: Vec2, velocity: Vec2, tick: usize) -> Option<FlagEvent> {
+        let by = self.carrier()?;
+        let dir = (aim - self.pos()).normalize_or_zero();
+        let event = self.drop(dir * THROW_SPEED + velocity);
+        self.thrown_by = Some((by, tick + THROW_PICKUP_DELAY));
+        event
+    }
+
+    /// Advances dropped flag by one simulation step, returning it to base
+    /// when it lies dropped for `return_ticks` or falls out of the map
+    pub fn update(&mut self, map: &MapFile, return_ticks: usize) -> Option<FlagEvent> {
+        if self.status != FlagStatus::Dropped {
+            return None;
+        }
+
+        self.particle.euler();
+        if self.map_collision(map) {
+            self.particle.pos = self.particle.old_pos;
+            self.particle.velocity = Vec2::ZERO;
+        }
+        self.dropped_for += 1;
+
+        let (x, y) = self.pos().into();
+        let outside =
+            f32::max(x.abs(), y.abs()) > (map.sectors_num * map.sectors_division - 10) as f32;
+
+        if outside || (return_ticks > 0 && self.dropped_for >= return_ticks) {
+            self.reset();
+            return Some(FlagEvent::Returned {
+                team: self.team,
+                by: None,
+            });
+        }
+
+        None
+    }
+
+    fn map_collision(&self, map: &MapFile) -> bool {
+        let (x, y) = self.pos().into();
+
+        map.sector_polys(vec2(x, y))
+            .iter()
+            .map(|p| (*p - 1) as usize)
+            .any(|p| map.polygons[p].flag_collides() && map.point_in_poly_edges(x, y, p as i32))
+    }
+
+    fn can_take(&self, soldier: Entity, tick: usize) -> bool {
+        match self.thrown_by {
+            Some((thrower, until)) => thrower != soldier || tick >= until,
+            None => true,
+        }
+    }
+}
+
+/// Applies Capture the Flag rules to soldier `soldier` of `team` standing at `pos`
+///
+/// Soldier touching enemy flag takes it, touching own dropped flag returns it to base
+/// and touching own flag at base while carrying enemy flag captures it.
+pub fn touch_flags(
+    flags: &mut [&mut Flag],
+    soldier: Entity,
+    team: Team,
+    pos: Vec2,
+    tick: usize,
+) -> Vec<FlagEvent> {
+    let mut events = Vec::new();
+
+    if team == Team::None {
+        return events;
+    }
+
+    let mut carrying = flags.iter().any(|flag| flag.carrier() == Some(soldier));
+    for flag in flags.iter_mut() {
+        if !flag.touches(pos) {
+            continue;
+        }
+        if flag.team == team {
+            if flag.status == FlagStatus::Dropped {
+                flag.reset();
+                events.push(FlagEvent::Returned {
+                    team,
+                    by: Some(soldier),
+                });
+            }
+        } else if !carrying && flag.carrier().is_none() && flag.can_take(soldier, tick) {
+            carrying = true;
+            flag.status = FlagStatus::Carried(soldier);
+            flag.thrown_by = None;
+            events.push(FlagEvent::Taken {
+                team: flag.team,
+                by: soldier,
+            });
+        }
+    }
+
+    let at_base = flags
+        .iter()
+        .any(|flag| flag.team == team && flag.status == FlagStatus::Base && flag.touches(pos));
+    if at_base {
+        for flag in flags.iter_mut() {
+            if flag.carrier() == Some(soldier) {
+                flag.reset();
+                events.push(FlagEvent::Captured {
+                    team: flag.team,
+                    by: soldier,
+                });
+            }
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn flags() -> (Flag, Flag) {
+        (
+            Flag::new(Team::Alpha, vec2(0., 0.), 0.06),
+            Flag::new(Team::Bravo, vec2(1000., 0.), 0.06),
+        )
+    }
+
+    #[test]
+    fn capture() {
+        let mut world = hecs::World::new();
+        let alpha_soldier = world.spawn(());
+        let (mut alpha, mut bravo) = flags();
+
+        let events = touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            alpha_soldier,
+            Team::Alpha,
+            vec2(1000., 5.),
+            1,
+        );
+        assert_eq!(
+            events,
+            vec![FlagEvent::Taken {
+                team: Team::Bravo,
+                by: alpha_soldier
+            }]
+        );
+        assert_eq!(bravo.carrier(), Some(alpha_soldier));
+
+        // own flag is not at base - no capture
+        alpha.status = FlagStatus::Dropped;
+        alpha.particle.pos = vec2(500., 0.);
+        bravo.carry(vec2(0., 0.));
+        let events = touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            alpha_soldier,
+            Team::Alpha,
+            vec2(0., 0.),
+            2,
+        );
+        assert!(events.is_empty());
+
+        let events = touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            alpha_soldier,
+            Team::Alpha,
+            vec2(500., 0.),
+            3,
+        );
+        assert_eq!(
+            events,
+            vec![FlagEvent::Returned {
+                team: Team::Alpha,
+                by: Some(alpha_soldier)
+            }]
+        );
+        assert_eq!(alpha.status, FlagStatus::Base);
+
+        let events = touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            alpha_soldier,
+            Team::Alpha,
+            vec2(0., 0.),
+            4,
+        );
+        assert_eq!(
+            events,
+            vec![FlagEvent::Captured {
+                team: Team::Bravo,
+                by: alpha_soldier
+            }]
+        );
+        assert_eq!(bravo.status, FlagStatus::Base);
+        assert_eq!(bravo.pos(), vec2(1000., 0.));
+    }
+
+    #[test]
+    fn throw() {
+        let mut world = hecs::World::new();
+        let bravo_soldier = world.spawn(());
+        let other_soldier = world.spawn(());
+        let (mut alpha, mut bravo) = flags();
+
+        // soldiers cannot take own flag
+        let events = touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            bravo_soldier,
+            Team::Bravo,
+            vec2(1000., 0.),
+            1,
+        );
+        assert!(events.is_empty());
+
+        touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            bravo_soldier,
+            Team::Bravo,
+            vec2(0., 0.),
+            1,
+        );
+        assert_eq!(alpha.carrier(), Some(bravo_soldier));
+        assert_eq!(
+            alpha.throw(vec2(100., 0.), Vec2::ZERO, 10),
+            Some(FlagEvent::Dropped {
+                team: Team::Alpha,
+                by: bravo_soldier
+            })
+        );
+        assert_eq!(alpha.status, FlagStatus::Dropped);
+        assert_eq!(alpha.particle.velocity, vec2(THROW_SPEED, 0.));
+
+        let events = touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            bravo_soldier,
+            Team::Bravo,
+            vec2(0., 0.),
+            11,
+        );
+        assert!(events.is_empty());
+
+        let events = touch_flags(
+            &mut [&mut alpha, &mut bravo],
+            other_soldier,
+            Team::Bravo,
+            vec2(0., 0.),
+            11,
+        );
+        assert_eq!(alpha.carrier(), Some(other_soldier));
+        assert_eq!(events.len(), 1);
+    }
+}
@@ -0,0 +1,246 @@
+use hecs::{Entity, World as HecsWorld};
+use std::collections::VecDeque;
+
+use crate::{
+    components::{Position, Soldier},
+    constants::*,
+    math::Vec2,
+    soldier::Soldier as SoldierSimulation,
+    weapons::HitRegion,
+    world::{CloneRegistry, World},
+};
+
+const MAX_HITBOX_HISTORY: usize = 64; // ticks
+
+/// Collision circle of a soldier body
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hitbox {
+    pub radius: f32,
+}
+
+impl Hitbox {
+    /// Body region at `offset` from hitbox center - upper third is head, lower third legs
+    pub fn region(&self, offset: Vec2) -> HitRegion {
+        if offset.y < -self.radius / 3. {
+            HitRegion::Head
+        } else if offset.y > self.radius / 3. {
+            HitRegion::Legs
+        } else {
+            HitRegion::Chest
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hit {
+    pub entity: Entity,
+    pub point: Vec2,
+    pub region: HitRegion,
+}
+
+/// Soldier hitboxes as they were in past ticks, so hits can be tested
+/// against the world the shooter saw instead of the current one
+pub struct HitboxHistory {
+    current: World,
+    history: VecDeque<(usize, World)>,
+}
+
+impl Default for HitboxHistory {
+    fn default() -> Self {
+        Self {
+            current: World::new(
+                CloneRegistry::default()
+                    .register::<Position>()
+                    .register::<Hitbox>(),
+            ),
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl HitboxHistory {
+    /// Stores hitboxes of all living soldiers in `world` as of `tick`, corpses do not stop bullets
+    pub fn record(&mut self, world: &HecsWorld, tick: usize) {
+        let alive = |entity: Entity| {
+            world.get::<Soldier>(entity).is_ok()
+                && world
+                    .get::<SoldierSimulation>(entity)
+                    .map_or(true, |soldier| !soldier.dead_meat)
+        };
+
+        let stale: Vec<Entity> = self
+            .current
+            .iter()
+            .map(|entity_ref| entity_ref.entity())
+            .filter(|&entity| !alive(entity))
+            .collect();
+        for entity in stale {
+            let _ = self.current.despawn(entity);
+        }
+
+        for (entity, position) in world.query::<&Position>().with::<Soldier>().iter() {
+            if !alive(entity) {
+                continue;
+            }
+            match self.current.get_mut::<Position>(entity) {
+                Ok(mut current) => *current = *position,
+                Err(_) => self.current.spawn_at(
+                    entity,
+                    (
+                        *position,
+                        Hitbox {
+                            radius: SOLDIER_HITBOX_RADIUS,
+                        },
+                    ),
+                ),
+            }
+        }
+
+        self.history.retain(|(t, _)| *t < tick);
+        self.history.push_back((tick, self.current.clone()));
+        while self.history.len() > MAX_HITBOX_HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    /// Hitboxes at `tick`, or the oldest ones kept if `tick` is older than the history
+    pub fn rewind(&self, tick: usize) -> Option<&World> {
+        self.history
+            .iter()
+            .rev()
+            .find(|(t, _)| *t <= tick)
+            .or_else(|| self.history.front())
+            .map(|(_, world)| world)
+    }
+
+    /// First hitbox crossed by segment `from`-`to` at `tick`, other than `shooter`'s one
+    pub fn hit_test(&self, tick: usize, from: Vec2, to: Vec2, shooter: Entity) -> Option<Hit> {
+        self.rewind(tick)?
+            .query::<(&Position, &Hitbox)>()
+            .iter()
+            .filter(|(entity, _)| *entity != shooter)
+            .filter_map(|(entity, (position, hitbox))| {
+                segment_circle(from, to, **position, hitbox.radius)
+                    .map(|t| (t, entity, **position, *hitbox))
+            })
+            // degenerate segment or positions must not take the server down
+            .filter(|(t, ..)| t.is_finite())
+            .min_by(|(a, ..), (b, ..)| a.total_cmp(b))
+            .map(|(t, entity, center, hitbox)| {
+                let point = from.lerp(to, t);
+                Hit {
+                    entity,
+                    point,
+                    region: hitbox.region(point - center),
+                }
+            })
+    }
+}
+
+/// Fraction of segment `from`-`to` at which it enters the circle, if it does
+fn segment_circle(from: Vec2, to: Vec2, center: Vec2, radius: f32) -> Option<f32> {
+    let direction = to - from;
+    let offset = from - center;
+    let c = offset.dot(offset) - radius * radius;
+    if c <= 0. {
+        return Some(0.); // starts inside
+    }
+    let a = direction.dot(direction);
+    if a == 0. {
+        return None;
+    }
+
+    let b = offset.dot(direction);
+    let discriminant = b * b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / a;
+    if (0. ..=1.).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// Tick of the world client saw when sending input which took `latency` millis
+/// (half of its round trip time) to arrive, taking its `interpolation_delay` into account.
+/// Latency is compensated up to `max_latency`, 0 disables rewinding altogether.
+pub fn rewind_tick(tick: usize, latency: f32, interpolation_delay: u32, max_latency: u32) -> usize {
+    if max_latency == 0 {
+        return tick;
+    }
+    let latency = latency.max(0.).min(max_latency as f32);
+    let millis = latency as f64 + interpolation_delay as f64;
+    tick.saturating_sub((millis / 1000. / TIMESTEP_RATE).round() as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::vec2;
+
+    #[test]
+    fn rewind() {
+        assert_eq!(rewind_tick(1000, 50., 100, 300), 991);
+        assert_eq!(rewind_tick(1000, 200., 200, 300), 976);
+        assert_eq!(rewind_tick(1000, 500., 200, 300), 970);
+        assert_eq!(rewind_tick(1000, 50., 100, 0), 1000);
+        assert_eq!(rewind_tick(1000, 0., 0, 300), 1000);
+        assert_eq!(rewind_tick(1000, f32::NAN, 0, 300), 1000);
+        assert_eq!(rewind_tick(3, 50., 100, 300), 0);
+
+        // shipped defaults - latency matters, and rewound hitboxes are still kept
+        let (delay, max) = (INTERPOLATION_DELAY, LAG_COMPENSATION_LATENCY);
+        assert!(rewind_tick(1000, 100., delay, max) < rewind_tick(1000, 20., delay, max));
+        assert_eq!(rewind_tick(1000, 0., delay, max), 1000 - 24);
+        assert!(1000 - rewind_tick(1000, 1000., delay, max) < MAX_HITBOX_HISTORY);
+
+        let mut world = HecsWorld::new();
+        let shooter = world.spawn((Soldier, Position::new(0., 0.)));
+        let target = world.spawn((Soldier, Position::new(100., 0.)));
+        let mut history = HitboxHistory::default();
+        history.record(&world, 10);
+        *world.get_mut::<Position>(target).unwrap() = Position::new(100., 100.);
+        history.record(&world, 11);
+
+        let (from, to) = (vec2(0., 0.), vec2(200., 0.));
+        let hit = history.hit_test(10, from, to, shooter).unwrap();
+        assert_eq!(hit.entity, target);
+        assert!((hit.point - vec2(100. - SOLDIER_HITBOX_RADIUS, 0.)).length() < 0.001);
+        assert_eq!(hit.region, HitRegion::Chest);
+        let head = vec2(0., -SOLDIER_HITBOX_RADIUS + 1.);
+        let hit = history.hit_test(10, head, to + head, shooter).unwrap();
+        assert_eq!(hit.region, HitRegion::Head);
+        assert!(history.hit_test(11, from, to, shooter).is_none());
+        assert!(history.hit_test(5, from, to, shooter).is_some()); // oldest kept
+        assert!(history
+            .hit_test(10, vec2(f32::NAN, 0.), to, shooter)
+            .is_none());
+
+        world.despawn(target).unwrap();
+        history.record(&world, 12);
+        assert!(history
+            .hit_test(12, from, vec2(100., 100.), shooter)
+            .is_none());
+    }
+
+    #[test]
+    fn segments() {
+        let center = vec2(10., 0.);
+        assert_eq!(
+            segment_circle(vec2(0., 0.), vec2(20., 0.), center, 5.),
+            Some(0.25)
+        );
+        assert_eq!(segment_circle(vec2(0., 0.), vec2(4., 0.), center, 5.), None);
+        assert_eq!(
+            segment_circle(vec2(0., 10.), vec2(20., 10.), center, 5.),
+            None
+        );
+        assert_eq!(
+            segment_circle(vec2(9., 0.), vec2(20., 0.), center, 5.),
+            Some(0.)
+        );
+        assert_eq!(segment_circle(vec2(0., 0.), vec2(0., 0.), center, 5.), None);
+    }
+}
@@ -1,11 +1,29 @@
+macro_rules! iif(
+    ($cond:expr, $then:expr, $else:expr) => (if $cond { $then } else { $else })
+);
+
+pub mod anims;
+pub mod auth;
+pub mod bullet;
+pub mod calc;
 pub mod components;
 pub mod constants;
 pub mod control;
 pub mod cvars;
+pub mod flag;
+pub mod lag_compensation;
+pub mod mapfile;
 pub mod messages;
 pub mod networking;
+pub mod particles;
 pub mod physics;
+pub mod pickup;
+pub mod prediction;
+pub mod replication;
+pub mod soldier;
 pub mod systems;
+pub mod vfs;
+pub mod weapons;
 pub mod world;
 
 use hexdump::hexdump_iter;
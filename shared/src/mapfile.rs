@@ -1,5 +1,10 @@
-use super::*;
+use crate::{
+    calc::*,
+    components::Team,
+    math::{vec2, vec3, Vec2, Vec3},
+};
 use byteorder::{LittleEndian, ReadBytesExt};
+use gvfs::filesystem::Filesystem;
 use std::error::Error;
 use std::io::{BufReader, Read};
 use std::path::PathBuf;
@@ -55,17 +60,6 @@ pub struct MapColor {
     pub a: u8,
 }
 
-impl From<MapColor> for gfx2d::Color {
-    fn from(color: MapColor) -> Self {
-        Self {
-            r: color.r,
-            g: color.g,
-            b: color.b,
-            a: color.a,
-        }
-    }
-}
-
 #[derive(Debug, Copy, Clone)]
 pub struct MapVertex {
     pub x: f32,
@@ -98,7 +92,7 @@ pub struct MapProp {
     pub height: i32,
     pub x: f32,
     pub y: f32,
-    pub rotation: Rad,
+    pub rotation: f32,
     pub scale_x: f32,
     pub scale_y: f32,
     pub alpha: u8,
@@ -128,6 +122,27 @@ pub struct MapSpawnpoint {
     pub team: i32,
 }
 
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum SpawnType {
+    General,
+    Alpha,
+    Bravo,
+    Charlie,
+    Delta,
+    AlphaFlag,
+    BravoFlag,
+    Grenades,
+    Medikits,
+    Clusters,
+    Vest,
+    Flamer,
+    Berserker,
+    Predator,
+    YellowFlag,
+    RamboBow,
+    StatGun,
+}
+
 #[allow(dead_code)]
 pub struct MapFile {
     pub filename: String,
@@ -174,9 +189,84 @@ impl MapPolygon {
             _ => true,
         }
     }
+
+    pub fn player_collides(&self, team: Team, flagger: bool) -> bool {
+        match self.polytype {
+            PolyType::AlphaPlayers => team == Team::Alpha,
+            PolyType::BravoPlayers => team == Team::Bravo,
+            PolyType::CharliePlayers => team == Team::Charlie,
+            PolyType::DeltaPlayers => team == Team::Delta,
+            PolyType::OnlyFlaggers => flagger,
+            PolyType::NotFlaggers => !flagger,
+            PolyType::AlphaBullets => false,
+            PolyType::BravoBullets => false,
+            PolyType::CharlieBullets => false,
+            PolyType::DeltaBullets => false,
+            PolyType::OnlyBulletsCollide => false,
+            PolyType::NoCollide => false,
+            PolyType::FlagCollide => false,
+            PolyType::Background => false,
+            PolyType::BackgroundTransition => false,
+            _ => true,
+        }
+    }
+
+    pub fn flag_collides(&self) -> bool {
+        !matches!(
+            self.polytype,
+            PolyType::AlphaBullets
+                | PolyType::AlphaPlayers
+                | PolyType::BravoBullets
+                | PolyType::BravoPlayers
+                | PolyType::CharlieBullets
+                | PolyType::CharliePlayers
+                | PolyType::DeltaBullets
+                | PolyType::DeltaPlayers
+                | PolyType::OnlyBulletsCollide
+                | PolyType::OnlyPlayersCollide
+                | PolyType::NoCollide
+                | PolyType::OnlyFlaggers
+                | PolyType::NotFlaggers
+                | PolyType::Background
+                | PolyType::BackgroundTransition
+        )
+    }
+}
+
+impl MapSpawnpoint {
+    pub fn spawn_type(&self) -> Option<SpawnType> {
+        match self.team {
+            0 => Some(SpawnType::General),
+            1 => Some(SpawnType::Alpha),
+            2 => Some(SpawnType::Bravo),
+            3 => Some(SpawnType::Charlie),
+            4 => Some(SpawnType::Delta),
+            5 => Some(SpawnType::AlphaFlag),
+            6 => Some(SpawnType::BravoFlag),
+            7 => Some(SpawnType::Grenades),
+            8 => Some(SpawnType::Medikits),
+            9 => Some(SpawnType::Clusters),
+            10 => Some(SpawnType::Vest),
+            11 => Some(SpawnType::Flamer),
+            12 => Some(SpawnType::Berserker),
+            13 => Some(SpawnType::Predator),
+            14 => Some(SpawnType::YellowFlag),
+            15 => Some(SpawnType::RamboBow),
+            16 => Some(SpawnType::StatGun),
+            _ => None,
+        }
+    }
 }
 
 impl MapFile {
+    /// Whether map `file_name` can be loaded with `load_map_file`
+    pub fn exists(fs: &Filesystem, file_name: &str) -> bool {
+        let mut path = PathBuf::new();
+        path.push("maps/");
+        path.push(file_name);
+        fs.is_file(path)
+    }
+
     pub fn load_map_file(fs: &mut Filesystem, file_name: &str) -> MapFile {
         let mut path = PathBuf::new();
         path.push("maps/");
@@ -327,7 +417,7 @@ impl MapFile {
             let height = buf.read_i32::<LittleEndian>().unwrap();
             let x = buf.read_f32::<LittleEndian>().unwrap();
             let y = buf.read_f32::<LittleEndian>().unwrap();
-            let rotation = rad(buf.read_f32::<LittleEndian>().unwrap());
+            let rotation = buf.read_f32::<LittleEndian>().unwrap();
             let scale_x = buf.read_f32::<LittleEndian>().unwrap();
             let scale_y = buf.read_f32::<LittleEndian>().unwrap();
             let alpha = buf.read_i32::<LittleEndian>().unwrap() as u8;
@@ -416,6 +506,13 @@ impl MapFile {
         }
     }
 
+    /// Active spawnpoints of given type
+    pub fn spawnpoints_of(&self, spawn_type: SpawnType) -> impl Iterator<Item = &MapSpawnpoint> {
+        self.spawnpoints
+            .iter()
+            .filter(move |spawn| spawn.active && spawn.spawn_type() == Some(spawn_type))
+    }
+
     pub fn point_in_poly(&self, p: Vec2, poly: &MapPolygon) -> bool {
         let a = &poly.vertices[0];
         let b = &poly.vertices[1];
@@ -1,42 +1,107 @@
 use crate::{
-    components,
+    auth::{AuthMac, Nonce, RejectReason, MAC_SIZE, NONCE_SIZE},
+    constants::MAX_PACKET_SIZE,
     control::Control,
     math::Vec2,
     networking::{NetCommand, NetSnapshot},
+    replication::{ComponentId, ComponentValue},
 };
 use bytes::Bytes;
 use enum_primitive_derive::Primitive;
 use hecs::Entity;
-use nanoserde::{DeBin, SerBin};
-use num_traits::{FromPrimitive, ToPrimitive};
-use std::{collections::HashMap, convert::TryFrom, mem::size_of, process::abort};
+use nanoserde::SerBin;
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    mem::size_of,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
 
-const NET_PROTOCOL_VERSION: u8 = 0x01;
+/// Newest protocol version spoken by this build
+const NET_PROTOCOL_VERSION: u8 = 0x03;
+/// Oldest protocol version this build can still talk to
+const NET_PROTOCOL_VERSION_MIN: u8 = 0x03;
 
 #[allow(non_camel_case_types)]
 #[repr(u8)]
-#[derive(Debug, Eq, PartialEq, Primitive)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Primitive)]
 pub enum OperationCode {
     // incoming
     CCREQ_CONNECT = 0x01,
     CCREQ_AUTHORIZE = 0x02,
+    CCREQ_DISCONNECT = 0x03,
     CCREQ_READY = 0x08,
     STT_CONTROL = 0x10,
     STT_ENTITIES = 0x11,
     STT_SNAPSHOT = 0x12,
     STT_COMMAND = 0x13,
+    STT_FRAGMENT = 0x14,
+    STT_PING = 0x15,
+    STT_PONG = 0x16,
     STT_CVARS = 0x18,
+    STT_MATCH = 0x19,
+    STT_MAP = 0x1A,
+    // out-of-band
+    MSREQ_HEARTBEAT = 0x20,
+    MSREQ_LIST = 0x21,
+    SVREQ_INFO = 0x22,
+    SVREQ_QUERY = 0x23,
     // outgoing
     CCREP_ACCEPT = 0x81,
     CCREP_REJECT = 0x82,
     CCREP_AUTHORIZED = 0x83,
+    CCREP_KICK = 0x84,
+    SVREP_INFO = 0x85,
+    MSREP_LIST = 0x86,
+    SVREP_QUERY = 0x87,
+}
+
+/// Server game flow state
+///
+/// Match goes through `Warmup` -> `Countdown` -> `InGame` -> `Intermission`
+/// and back to `Warmup` on the next map.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Primitive)]
+pub enum GameState {
+    /// waiting for players to get ready, scores are not counted
+    Warmup = 0x01,
+    /// match is live
+    InGame = 0x02,
+    /// all players ready, match starts shortly
+    Countdown = 0x03,
+    /// match ended, showing scoreboard before changing map
+    Intermission = 0x04,
+}
+
+/// Reason code sent in `CCREQ_DISCONNECT` and `CCREP_KICK`
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Primitive)]
+pub enum DisconnectReason {
+    Quit = 0x01,
+    Timeout = 0x02,
+    Kicked = 0x03,
+    Banned = 0x04,
+    Shutdown = 0x05,
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::Quit => write!(f, "quit"),
+            DisconnectReason::Timeout => write!(f, "connection timed out"),
+            DisconnectReason::Kicked => write!(f, "kicked"),
+            DisconnectReason::Banned => write!(f, "banned"),
+            DisconnectReason::Shutdown => write!(f, "server shutdown"),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NetworkMessage {
+    /// Response to the challenge received in `CCREP_ACCEPT`
     ConnectionAuthorize {
         nick: String,
-        key: String,
+        mac: AuthMac,
     },
     Cvars(Vec<(String, String)>),
     ControlState {
@@ -44,35 +109,52 @@ pub enum NetworkMessage {
         begin_tick: usize,
         control: Vec<(Control, Vec2)>,
     },
+    /// Entities state at `tick`, encoded as a delta against `base_tick`.
+    ///
+    /// `base_tick == 0` means a full state - receiver should drop all entities
+    /// not present in the message.
+    /// `ack_tick` is the last receiver's input tick applied to this state.
     GameState {
         tick: usize,
+        base_tick: usize,
+        ack_tick: usize,
         entities: HashMap<Entity, Vec<ComponentValue>>,
+        removed: HashMap<Entity, Vec<ComponentId>>,
+        despawned: Vec<Entity>,
     },
-    // Snapshot(Timestamped<NetSnapshot>),
-    // Command(Timestamped<NetCommand>),
+    /// Full state pushed (reliably) to resynchronize receiver
+    Snapshot {
+        tick: usize,
+        snapshot: NetSnapshot,
+    },
+    Command {
+        tick: usize,
+        command: NetCommand,
+    },
+    /// Server switched to `state` at `tick`.
+    /// `ends` is the tick it switches to next one, `0` when it waits for players.
+    MatchState {
+        tick: usize,
+        state: GameState,
+        ends: usize,
+    },
+    /// Server changed map - receiver should load map file of this name
+    MapChange(String),
 }
 
-#[repr(u8)]
-#[derive(Debug, Eq, PartialEq, Primitive)]
-pub enum ComponentType {
-    Soldier = 1,
-    Nick = 2,
-    Pos = 3,
-}
+/// Flag set on component id byte to mark component removal (no payload follows)
+const COMPONENT_REMOVED: u8 = 0x80;
 
-#[derive(Debug)]
-pub enum ComponentValue {
-    Soldier(components::Soldier),
-    Nick(components::Nick),
-    Pos(components::Position),
-}
+/// `NetCommand` variant tags
+const COMMAND_STRING: u8 = 0x01;
+const COMMAND_OUTPUT: u8 = 0x02;
 
 pub fn encode_message(msg: NetworkMessage) -> Bytes {
     match msg {
-        NetworkMessage::ConnectionAuthorize { nick, key } => {
+        NetworkMessage::ConnectionAuthorize { nick, mac } => {
             let mut msg = vec![OperationCode::CCREQ_AUTHORIZE as u8];
-            let pkt = AuthPacket { nick, key };
-            msg.extend(SerBin::serialize_bin(&pkt));
+            msg.extend(SerBin::serialize_bin(&nick));
+            msg.extend_from_slice(&mac);
             msg.into()
         }
         NetworkMessage::Cvars(cvars) => {
@@ -93,346 +175,1586 @@ pub fn encode_message(msg: NetworkMessage) -> Bytes {
                     .iter()
                     .map(|(c, v)| {
                         let v = v.normalize_or_zero();
-                        (
-                            *c,
-                            ((((v.x + 0.5) * 255.) as u8), ((v.y + 0.5) * 255.) as u8),
-                        )
+                        (*c, (quantize_aim(v.x), quantize_aim(v.y)))
                     })
                     .collect(),
             };
             msg.extend(SerBin::serialize_bin(&pkt));
             msg.into()
         }
-        NetworkMessage::GameState { tick, entities } => {
+        NetworkMessage::GameState {
+            tick,
+            base_tick,
+            ack_tick,
+            entities,
+            removed,
+            despawned,
+        } => {
             let mut msg = vec![OperationCode::STT_ENTITIES as u8];
-            let pkt = StatePacket { tick };
+            let pkt = StatePacket {
+                tick,
+                base_tick,
+                ack_tick,
+            };
 
             msg.extend(SerBin::serialize_bin(&pkt));
+            encode_entities(&mut msg, entities, removed);
 
-            msg.push(entities.len() as u8); // max 255 entities in packet
-            for (entity, components) in entities {
+            encode_varint(&mut msg, despawned.len());
+            for entity in despawned {
                 msg.extend(entity.to_bits().get().to_be_bytes().to_vec());
-                msg.push(components.len() as u8);
-                for component in components {
-                    match component {
-                        ComponentValue::Soldier(soldier) => {
-                            msg.push(ComponentType::Soldier.to_u8().unwrap());
-                            msg.extend(SerBin::serialize_bin(&soldier));
-                        }
-                        ComponentValue::Nick(nick) => {
-                            msg.push(ComponentType::Nick.to_u8().unwrap());
-                            msg.extend(SerBin::serialize_bin(&nick));
-                        }
-                        ComponentValue::Pos(pos) => {
-                            msg.push(ComponentType::Pos.to_u8().unwrap());
-                            msg.extend(SerBin::serialize_bin(&pos));
-                        }
-                    }
-                }
             }
 
             msg.into()
-        } //
-          // NetworkMessage::Snapshot(snapshot) => {
-          //     let mut msg = vec![OperationCode::STT_SNAPSHOT as u8];
-          //     let pkt = SnapshotPacket {
-          //         timestamp: snapshot.timestamp().to_i16(),
-          //         snapshot: snapshot.inner().clone(),
-          //     };
-          //     msg.extend(SerBin::serialize_bin(&pkt));
-          //     msg.into()
-          // }
-          // NetworkMessage::Command(command) => {
-          //     let mut msg = vec![OperationCode::STT_COMMAND as u8];
-          //     let pkt = CommandPacket {
-          //         timestamp: command.timestamp().to_i16(),
-          //         command: command.inner().clone(),
-          //     };
-          //     msg.extend(SerBin::serialize_bin(&pkt));
-          //     msg.into()
-          // }
-    }
-}
-
-pub fn decode_message(data: &[u8]) -> Option<NetworkMessage> {
-    let code = data[0];
-    if let Ok(op_code) = OperationCode::try_from(code) {
-        match op_code {
-            OperationCode::CCREQ_CONNECT
-            | OperationCode::CCREP_ACCEPT
-            | OperationCode::CCREP_REJECT
-            | OperationCode::CCREP_AUTHORIZED
-            | OperationCode::CCREQ_READY => {
-                panic!("Should not handle packet: 0x{:x} ({:?})", code, op_code)
-            }
-            OperationCode::CCREQ_AUTHORIZE => {
-                if let Ok(AuthPacket { nick, key }) = DeBin::deserialize_bin(&data[1..]) {
-                    return Some(NetworkMessage::ConnectionAuthorize { nick, key });
+        }
+        NetworkMessage::Snapshot { tick, snapshot } => {
+            let mut msg = vec![OperationCode::STT_SNAPSHOT as u8];
+            msg.extend(SerBin::serialize_bin(&tick));
+            encode_entities(&mut msg, snapshot.entities, HashMap::new());
+            msg.into()
+        }
+        NetworkMessage::Command { tick, command } => {
+            let mut msg = vec![OperationCode::STT_COMMAND as u8];
+            msg.extend(SerBin::serialize_bin(&tick));
+            match command {
+                NetCommand::Command(command) => {
+                    msg.push(COMMAND_STRING);
+                    msg.extend(SerBin::serialize_bin(&command));
                 }
-            }
-            OperationCode::STT_CVARS => {
-                if let Ok(cvars) = DeBin::deserialize_bin(&data[1..]) {
-                    return Some(NetworkMessage::Cvars(cvars));
+                NetCommand::Output(output) => {
+                    msg.push(COMMAND_OUTPUT);
+                    msg.extend(SerBin::serialize_bin(&output));
                 }
             }
-            OperationCode::STT_CONTROL => {
-                if let Ok(ControlPacket {
-                    ack_tick,
-                    begin_tick,
-                    control,
-                }) = DeBin::deserialize_bin(&data[1..])
-                {
-                    return Some(NetworkMessage::ControlState {
-                        ack_tick,
-                        begin_tick,
-                        control: control
-                            .iter()
-                            .map(|&(c, (x, y))| {
-                                (
-                                    c,
-                                    Vec2::new((x as f32) / 255. - 0.5, (y as f32) / 255. - 0.5),
-                                )
-                            })
-                            .collect(),
-                    });
-                }
+            msg.into()
+        }
+        NetworkMessage::MatchState { tick, state, ends } => {
+            let mut msg = vec![OperationCode::STT_MATCH as u8];
+            msg.extend(SerBin::serialize_bin(&tick));
+            msg.push(state as u8);
+            msg.extend(SerBin::serialize_bin(&ends));
+            msg.into()
+        }
+        NetworkMessage::MapChange(name) => {
+            let mut msg = vec![OperationCode::STT_MAP as u8];
+            msg.extend(SerBin::serialize_bin(&name));
+            msg.into()
+        }
+    }
+}
+
+/// Space left for fragment payload after `STT_FRAGMENT` header
+const MAX_FRAGMENT_PAYLOAD: usize = MAX_PACKET_SIZE - 32;
+/// Most fragments a single message can be split into - receiver drops anything above
+pub const MAX_FRAGMENTS: usize = 256;
+
+/// Encoded message needs more than `MAX_FRAGMENTS` fragments - carries its size in bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageTooLarge(pub usize);
+
+impl std::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "message of {} bytes does not fit {} fragments",
+            self.0, MAX_FRAGMENTS
+        )
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+/// Encodes message and splits it into `STT_FRAGMENT` datagrams if it does not fit `MAX_PACKET_SIZE`.
+/// Only `GameState` gets fragmented - fragments are reassembled by tick on receiving side.
+pub fn encode_message_fragmented(msg: NetworkMessage) -> Result<Vec<Bytes>, MessageTooLarge> {
+    let tick = match &msg {
+        NetworkMessage::GameState { tick, .. } => Some(*tick),
+        _ => None,
+    };
+    let data = encode_message(msg);
+
+    match tick {
+        Some(tick) if data.len() > MAX_PACKET_SIZE => {
+            let chunks = data.chunks(MAX_FRAGMENT_PAYLOAD);
+            let count = chunks.len();
+            if count > MAX_FRAGMENTS {
+                return Err(MessageTooLarge(data.len()));
             }
-            OperationCode::STT_ENTITIES => {
-                let mut offset = 1;
-                if let Ok(StatePacket { tick }) = DeBin::de_bin(&mut offset, data) {
-                    let mut entities = HashMap::new();
-                    if offset < data.len() {
-                        let mut entities_count = data[offset];
-                        offset += 1;
-                        while entities_count > 0 {
-                            entities_count -= 1;
-
-                            let entity = if offset + size_of::<u64>() < data.len() {
-                                let data: [u8; size_of::<u64>()] = [
-                                    data[offset],
-                                    data[offset + 1],
-                                    data[offset + 2],
-                                    data[offset + 3],
-                                    data[offset + 4],
-                                    data[offset + 5],
-                                    data[offset + 6],
-                                    data[offset + 7],
-                                ];
-                                offset += size_of::<u64>();
-                                Entity::from_bits(u64::from_be_bytes(data)).unwrap()
-                            } else {
-                                log::error!(
-                                    "@{}: Cannot deserialize {} entity id",
-                                    offset,
-                                    entities_count
-                                );
-                                return None;
-                            };
-
-                            if offset < data.len() {
-                                let mut components = Vec::new();
-
-                                let mut components_count = data[offset];
-                                offset += 1;
-                                while components_count > 0 {
-                                    components_count -= 1;
-
-                                    if offset < data.len() {
-                                        let component_type = data[offset];
-                                        offset += 1;
-                                        if let Some(component_type) =
-                                            ComponentType::from_u8(component_type)
-                                        {
-                                            match component_type {
-                                                ComponentType::Soldier => {
-                                                    if let Ok(soldier) = components::Soldier::de_bin(
-                                                        &mut offset,
-                                                        data,
-                                                    ) {
-                                                        components
-                                                            .push(ComponentValue::Soldier(soldier))
-                                                    } else {
-                                                        log::error!(
-                                                        "@{}: Cannot deserialize Soldier component",
-                                                        offset
-                                                    );
-                                                        return None;
-                                                    }
-                                                }
-                                                ComponentType::Nick => {
-                                                    if let Ok(nick) =
-                                                        components::Nick::de_bin(&mut offset, data)
-                                                    {
-                                                        components.push(ComponentValue::Nick(nick))
-                                                    } else {
-                                                        log::error!(
-                                                        "@{}: Cannot deserialize Nick component",
-                                                        offset
-                                                    );
-                                                        return None;
-                                                    }
-                                                }
-                                                ComponentType::Pos => {
-                                                    if let Ok(pos) = components::Position::de_bin(
-                                                        &mut offset,
-                                                        data,
-                                                    ) {
-                                                        components.push(ComponentValue::Pos(pos))
-                                                    } else {
-                                                        log::error!(
-                                                        "@{}: Cannot deserialize Position component",
-                                                        offset
-                                                    );
-                                                        return None;
-                                                    }
-                                                }
-                                            }
-                                        } else {
-                                            log::error!(
-                                                "@{}: Unhandled component type: {}",
-                                                offset,
-                                                component_type
-                                            );
-                                            return None;
-                                        }
-                                    } else {
-                                        log::error!(
-                                            "@{}: Not enough data to deserialize {} component",
-                                            offset,
-                                            components_count
-                                        );
-                                        return None;
-                                    }
-                                }
-
-                                entities.insert(entity, components);
-                            } else {
-                                log::error!("@{}: Not enough data to get components count", offset);
-                                return None;
-                            }
-                        }
-
-                        return Some(NetworkMessage::GameState { tick, entities });
-                    } else {
-                        log::error!("@{}: Not enough data to get entities count", offset);
-                        return None;
-                    }
-                }
+            Ok(chunks
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let mut msg = vec![OperationCode::STT_FRAGMENT as u8];
+                    let pkt = Fragment {
+                        tick,
+                        index: index as u16,
+                        count: count as u16,
+                        payload: chunk.to_vec(),
+                    };
+                    msg.extend(SerBin::serialize_bin(&pkt));
+                    msg.into()
+                })
+                .collect())
+        }
+        _ => Ok(vec![data]),
+    }
+}
+
+pub fn decode_fragment(data: &[u8]) -> Result<Fragment, DecodeError> {
+    let mut reader = Reader::new(data);
+    let code = reader.u8()?;
+    if code != OperationCode::STT_FRAGMENT as u8 {
+        return Err(DecodeError::UnknownOpcode(code));
+    }
+
+    let fragment = Fragment {
+        tick: reader.usize()?,
+        index: reader.u16()?,
+        count: reader.u16()?,
+        payload: {
+            let len = reader.len()?;
+            reader.bytes(len)?.to_vec()
+        },
+    };
+    reader.finish()?;
+
+    Ok(fragment)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// Packet ended before message was fully decoded
+    Truncated { offset: usize },
+    /// First byte is not a known `OperationCode`
+    UnknownOpcode(u8),
+    /// Known `OperationCode`, but not one that carries a `NetworkMessage`
+    UnexpectedOpcode(OperationCode),
+    /// Entity component tagged with unknown `ComponentId`
+    BadComponentType(ComponentId),
+    /// Value decoded, but does not make sense (bad UTF-8, unknown control bits, null entity)
+    InvalidValue { offset: usize },
+    /// Message decoded, but packet has more data
+    TrailingBytes { offset: usize, len: usize },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Truncated { offset } => write!(f, "@{}: packet truncated", offset),
+            DecodeError::UnknownOpcode(code) => write!(f, "unknown packet: 0x{:x}", code),
+            DecodeError::UnexpectedOpcode(op_code) => {
+                write!(f, "should not decode packet: {:?}", op_code)
+            }
+            DecodeError::BadComponentType(id) => write!(f, "unhandled component type: {}", id),
+            DecodeError::InvalidValue { offset } => write!(f, "@{}: invalid value", offset),
+            DecodeError::TrailingBytes { offset, len } => {
+                write!(f, "@{}: {} trailing bytes", offset, len - offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode_message(data: &[u8]) -> Result<NetworkMessage, DecodeError> {
+    let mut reader = Reader::new(data);
+    let code = reader.u8()?;
+    let op_code = OperationCode::try_from(code).map_err(|_| DecodeError::UnknownOpcode(code))?;
+
+    let message = match op_code {
+        OperationCode::CCREQ_CONNECT
+        | OperationCode::CCREP_ACCEPT
+        | OperationCode::CCREP_REJECT
+        | OperationCode::CCREP_AUTHORIZED
+        | OperationCode::CCREQ_READY
+        | OperationCode::CCREQ_DISCONNECT
+        | OperationCode::CCREP_KICK
+        | OperationCode::STT_FRAGMENT
+        | OperationCode::STT_PING
+        | OperationCode::STT_PONG
+        | OperationCode::MSREQ_HEARTBEAT
+        | OperationCode::MSREQ_LIST
+        | OperationCode::SVREQ_INFO
+        | OperationCode::SVREQ_QUERY
+        | OperationCode::SVREP_INFO
+        | OperationCode::MSREP_LIST
+        | OperationCode::SVREP_QUERY => {
+            return Err(DecodeError::UnexpectedOpcode(op_code));
+        }
+        OperationCode::CCREQ_AUTHORIZE => NetworkMessage::ConnectionAuthorize {
+            nick: reader.string()?,
+            mac: reader.bytes(MAC_SIZE)?.try_into().unwrap(),
+        },
+        OperationCode::STT_CVARS => {
+            let mut cvars = Vec::new();
+            for _ in 0..reader.len()? {
+                cvars.push((reader.string()?, reader.string()?));
             }
-            OperationCode::STT_SNAPSHOT => {
-                if let Ok(SnapshotPacket {
-                    timestamp,
-                    snapshot,
-                }) = DeBin::deserialize_bin(&data[1..])
-                {
-                    return None;
-                    // return Some(NetworkMessage::Snapshot(Timestamped::<NetSnapshot>::new(
-                    //     snapshot,
-                    //     Timestamp::from_i16(timestamp),
-                    // )));
+            NetworkMessage::Cvars(cvars)
+        }
+        OperationCode::STT_CONTROL => {
+            let ack_tick = reader.usize()?;
+            let begin_tick = reader.usize()?;
+            let mut control = Vec::new();
+            for _ in 0..reader.len()? {
+                let offset = reader.offset;
+                let c = Control::from_bits(reader.u16()?)
+                    .ok_or(DecodeError::InvalidValue { offset })?;
+                let (x, y) = (reader.u8()?, reader.u8()?);
+                control.push((c, Vec2::new(dequantize_aim(x), dequantize_aim(y))));
+            }
+            NetworkMessage::ControlState {
+                ack_tick,
+                begin_tick,
+                control,
+            }
+        }
+        OperationCode::STT_SNAPSHOT => {
+            let tick = reader.usize()?;
+            let offset = reader.offset;
+            let (entities, removed) = reader.entities()?;
+            if !removed.is_empty() {
+                return Err(DecodeError::InvalidValue { offset });
+            }
+            NetworkMessage::Snapshot {
+                tick,
+                snapshot: NetSnapshot { entities },
+            }
+        }
+        OperationCode::STT_COMMAND => {
+            let tick = reader.usize()?;
+            let offset = reader.offset;
+            let command = match reader.u8()? {
+                COMMAND_STRING => NetCommand::Command(reader.string()?),
+                COMMAND_OUTPUT => NetCommand::Output(reader.string()?),
+                _ => return Err(DecodeError::InvalidValue { offset }),
+            };
+            NetworkMessage::Command { tick, command }
+        }
+        OperationCode::STT_MATCH => {
+            let tick = reader.usize()?;
+            let offset = reader.offset;
+            let state = GameState::try_from(reader.u8()?)
+                .map_err(|_| DecodeError::InvalidValue { offset })?;
+            let ends = reader.usize()?;
+            NetworkMessage::MatchState { tick, state, ends }
+        }
+        OperationCode::STT_MAP => NetworkMessage::MapChange(reader.string()?),
+        OperationCode::STT_ENTITIES => {
+            let tick = reader.usize()?;
+            let base_tick = reader.usize()?;
+            let ack_tick = reader.usize()?;
+            let (entities, removed) = reader.entities()?;
+
+            let mut despawned = Vec::new();
+            for _ in 0..reader.count()? {
+                despawned.push(reader.entity()?);
+            }
+
+            NetworkMessage::GameState {
+                tick,
+                base_tick,
+                ack_tick,
+                entities,
+                removed,
+                despawned,
+            }
+        }
+    };
+    reader.finish()?;
+
+    Ok(message)
+}
+
+/// Maps normalized aim vector component `-1..=1` to `1..=255`, with `0` exactly at `128`
+fn quantize_aim(v: f32) -> u8 {
+    ((v.clamp(-1., 1.) * 127.).round() + 128.) as u8
+}
+
+fn dequantize_aim(v: u8) -> f32 {
+    ((v as f32 - 128.) / 127.).clamp(-1., 1.)
+}
+
+/// Entity id, component count and components (id, length, payload) or removed component ids
+fn encode_entities(
+    msg: &mut Vec<u8>,
+    mut entities: HashMap<Entity, Vec<ComponentValue>>,
+    mut removed: HashMap<Entity, Vec<ComponentId>>,
+) {
+    let mut changed: Vec<Entity> = entities.keys().copied().collect();
+    changed.extend(removed.keys().filter(|e| !entities.contains_key(e)));
+
+    encode_varint(msg, changed.len());
+    for entity in changed {
+        let components = entities.remove(&entity).unwrap_or_default();
+        let removed = removed.remove(&entity).unwrap_or_default();
+        msg.extend(entity.to_bits().get().to_be_bytes().to_vec());
+        encode_varint(msg, components.len() + removed.len());
+        for component in components {
+            msg.push(component.id);
+            encode_varint(msg, component.data.len());
+            msg.extend(component.data);
+        }
+        for id in removed {
+            msg.push(id | COMPONENT_REMOVED);
+        }
+    }
+}
+
+/// LEB128 encoding of unsigned value - 7 bits per byte, MSB set if more bytes follow
+fn encode_varint(msg: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            msg.push(byte);
+            return;
+        }
+        msg.push(byte | 0x80);
+    }
+}
+
+/// Bounds-checked reader over untrusted packet data.
+///
+/// nanoserde trusts length prefixes (allocates before checking), so containers
+/// are read by hand here and component payloads are length-delimited before
+/// `DeBin` ever sees them.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, offset: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.remaining() {
+            return Err(DecodeError::Truncated {
+                offset: self.offset,
+            });
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.offset;
+        let val = u64::from_le_bytes(self.bytes(size_of::<u64>())?.try_into().unwrap());
+        usize::try_from(val).map_err(|_| DecodeError::InvalidValue { offset })
+    }
+
+    /// nanoserde container length - every element takes at least one byte,
+    /// so it cannot be larger than remaining data
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.offset;
+        let len = self.usize()?;
+        if len > self.remaining() {
+            return Err(DecodeError::Truncated { offset });
+        }
+        Ok(len)
+    }
+
+    /// Varint encoded count of items taking at least one byte each
+    fn count(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.offset;
+        let mut value: usize = 0;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7F) as usize)
+                .checked_shl(shift)
+                .ok_or(DecodeError::InvalidValue { offset })?;
+            if byte & 0x80 == 0 {
+                if value > self.remaining() {
+                    return Err(DecodeError::Truncated { offset });
                 }
+                return Ok(value);
             }
-            OperationCode::STT_COMMAND => {
-                if let Ok(CommandPacket { timestamp, command }) = DeBin::deserialize_bin(&data[1..])
-                {
-                    return None;
-                    // return Some(NetworkMessage::Command(Timestamped::<NetCommand>::new(
-                    //     command,
-                    //     Timestamp::from_i16(timestamp),
-                    // )));
+        }
+        Err(DecodeError::InvalidValue { offset })
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.len()?;
+        let offset = self.offset;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| DecodeError::InvalidValue { offset })
+    }
+
+    fn entity(&mut self) -> Result<Entity, DecodeError> {
+        let offset = self.offset;
+        let bits = u64::from_be_bytes(self.bytes(size_of::<u64>())?.try_into().unwrap());
+        Entity::from_bits(bits).ok_or(DecodeError::InvalidValue { offset })
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn entities(
+        &mut self,
+    ) -> Result<
+        (
+            HashMap<Entity, Vec<ComponentValue>>,
+            HashMap<Entity, Vec<ComponentId>>,
+        ),
+        DecodeError,
+    > {
+        let mut entities = HashMap::new();
+        let mut removed = HashMap::new();
+        for _ in 0..self.count()? {
+            let entity = self.entity()?;
+
+            let mut components = Vec::new();
+            let mut removed_components = Vec::new();
+            for _ in 0..self.count()? {
+                let code = self.u8()?;
+                let id = code & !COMPONENT_REMOVED;
+                if id == 0 {
+                    return Err(DecodeError::BadComponentType(id));
+                }
+                if code & COMPONENT_REMOVED != 0 {
+                    removed_components.push(id);
+                    continue;
                 }
+                // payload is decoded by ReplicationRegistry when applied to World
+                let len = self.count()?;
+                components.push(ComponentValue {
+                    id,
+                    data: self.bytes(len)?.to_vec(),
+                });
             }
+
+            if !components.is_empty() {
+                entities.insert(entity, components);
+            }
+            if !removed_components.is_empty() {
+                removed.insert(entity, removed_components);
+            }
+        }
+        Ok((entities, removed))
+    }
+
+    fn short_string(&mut self) -> Result<String, DecodeError> {
+        let len = self.u8()? as usize;
+        let offset = self.offset;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| DecodeError::InvalidValue { offset })
+    }
+
+    fn version_info(&mut self) -> Result<VersionInfo, DecodeError> {
+        Ok(VersionInfo {
+            min: self.u8()?,
+            max: self.u8()?,
+            build: self.short_string()?,
+        })
+    }
+
+    fn server_status(&mut self) -> Result<ServerStatus, DecodeError> {
+        Ok(ServerStatus {
+            name: self.short_string()?,
+            map: self.short_string()?,
+            players: self.u8()?,
+            max_players: self.u8()?,
+            version: self.version_info()?,
+        })
+    }
+
+    fn socket_addr(&mut self) -> Result<SocketAddr, DecodeError> {
+        let offset = self.offset;
+        let ip = match self.u8()? {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(self.bytes(4)?).unwrap())),
+            6 => IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(self.bytes(16)?).unwrap(),
+            )),
+            _ => return Err(DecodeError::InvalidValue { offset }),
+        };
+        Ok(SocketAddr::new(ip, self.u16()?))
+    }
+
+    /// Reads operation code and handshake magic
+    fn header(&mut self, expected: OperationCode) -> Result<(), DecodeError> {
+        self.op_code(expected)?;
+        let offset = self.offset;
+        if self.bytes(4)? != b"SLDT" {
+            return Err(DecodeError::InvalidValue { offset });
+        }
+        Ok(())
+    }
+
+    /// Reads operation code, which must be the `expected` one
+    fn op_code(&mut self, expected: OperationCode) -> Result<(), DecodeError> {
+        let code = self.u8()?;
+        let op_code =
+            OperationCode::try_from(code).map_err(|_| DecodeError::UnknownOpcode(code))?;
+        if op_code != expected {
+            return Err(DecodeError::UnexpectedOpcode(op_code));
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), DecodeError> {
+        if self.offset != self.data.len() {
+            return Err(DecodeError::TrailingBytes {
+                offset: self.offset,
+                len: self.data.len(),
+            });
         }
+        Ok(())
     }
-    None
+}
+
+/// Protocol versions range supported by a peer, with its build description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub min: u8,
+    pub max: u8,
+    pub build: String,
+}
+
+impl VersionInfo {
+    pub fn current() -> Self {
+        VersionInfo {
+            min: NET_PROTOCOL_VERSION_MIN,
+            max: NET_PROTOCOL_VERSION,
+            build: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    pub fn supports(&self, version: u8) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+
+    /// Highest protocol version supported by both sides
+    pub fn negotiate(&self, other: &VersionInfo) -> Option<u8> {
+        let min = u8::max(self.min, other.min);
+        let max = u8::min(self.max, other.max);
+        if min <= max {
+            Some(max)
+        } else {
+            None
+        }
+    }
+
+    fn encode(&self, msg: &mut Vec<u8>) {
+        msg.push(self.min);
+        msg.push(self.max);
+        encode_short_string(msg, &self.build);
+    }
+}
+
+impl std::fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "protocol v{} ({})", self.max, self.build)
+        } else {
+            write!(f, "protocol v{}-v{} ({})", self.min, self.max, self.build)
+        }
+    }
+}
+
+/// String prefixed with u8 length - truncated at char boundary if longer
+fn encode_short_string(msg: &mut Vec<u8>, value: &str) {
+    let mut len = usize::min(value.len(), u8::MAX as usize);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    msg.push(len as u8);
+    msg.extend_from_slice(&value.as_bytes()[..len]);
+}
+
+fn packet_header(op_code: OperationCode) -> Vec<u8> {
+    vec![op_code as u8, b'S', b'L', b'D', b'T']
 }
 
 pub fn connection_request() -> Bytes {
-    vec![
-        OperationCode::CCREQ_CONNECT as u8,
-        b'S',
-        b'L',
-        b'D',
-        b'T',
-        NET_PROTOCOL_VERSION,
-    ]
-    .into()
-}
-
-pub fn connection_accept() -> Bytes {
-    vec![
-        OperationCode::CCREP_ACCEPT as u8,
-        b'S',
-        b'L',
-        b'D',
-        b'T',
-        NET_PROTOCOL_VERSION,
-    ]
-    .into()
-}
-
-pub fn connection_reject() -> Bytes {
-    vec![
-        OperationCode::CCREP_REJECT as u8,
-        b'S',
-        b'L',
-        b'D',
-        b'T',
-        NET_PROTOCOL_VERSION,
-    ]
-    .into()
+    let mut msg = packet_header(OperationCode::CCREQ_CONNECT);
+    VersionInfo::current().encode(&mut msg);
+    msg.into()
 }
 
-pub fn packet_verify(packet: &[u8]) -> bool {
-    packet[1] == b'S'
-        && packet[2] == b'L'
-        && packet[3] == b'D'
-        && packet[4] == b'T'
-        && packet[5] == NET_PROTOCOL_VERSION
+pub fn decode_request(data: &[u8]) -> Result<VersionInfo, DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.header(OperationCode::CCREQ_CONNECT)?;
+    let version = reader.version_info()?;
+    reader.finish()?;
+    Ok(version)
+}
+
+/// Accepts connection with negotiated protocol `version`,
+/// challenging client to prove it knows the connection key
+pub fn connection_accept(version: u8, nonce: &Nonce) -> Bytes {
+    let mut msg = packet_header(OperationCode::CCREP_ACCEPT);
+    msg.push(version);
+    msg.extend_from_slice(nonce);
+    encode_short_string(&mut msg, &VersionInfo::current().build);
+    msg.into()
 }
 
-pub fn connection_authorized<S: AsRef<str>>(motd: S) -> Bytes {
-    let motd = motd.as_ref().as_bytes();
-    if motd.len() > u8::MAX as usize {
-        log::error!("Server MOTD is longer than {} bytes", u8::MAX);
-        abort();
+/// Returns negotiated protocol version, challenge nonce and server build
+pub fn decode_accept(data: &[u8]) -> Result<(u8, Nonce, String), DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.header(OperationCode::CCREP_ACCEPT)?;
+    let version = reader.u8()?;
+    let nonce = reader.bytes(NONCE_SIZE)?.try_into().unwrap();
+    let build = reader.short_string()?;
+    reader.finish()?;
+    Ok((version, nonce, build))
+}
+
+/// Rejects connection. `VersionMismatch` carries versions supported by server.
+pub fn connection_reject(reason: RejectReason) -> Bytes {
+    let mut msg = packet_header(OperationCode::CCREP_REJECT);
+    msg.push(reason as u8);
+    if reason == RejectReason::VersionMismatch {
+        VersionInfo::current().encode(&mut msg);
     }
+    msg.into()
+}
+
+pub fn decode_reject(data: &[u8]) -> Result<(RejectReason, Option<VersionInfo>), DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.header(OperationCode::CCREP_REJECT)?;
+    let offset = reader.offset;
+    let reason =
+        RejectReason::try_from(reader.u8()?).map_err(|_| DecodeError::InvalidValue { offset })?;
+    let version = if reason == RejectReason::VersionMismatch {
+        Some(reader.version_info()?)
+    } else {
+        None
+    };
+    reader.finish()?;
+    Ok((reason, version))
+}
+
+/// Checks the magic of connection handshake packets
+pub fn packet_verify(packet: &[u8]) -> bool {
+    packet.get(1..5) == Some(&b"SLDT"[..])
+}
+
+/// Authorization reply carrying server MOTD and the entity reserved for the player.
+/// MOTD longer than 255 bytes is truncated.
+pub fn connection_authorized<S: AsRef<str>>(motd: S, entity: Entity) -> Bytes {
     let mut msg = vec![OperationCode::CCREP_AUTHORIZED as u8];
-    msg.push(motd.len() as u8);
-    msg.extend_from_slice(motd);
+    encode_short_string(&mut msg, motd.as_ref());
+    msg.extend_from_slice(&entity.to_bits().get().to_be_bytes());
     msg.into()
 }
 
+pub fn decode_authorized(data: &[u8]) -> Result<(String, Entity), DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.op_code(OperationCode::CCREP_AUTHORIZED)?;
+    let motd_len = reader.u8()? as usize;
+    let motd = String::from_utf8_lossy(reader.bytes(motd_len)?).to_string();
+    let entity = reader.entity()?;
+    reader.finish()?;
+    Ok((motd, entity))
+}
+
 pub fn connection_ready() -> Bytes {
     vec![OperationCode::CCREQ_READY as u8].into()
 }
 
-#[derive(DeBin, SerBin)]
-struct AuthPacket {
-    nick: String,
-    key: String,
+/// Client leaving the server
+pub fn connection_disconnect(reason: DisconnectReason) -> Bytes {
+    vec![OperationCode::CCREQ_DISCONNECT as u8, reason as u8, 0].into()
+}
+
+/// Server dropping the client, with a message to show
+pub fn connection_kick<S: AsRef<str>>(reason: DisconnectReason, message: S) -> Bytes {
+    let mut msg = vec![OperationCode::CCREP_KICK as u8, reason as u8];
+    encode_short_string(&mut msg, message.as_ref());
+    msg.into()
+}
+
+/// Decodes either `CCREQ_DISCONNECT` or `CCREP_KICK`
+pub fn decode_disconnect(data: &[u8]) -> Result<(DisconnectReason, String), DecodeError> {
+    let mut reader = Reader::new(data);
+    let code = reader.u8()?;
+    let op_code = OperationCode::try_from(code).map_err(|_| DecodeError::UnknownOpcode(code))?;
+    if op_code != OperationCode::CCREQ_DISCONNECT && op_code != OperationCode::CCREP_KICK {
+        return Err(DecodeError::UnexpectedOpcode(op_code));
+    }
+    let offset = reader.offset;
+    let reason = DisconnectReason::try_from(reader.u8()?)
+        .map_err(|_| DecodeError::InvalidValue { offset })?;
+    let message = reader.short_string()?;
+    reader.finish()?;
+    Ok((reason, message))
+}
+
+/// Round trip time probe, sent by both sides and answered with `pong`
+pub fn ping(sequence: u16) -> Bytes {
+    let mut msg = vec![OperationCode::STT_PING as u8];
+    msg.extend_from_slice(&sequence.to_le_bytes());
+    msg.into()
+}
+
+pub fn pong(sequence: u16) -> Bytes {
+    let mut msg = vec![OperationCode::STT_PONG as u8];
+    msg.extend_from_slice(&sequence.to_le_bytes());
+    msg.into()
+}
+
+/// Decodes either `STT_PING` or `STT_PONG` sequence number
+pub fn decode_ping(data: &[u8]) -> Result<(OperationCode, u16), DecodeError> {
+    let mut reader = Reader::new(data);
+    let code = reader.u8()?;
+    let op_code = OperationCode::try_from(code).map_err(|_| DecodeError::UnknownOpcode(code))?;
+    if op_code != OperationCode::STT_PING && op_code != OperationCode::STT_PONG {
+        return Err(DecodeError::UnexpectedOpcode(op_code));
+    }
+    let sequence = reader.u16()?;
+    reader.finish()?;
+    Ok((op_code, sequence))
+}
+
+/// Server description shown in server browser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerStatus {
+    pub name: String,
+    pub map: String,
+    pub players: u8,
+    pub max_players: u8,
+    pub version: VersionInfo,
+}
+
+impl ServerStatus {
+    fn encode(&self, msg: &mut Vec<u8>) {
+        encode_short_string(msg, &self.name);
+        encode_short_string(msg, &self.map);
+        msg.push(self.players);
+        msg.push(self.max_players);
+        self.version.encode(msg);
+    }
+}
+
+fn encode_socket_addr(msg: &mut Vec<u8>, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            msg.push(4);
+            msg.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            msg.push(6);
+            msg.extend_from_slice(&ip.octets());
+        }
+    }
+    msg.extend_from_slice(&addr.port().to_le_bytes());
+}
+
+/// Server registration, sent periodically to master server
+pub fn master_heartbeat(status: &ServerStatus) -> Bytes {
+    let mut msg = packet_header(OperationCode::MSREQ_HEARTBEAT);
+    status.encode(&mut msg);
+    msg.into()
+}
+
+pub fn decode_heartbeat(data: &[u8]) -> Result<ServerStatus, DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.header(OperationCode::MSREQ_HEARTBEAT)?;
+    let status = reader.server_status()?;
+    reader.finish()?;
+    Ok(status)
+}
+
+pub fn master_list_request() -> Bytes {
+    packet_header(OperationCode::MSREQ_LIST).into()
+}
+
+/// Servers registered with master server, split into datagrams fitting `MAX_PACKET_SIZE`
+pub fn master_list(servers: &[(SocketAddr, ServerStatus)]) -> Vec<Bytes> {
+    let mut datagrams = Vec::new();
+    let mut msg = packet_header(OperationCode::MSREP_LIST);
+    let header_len = msg.len();
+    for (addr, status) in servers {
+        let mut entry = Vec::new();
+        encode_socket_addr(&mut entry, addr);
+        status.encode(&mut entry);
+        if msg.len() + entry.len() > MAX_PACKET_SIZE && msg.len() > header_len {
+            datagrams.push(msg.into());
+            msg = packet_header(OperationCode::MSREP_LIST);
+        }
+        msg.extend_from_slice(&entry);
+    }
+    if msg.len() > header_len || datagrams.is_empty() {
+        datagrams.push(msg.into());
+    }
+    datagrams
+}
+
+pub fn decode_master_list(data: &[u8]) -> Result<Vec<(SocketAddr, ServerStatus)>, DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.header(OperationCode::MSREP_LIST)?;
+    let mut servers = Vec::new();
+    while reader.remaining() > 0 {
+        servers.push((reader.socket_addr()?, reader.server_status()?));
+    }
+    Ok(servers)
+}
+
+/// Asks server to describe itself - sent directly or broadcast to `SERVER_PORT` on LAN
+pub fn server_info_request() -> Bytes {
+    packet_header(OperationCode::SVREQ_INFO).into()
 }
 
-#[derive(DeBin, SerBin)]
+pub fn server_info(status: &ServerStatus) -> Bytes {
+    let mut msg = packet_header(OperationCode::SVREP_INFO);
+    status.encode(&mut msg);
+    msg.into()
+}
+
+pub fn decode_server_info(data: &[u8]) -> Result<ServerStatus, DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.header(OperationCode::SVREP_INFO)?;
+    let status = reader.server_status()?;
+    reader.finish()?;
+    Ok(status)
+}
+
+/// Player entry of `SVREP_QUERY`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerInfo {
+    pub nick: String,
+    pub score: i32,
+    /// round trip time, millis
+    pub ping: u16,
+}
+
+/// Detailed server state for monitoring tools
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryInfo {
+    pub name: String,
+    pub motd: String,
+    pub map: String,
+    pub game_state: GameState,
+    pub max_players: u8,
+    pub version: VersionInfo,
+    pub players: Vec<PlayerInfo>,
+    /// public cvars
+    pub rules: Vec<(String, String)>,
+}
+
+/// Detailed query, answered by any server without connecting
+pub fn server_query_request() -> Bytes {
+    packet_header(OperationCode::SVREQ_QUERY).into()
+}
+
+/// Encodes query reply into a single datagram - players and rules not fitting
+/// `MAX_PACKET_SIZE` are left out
+pub fn server_query(info: &QueryInfo) -> Bytes {
+    let mut msg = packet_header(OperationCode::SVREP_QUERY);
+    encode_short_string(&mut msg, &info.name);
+    encode_short_string(&mut msg, &info.motd);
+    encode_short_string(&mut msg, &info.map);
+    msg.push(info.game_state as u8);
+    msg.push(info.max_players);
+    info.version.encode(&mut msg);
+
+    let players = info.players.iter().map(|player| {
+        let mut entry = Vec::new();
+        encode_short_string(&mut entry, &player.nick);
+        entry.extend_from_slice(&player.score.to_le_bytes());
+        entry.extend_from_slice(&player.ping.to_le_bytes());
+        entry
+    });
+    encode_fitting(&mut msg, players, 1); // leave room for rules count
+    let rules = info.rules.iter().map(|(name, value)| {
+        let mut entry = Vec::new();
+        encode_short_string(&mut entry, name);
+        encode_short_string(&mut entry, value);
+        entry
+    });
+    encode_fitting(&mut msg, rules, 0);
+    msg.into()
+}
+
+/// Appends u8 count and as many of `entries` as fit `MAX_PACKET_SIZE` with `reserve` bytes left
+fn encode_fitting<I: Iterator<Item = Vec<u8>>>(msg: &mut Vec<u8>, entries: I, reserve: usize) {
+    let count_offset = msg.len();
+    msg.push(0);
+    let mut count = 0;
+    for entry in entries {
+        if count == u8::MAX || msg.len() + entry.len() + reserve > MAX_PACKET_SIZE {
+            break;
+        }
+        msg.extend_from_slice(&entry);
+        count += 1;
+    }
+    msg[count_offset] = count;
+}
+
+pub fn decode_server_query(data: &[u8]) -> Result<QueryInfo, DecodeError> {
+    let mut reader = Reader::new(data);
+    reader.header(OperationCode::SVREP_QUERY)?;
+    let name = reader.short_string()?;
+    let motd = reader.short_string()?;
+    let map = reader.short_string()?;
+    let offset = reader.offset;
+    let game_state =
+        GameState::try_from(reader.u8()?).map_err(|_| DecodeError::InvalidValue { offset })?;
+    let max_players = reader.u8()?;
+    let version = reader.version_info()?;
+    let mut players = Vec::new();
+    for _ in 0..reader.u8()? {
+        players.push(PlayerInfo {
+            nick: reader.short_string()?,
+            score: reader.i32()?,
+            ping: reader.u16()?,
+        });
+    }
+    let mut rules = Vec::new();
+    for _ in 0..reader.u8()? {
+        rules.push((reader.short_string()?, reader.short_string()?));
+    }
+    reader.finish()?;
+    Ok(QueryInfo {
+        name,
+        motd,
+        map,
+        game_state,
+        max_players,
+        version,
+        players,
+        rules,
+    })
+}
+
+#[derive(SerBin)]
 struct ControlPacket {
     ack_tick: usize,
     begin_tick: usize,
     control: Vec<(Control, (u8, u8))>,
 }
 
-#[derive(DeBin, SerBin)]
+#[derive(SerBin)]
 struct StatePacket {
     tick: usize,
+    base_tick: usize,
+    ack_tick: usize,
 }
 
-#[derive(DeBin, SerBin)]
-struct SnapshotPacket {
-    timestamp: i16,
-    snapshot: NetSnapshot,
+#[derive(Debug, SerBin)]
+pub struct Fragment {
+    pub tick: usize,
+    pub index: u16,
+    pub count: u16,
+    pub payload: Vec<u8>,
 }
 
-#[derive(DeBin, SerBin)]
-struct CommandPacket {
-    timestamp: i16,
-    command: NetCommand,
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        auth::auth_mac, components, networking::FragmentBuffer, replication::MAX_COMPONENT_ID,
+    };
+
+    const VARIANTS: usize = 8;
+    const ITERATIONS: usize = 256;
+
+    /// xorshift64* - deterministic, so failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn f32(&mut self) -> f32 {
+            self.below(20001) as f32 / 10. - 1000.
+        }
+
+        fn string(&mut self) -> String {
+            (0..self.below(16))
+                .map(|_| match self.below(8) {
+                    0 => '\u{17C}',
+                    _ => char::from(b'a' + self.below(26) as u8),
+                })
+                .collect()
+        }
+
+        fn entity(&mut self) -> Entity {
+            let generation = 1 + self.below(1000) as u64;
+            Entity::from_bits((generation << 32) | (self.next() & 0xFFFF_FFFF)).unwrap()
+        }
+    }
+
+    fn random_component(rng: &mut Rng) -> ComponentValue {
+        match rng.below(3) {
+            0 => ComponentValue::new(1, &components::Soldier),
+            1 => ComponentValue::new(2, &components::Nick(rng.string())),
+            _ => ComponentValue::new(3, &components::Position::new(rng.f32(), rng.f32())),
+        }
+    }
+
+    fn random_component_id(rng: &mut Rng) -> ComponentId {
+        1 + rng.below(MAX_COMPONENT_ID as usize) as ComponentId
+    }
+
+    fn random_message(rng: &mut Rng, variant: usize) -> NetworkMessage {
+        match variant {
+            0 => NetworkMessage::ConnectionAuthorize {
+                nick: rng.string(),
+                mac: auth_mac(&rng.string(), &[rng.next() as u8; NONCE_SIZE], "nick"),
+            },
+            1 => NetworkMessage::Cvars(
+                (0..rng.below(8))
+                    .map(|_| (rng.string(), rng.string()))
+                    .collect(),
+            ),
+            2 => NetworkMessage::ControlState {
+                ack_tick: rng.below(100_000),
+                begin_tick: rng.below(100_000),
+                control: (0..rng.below(8))
+                    .map(|_| {
+                        (
+                            Control::from_bits_truncate(rng.next() as u16),
+                            Vec2::new(rng.f32(), rng.f32()),
+                        )
+                    })
+                    .collect(),
+            },
+            3 => NetworkMessage::Snapshot {
+                tick: rng.below(100_000),
+                snapshot: NetSnapshot {
+                    entities: (0..rng.below(8))
+                        .map(|_| {
+                            let components: Vec<_> = (0..1 + rng.below(3))
+                                .map(|_| random_component(rng))
+                                .collect();
+                            (rng.entity(), components)
+                        })
+                        .collect(),
+                },
+            },
+            4 => NetworkMessage::Command {
+                tick: rng.below(100_000),
+                command: if rng.below(2) == 0 {
+                    NetCommand::Command(rng.string())
+                } else {
+                    NetCommand::Output(rng.string())
+                },
+            },
+            5 => NetworkMessage::MatchState {
+                tick: rng.below(100_000),
+                state: GameState::try_from(1 + rng.below(4) as u8).unwrap(),
+                ends: rng.below(100_000),
+            },
+            6 => NetworkMessage::MapChange(rng.string()),
+            _ => NetworkMessage::GameState {
+                tick: rng.below(100_000),
+                base_tick: rng.below(100_000),
+                ack_tick: rng.below(100_000),
+                entities: (0..rng.below(8))
+                    .map(|_| {
+                        let components: Vec<_> = (0..1 + rng.below(3))
+                            .map(|_| random_component(rng))
+                            .collect();
+                        (rng.entity(), components)
+                    })
+                    .collect(),
+                removed: (0..rng.below(4))
+                    .map(|_| {
+                        let types: Vec<_> = (0..1 + rng.below(3))
+                            .map(|_| random_component_id(rng))
+                            .collect();
+                        (rng.entity(), types)
+                    })
+                    .collect(),
+                despawned: (0..rng.below(4)).map(|_| rng.entity()).collect(),
+            },
+        }
+    }
+
+    /// Aim vectors are normalized and quantized on the wire
+    fn expected(msg: &NetworkMessage) -> NetworkMessage {
+        match msg {
+            NetworkMessage::ControlState {
+                ack_tick,
+                begin_tick,
+                control,
+            } => NetworkMessage::ControlState {
+                ack_tick: *ack_tick,
+                begin_tick: *begin_tick,
+                control: control
+                    .iter()
+                    .map(|(c, v)| {
+                        let v = v.normalize_or_zero();
+                        let x = dequantize_aim(quantize_aim(v.x));
+                        let y = dequantize_aim(quantize_aim(v.y));
+                        (*c, Vec2::new(x, y))
+                    })
+                    .collect(),
+            },
+            msg => msg.clone(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut rng = Rng(0x5EED_0001);
+        for variant in 0..VARIANTS {
+            for _ in 0..ITERATIONS {
+                let msg = random_message(&mut rng, variant);
+                let data = encode_message(msg.clone());
+                assert_eq!(decode_message(&data), Ok(expected(&msg)));
+            }
+        }
+    }
+
+    #[test]
+    fn aim_quantization() {
+        assert_eq!(dequantize_aim(quantize_aim(0.)), 0.);
+        assert_eq!(dequantize_aim(quantize_aim(1.)), 1.);
+        assert_eq!(dequantize_aim(quantize_aim(-1.)), -1.);
+        assert_eq!(dequantize_aim(0), -1.);
+
+        // quantized unit vectors stay close to unit length
+        for degrees in 0..360 {
+            let (y, x) = (degrees as f32).to_radians().sin_cos();
+            let x = dequantize_aim(quantize_aim(x));
+            let y = dequantize_aim(quantize_aim(y));
+            assert!((Vec2::new(x, y).length() - 1.).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let mut rng = Rng(0x5EED_0002);
+        for variant in 0..VARIANTS {
+            for _ in 0..ITERATIONS {
+                let data = encode_message(random_message(&mut rng, variant));
+                for len in 0..data.len() {
+                    assert!(decode_message(&data[..len]).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut rng = Rng(0x5EED_0003);
+        for variant in 0..VARIANTS {
+            let mut data = encode_message(random_message(&mut rng, variant)).to_vec();
+            let len = data.len();
+            data.push(0);
+            assert_eq!(
+                decode_message(&data),
+                Err(DecodeError::TrailingBytes {
+                    offset: len,
+                    len: len + 1
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn unexpected_opcodes() {
+        for data in [
+            connection_request(),
+            connection_accept(NET_PROTOCOL_VERSION, &[0; NONCE_SIZE]),
+            connection_reject(RejectReason::BadKey),
+            connection_authorized("motd", Entity::from_bits(1 << 32 | 7).unwrap()),
+            connection_ready(),
+            connection_disconnect(DisconnectReason::Quit),
+            connection_kick(DisconnectReason::Kicked, "bye"),
+            ping(1),
+            pong(1),
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                decode_message(data),
+                Err(DecodeError::UnexpectedOpcode(_))
+            ));
+        }
+        assert_eq!(
+            decode_message(&[]),
+            Err(DecodeError::Truncated { offset: 0 })
+        );
+        assert_eq!(
+            decode_message(&[0xFF]),
+            Err(DecodeError::UnknownOpcode(0xFF))
+        );
+        assert!(!packet_verify(&[OperationCode::CCREQ_CONNECT as u8, b'S']));
+        assert!(packet_verify(&connection_request()));
+    }
+
+    #[test]
+    fn authorized() {
+        let entity = Entity::from_bits(3 << 32 | 42).unwrap();
+        let data = connection_authorized("Welcome!", entity);
+        assert_eq!(
+            decode_authorized(&data),
+            Ok(("Welcome!".to_string(), entity))
+        );
+        for len in 0..data.len() {
+            assert!(decode_authorized(&data[..len]).is_err());
+        }
+        assert_eq!(
+            decode_authorized(&connection_ready()),
+            Err(DecodeError::UnexpectedOpcode(OperationCode::CCREQ_READY))
+        );
+
+        let motd = "ż".repeat(200);
+        let (decoded, _) = decode_authorized(&connection_authorized(&motd, entity)).unwrap();
+        assert_eq!(decoded, "ż".repeat(127));
+    }
+
+    #[test]
+    fn handshake() {
+        let current = VersionInfo::current();
+        let data = connection_request();
+        assert!(packet_verify(&data));
+        assert_eq!(decode_request(&data), Ok(current.clone()));
+
+        let nonce = [0xA5; NONCE_SIZE];
+        let data = connection_accept(2, &nonce);
+        assert!(packet_verify(&data));
+        assert_eq!(decode_accept(&data), Ok((2, nonce, current.build.clone())));
+        for len in 0..data.len() {
+            assert!(decode_accept(&data[..len]).is_err());
+        }
+
+        let data = connection_reject(RejectReason::Banned);
+        assert!(packet_verify(&data));
+        assert_eq!(decode_reject(&data), Ok((RejectReason::Banned, None)));
+        let mut invalid = data.to_vec();
+        *invalid.last_mut().unwrap() = 0xFF;
+        assert_eq!(
+            decode_reject(&invalid),
+            Err(DecodeError::InvalidValue { offset: 5 })
+        );
+        assert_eq!(
+            decode_reject(&connection_reject(RejectReason::VersionMismatch)),
+            Ok((RejectReason::VersionMismatch, Some(current)))
+        );
+        assert_eq!(
+            decode_reject(&connection_accept(2, &nonce)),
+            Err(DecodeError::UnexpectedOpcode(OperationCode::CCREP_ACCEPT))
+        );
+    }
+
+    #[test]
+    fn disconnect() {
+        assert_eq!(
+            decode_disconnect(&connection_disconnect(DisconnectReason::Quit)),
+            Ok((DisconnectReason::Quit, String::new()))
+        );
+        let data = connection_kick(DisconnectReason::Banned, "cheating");
+        assert_eq!(
+            decode_disconnect(&data),
+            Ok((DisconnectReason::Banned, "cheating".to_string()))
+        );
+        for len in 0..data.len() {
+            assert!(decode_disconnect(&data[..len]).is_err());
+        }
+        assert_eq!(
+            decode_disconnect(&[OperationCode::CCREP_KICK as u8, 0, 0]),
+            Err(DecodeError::InvalidValue { offset: 1 })
+        );
+    }
+
+    #[test]
+    fn server_browser() {
+        let status = ServerStatus {
+            name: "Soldank \u{17C}".to_string(),
+            map: "ctf_Ash".to_string(),
+            players: 3,
+            max_players: 16,
+            version: VersionInfo::current(),
+        };
+
+        let data = master_heartbeat(&status);
+        assert!(packet_verify(&data));
+        assert_eq!(decode_heartbeat(&data), Ok(status.clone()));
+        for len in 0..data.len() {
+            assert!(decode_heartbeat(&data[..len]).is_err());
+        }
+        assert_eq!(
+            decode_server_info(&server_info(&status)),
+            Ok(status.clone())
+        );
+        assert!(decode_server_info(&data).is_err());
+        assert!(packet_verify(&server_info_request()));
+        assert!(packet_verify(&master_list_request()));
+
+        assert_eq!(
+            master_list(&[])
+                .iter()
+                .map(|data| decode_master_list(data))
+                .collect::<Vec<_>>(),
+            vec![Ok(Vec::new())]
+        );
+        let servers: Vec<(SocketAddr, ServerStatus)> = (0..100u16)
+            .map(|n| {
+                let addr = if n % 2 == 0 {
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, n as u8)), 12351 + n)
+                } else {
+                    SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), n)
+                };
+                (addr, status.clone())
+            })
+            .collect();
+        let datagrams = master_list(&servers);
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|data| data.len() <= MAX_PACKET_SIZE));
+        let decoded: Vec<(SocketAddr, ServerStatus)> = datagrams
+            .iter()
+            .flat_map(|data| decode_master_list(data).unwrap())
+            .collect();
+        assert_eq!(decoded, servers);
+        let data = &datagrams[0];
+        assert!(decode_master_list(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn server_query_reply() {
+        let mut info = QueryInfo {
+            name: "Soldank".to_string(),
+            motd: "Welcome!".to_string(),
+            map: "ctf_Ash".to_string(),
+            game_state: GameState::InGame,
+            max_players: 16,
+            version: VersionInfo::current(),
+            players: vec![
+                PlayerInfo {
+                    nick: "Major".to_string(),
+                    score: 10,
+                    ping: 42,
+                },
+                PlayerInfo {
+                    nick: "\u{17C}o\u{142}nierz".to_string(),
+                    score: -2,
+                    ping: 120,
+                },
+            ],
+            rules: vec![("phys.gravity".to_string(), "0.06".to_string())],
+        };
+
+        assert!(packet_verify(&server_query_request()));
+        let data = server_query(&info);
+        assert!(packet_verify(&data));
+        assert_eq!(decode_server_query(&data), Ok(info.clone()));
+        for len in 0..data.len() {
+            assert!(decode_server_query(&data[..len]).is_err());
+        }
+
+        // rules which do not fit are left out
+        info.rules = (0..100)
+            .map(|n| (format!("rule.{}", n), "x".repeat(20)))
+            .collect();
+        let data = server_query(&info);
+        assert!(data.len() <= MAX_PACKET_SIZE);
+        let decoded = decode_server_query(&data).unwrap();
+        assert_eq!(decoded.players, info.players);
+        assert!(!decoded.rules.is_empty());
+        assert_eq!(decoded.rules[..], info.rules[..decoded.rules.len()]);
+    }
+
+    #[test]
+    fn ping_pong() {
+        assert_eq!(decode_ping(&ping(7)), Ok((OperationCode::STT_PING, 7)));
+        assert_eq!(
+            decode_ping(&pong(0xBEEF)),
+            Ok((OperationCode::STT_PONG, 0xBEEF))
+        );
+        assert_eq!(
+            decode_ping(&ping(1)[..2]),
+            Err(DecodeError::Truncated { offset: 1 })
+        );
+        assert!(decode_ping(&connection_ready()).is_err());
+    }
+
+    #[test]
+    fn negotiate() {
+        let version = |min, max| VersionInfo {
+            min,
+            max,
+            build: String::new(),
+        };
+        assert_eq!(version(1, 3).negotiate(&version(2, 5)), Some(3));
+        assert_eq!(version(2, 5).negotiate(&version(1, 3)), Some(3));
+        assert_eq!(version(3, 3).negotiate(&version(3, 3)), Some(3));
+        assert_eq!(version(1, 2).negotiate(&version(3, 4)), None);
+        assert!(version(1, 3).supports(2));
+        assert!(!version(1, 3).supports(4));
+
+        let mut build = String::new();
+        (0..200).for_each(|_| build.push('\u{17C}'));
+        let mut msg = Vec::new();
+        encode_short_string(&mut msg, &build);
+        assert_eq!(msg[0], 254);
+        assert_eq!(Reader::new(&msg).short_string().unwrap().len(), 254);
+    }
+
+    #[test]
+    fn fuzz() {
+        let mut rng = Rng(0x5EED_0004);
+        for variant in 0..VARIANTS {
+            for _ in 0..ITERATIONS * 4 {
+                let mut data = encode_message(random_message(&mut rng, variant)).to_vec();
+                for _ in 0..1 + rng.below(4) {
+                    let i = rng.below(data.len());
+                    data[i] = rng.next() as u8;
+                }
+                // must not panic, result does not matter
+                let _ = decode_message(&data);
+                let _ = decode_fragment(&data);
+                let _ = packet_verify(&data);
+            }
+        }
+        for _ in 0..ITERATIONS * 16 {
+            let data: Vec<u8> = (0..rng.below(64)).map(|_| rng.next() as u8).collect();
+            let _ = decode_message(&data);
+            let _ = decode_fragment(&data);
+            let _ = packet_verify(&data);
+        }
+    }
+
+    #[test]
+    fn fragments() {
+        let mut rng = Rng(0x5EED_0005);
+        let msg = NetworkMessage::GameState {
+            tick: 42,
+            base_tick: 0,
+            ack_tick: 40,
+            entities: (0..1000)
+                .map(|_| {
+                    let nick = components::Nick(rng.string());
+                    let pos = components::Position::new(rng.f32(), rng.f32());
+                    (
+                        rng.entity(),
+                        vec![ComponentValue::new(2, &nick), ComponentValue::new(3, &pos)],
+                    )
+                })
+                .collect(),
+            removed: HashMap::new(),
+            despawned: Vec::new(),
+        };
+
+        let mut fragments = encode_message_fragmented(msg.clone()).unwrap();
+        assert!(fragments.len() > 1);
+        assert!(fragments.iter().all(|data| data.len() <= MAX_PACKET_SIZE));
+
+        fragments.reverse();
+        let mut buffer = FragmentBuffer::default();
+        let (last, rest) = fragments.split_last().unwrap();
+        for data in rest {
+            assert_eq!(buffer.insert(data), None);
+        }
+        let data = buffer.insert(last).unwrap();
+        assert_eq!(decode_message(&data), Ok(msg));
+    }
+
+    /// `GameState` with a single component of given size, so it encodes to a known length
+    fn sized_message(tick: usize, size: usize) -> NetworkMessage {
+        let mut entities = HashMap::new();
+        entities.insert(
+            Entity::from_bits(1 << 32 | 1).unwrap(),
+            vec![ComponentValue {
+                id: 2,
+                data: vec![0xAA; size],
+            }],
+        );
+        NetworkMessage::GameState {
+            tick,
+            base_tick: 0,
+            ack_tick: 0,
+            entities,
+            removed: HashMap::new(),
+            despawned: Vec::new(),
+        }
+    }
+
+    /// Component size making message split into exactly `count` fragments
+    fn size_for_fragments(count: usize) -> usize {
+        let overhead = encode_message(sized_message(1, 0)).len() + 8; // varint length growth
+        count * MAX_FRAGMENT_PAYLOAD - overhead
+    }
+
+    #[test]
+    fn fragments_limit() {
+        let msg = sized_message(7, size_for_fragments(MAX_FRAGMENTS));
+        let fragments = encode_message_fragmented(msg.clone()).unwrap();
+        assert_eq!(fragments.len(), MAX_FRAGMENTS);
+        assert!(fragments.iter().all(|data| data.len() <= MAX_PACKET_SIZE));
+
+        let mut buffer = FragmentBuffer::default();
+        let (last, rest) = fragments.split_last().unwrap();
+        for data in rest {
+            assert_eq!(buffer.insert(data), None);
+        }
+        assert_eq!(decode_message(&buffer.insert(last).unwrap()), Ok(msg));
+
+        let msg = sized_message(8, size_for_fragments(MAX_FRAGMENTS + 1));
+        let size = encode_message(msg.clone()).len();
+        assert_eq!(encode_message_fragmented(msg), Err(MessageTooLarge(size)));
+
+        // messages that fit a single packet are not fragmented
+        let fragments = encode_message_fragmented(sized_message(9, 16)).unwrap();
+        assert_eq!(fragments.len(), 1);
+        assert_ne!(fragments[0][0], OperationCode::STT_FRAGMENT as u8);
+    }
+
+    #[test]
+    fn fragments_out_of_order() {
+        let mut rng = Rng(0x5EED_0006);
+        let mut buffer = FragmentBuffer::default();
+
+        let first = sized_message(10, size_for_fragments(5));
+        let second = sized_message(11, size_for_fragments(3));
+        let mut first_fragments = encode_message_fragmented(first.clone()).unwrap();
+        let mut second_fragments = encode_message_fragmented(second.clone()).unwrap();
+        assert_eq!(first_fragments.len(), 5);
+        assert_eq!(second_fragments.len(), 3);
+
+        // shuffle fragments of both messages, interleaving them
+        for fragments in [&mut first_fragments, &mut second_fragments].iter_mut() {
+            for i in (1..fragments.len()).rev() {
+                fragments.swap(i, rng.below(i + 1));
+            }
+        }
+        let last_first = first_fragments.pop().unwrap();
+        for data in first_fragments
+            .iter()
+            .chain(second_fragments.iter().take(2))
+        {
+            assert_eq!(buffer.insert(data), None);
+        }
+        // duplicate does not complete the message
+        assert_eq!(buffer.insert(&first_fragments[0]), None);
+        assert_eq!(
+            decode_message(&buffer.insert(&last_first).unwrap()),
+            Ok(first)
+        );
+        assert_eq!(
+            decode_message(&buffer.insert(&second_fragments[2]).unwrap()),
+            Ok(second)
+        );
+
+        // fragments of already completed tick are stale
+        assert_eq!(buffer.insert(&last_first), None);
+    }
 }
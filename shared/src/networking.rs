@@ -1,9 +1,51 @@
 use crate::{
+    constants::MASTER_PORT,
+    math::Vec2,
+    messages,
     physics::{self as physics, PhysicsEngine},
+    replication::ComponentValue,
     world::World,
 };
-use nanoserde::{DeBin, SerBin};
-use std::{fmt::Debug, net::SocketAddr, time::Instant};
+use hecs::Entity;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    hash::Hash,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+mod channel;
+pub use channel::*;
+
+const MAX_PENDING_FRAGMENTED: usize = 8;
+const MAX_INTERPOLATION_SNAPSHOTS: usize = 32;
+const SIM_REORDER_DELAY: u32 = 50; // millis added to packets picked for reordering
+const MAX_PENDING_PINGS: usize = 16;
+const RTT_SMOOTHING: f32 = 1. / 8.; // as TCP smoothed RTT
+const JITTER_SMOOTHING: f32 = 1. / 16.; // as RFC 3550 interarrival jitter
+const LOSS_SMOOTHING: f32 = 1. / 16.;
+
+/// Resolves `net.master` address, `MASTER_PORT` is used unless given
+pub fn resolve_master(master: &str) -> Option<SocketAddr> {
+    let addrs = if master.contains(':') {
+        master.to_socket_addrs()
+    } else {
+        (master, MASTER_PORT).to_socket_addrs()
+    };
+    match addrs.map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => Some(addr),
+        Ok(None) => {
+            log::error!("Cannot resolve master server `{}`", master);
+            None
+        }
+        Err(error) => {
+            log::error!("Cannot resolve master server `{}`: {}", master, error);
+            None
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct PacketStats {
@@ -13,6 +55,20 @@ pub struct PacketStats {
     pub bytes_rx: usize,
     pub last_tx: Instant,
     pub last_rx: Instant,
+    /// smoothed round trip time, millis
+    pub rtt: f32,
+    /// mean deviation between consecutive round trip times, millis
+    pub jitter: f32,
+    /// smoothed ping loss, percent
+    pub loss: f32,
+    /// bytes per second, measured over last second
+    pub rate_tx: f32,
+    pub rate_rx: f32,
+    last_rtt: Option<f32>,
+    last_ping: Option<Instant>,
+    ping_sequence: u16,
+    pings: VecDeque<(u16, Instant)>,
+    rate_window: (Instant, usize, usize),
 }
 
 impl Default for PacketStats {
@@ -24,6 +80,16 @@ impl Default for PacketStats {
             bytes_rx: Default::default(),
             last_tx: Instant::now(),
             last_rx: Instant::now(),
+            rtt: Default::default(),
+            jitter: Default::default(),
+            loss: Default::default(),
+            rate_tx: Default::default(),
+            rate_rx: Default::default(),
+            last_rtt: None,
+            last_ping: None,
+            ping_sequence: 0,
+            pings: VecDeque::new(),
+            rate_window: (Instant::now(), 0, 0),
         }
     }
 }
@@ -33,11 +99,352 @@ impl PacketStats {
         self.last_tx = Instant::now();
         self.packets_tx += 1;
         self.bytes_tx += num_bytes;
+        self.update_rates();
     }
     pub fn add_rx(&mut self, num_bytes: usize) {
         self.last_rx = Instant::now();
         self.packets_rx += 1;
         self.bytes_rx += num_bytes;
+        self.update_rates();
+    }
+
+    fn update_rates(&mut self) {
+        let (since, bytes_tx, bytes_rx) = self.rate_window;
+        let elapsed = since.elapsed().as_secs_f32();
+        if elapsed >= 1. {
+            self.rate_tx = (self.bytes_tx - bytes_tx) as f32 / elapsed;
+            self.rate_rx = (self.bytes_rx - bytes_rx) as f32 / elapsed;
+            self.rate_window = (Instant::now(), self.bytes_tx, self.bytes_rx);
+        }
+    }
+
+    pub fn add_rtt(&mut self, rtt: Duration) {
+        let sample = rtt.as_secs_f32() * 1000.;
+        match self.last_rtt {
+            Some(last) => {
+                self.rtt += (sample - self.rtt) * RTT_SMOOTHING;
+                self.jitter += ((sample - last).abs() - self.jitter) * JITTER_SMOOTHING;
+            }
+            None => self.rtt = sample,
+        }
+        self.last_rtt = Some(sample);
+    }
+
+    pub fn add_loss(&mut self, lost: usize, delivered: usize) {
+        for _ in 0..lost {
+            self.loss += (100. - self.loss) * LOSS_SMOOTHING;
+        }
+        for _ in 0..delivered {
+            self.loss -= self.loss * LOSS_SMOOTHING;
+        }
+    }
+
+    /// Sequence number of next ping to send, if `interval` millis passed since the last one
+    pub fn ping(&mut self, interval: u32) -> Option<u16> {
+        if interval == 0
+            || matches!(self.last_ping, Some(last) if last.elapsed().as_millis() < interval as u128)
+        {
+            return None;
+        }
+        let now = Instant::now();
+        self.last_ping = Some(now);
+        self.ping_sequence = self.ping_sequence.wrapping_add(1);
+        self.pings.push_back((self.ping_sequence, now));
+        if self.pings.len() > MAX_PENDING_PINGS {
+            self.pings.pop_front();
+            self.add_loss(1, 0);
+        }
+        Some(self.ping_sequence)
+    }
+
+    /// Accounts answer to ping `sequence` - pings sent before it and not answered are lost
+    pub fn pong(&mut self, sequence: u16) {
+        if let Some(index) = self.pings.iter().position(|(s, _)| *s == sequence) {
+            let (_, sent) = self.pings.drain(..=index).next_back().unwrap();
+            self.add_rtt(sent.elapsed());
+            self.add_loss(index, 1);
+        }
+    }
+}
+
+/// Network conditions simulated on outgoing packets, exposed as `net.sim_*` cvars
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SimConditions {
+    pub latency: u32,   // millis
+    pub jitter: u32,    // millis
+    pub loss: f32,      // percent
+    pub duplicate: f32, // percent
+    pub reorder: f32,   // percent
+    pub seed: u64,
+}
+
+/// Delays, drops, duplicates and reorders packets to mimic bad network on localhost
+///
+/// Random decisions come from a generator seeded with `SimConditions::seed`,
+/// so a test session can be repeated exactly.
+#[derive(Debug)]
+pub struct NetworkSimulator<T> {
+    conditions: Arc<RwLock<SimConditions>>,
+    seed: u64,
+    rng: u64,
+    sequence: usize,
+    queue: Vec<(Instant, usize, T)>,
+}
+
+impl<T: Clone> NetworkSimulator<T> {
+    pub fn new(conditions: Arc<RwLock<SimConditions>>) -> Self {
+        let seed = conditions.read().unwrap().seed;
+        Self {
+            conditions,
+            seed,
+            rng: seed,
+            sequence: 0,
+            queue: Vec::new(),
+        }
+    }
+
+    /// Queues `packet` for delivery or drops it, according to current conditions
+    pub fn send(&mut self, now: Instant, packet: T) {
+        let conditions = *self.conditions.read().unwrap();
+        if conditions.seed != self.seed {
+            self.seed = conditions.seed;
+            self.rng = conditions.seed;
+        }
+
+        if self.chance(conditions.loss) {
+            log::trace!("Simulated packet loss");
+            return;
+        }
+        let copies = if self.chance(conditions.duplicate) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut delay = conditions.latency as f32 + conditions.jitter as f32 * self.random();
+            if self.chance(conditions.reorder) {
+                delay += SIM_REORDER_DELAY as f32;
+            }
+            self.sequence += 1;
+            self.queue.push((
+                now + Duration::from_micros((delay * 1000.) as u64),
+                self.sequence,
+                packet.clone(),
+            ));
+        }
+    }
+
+    /// Packets due for delivery at `now`, in delivery order
+    pub fn poll(&mut self, now: Instant) -> Vec<T> {
+        let (mut due, pending): (Vec<_>, Vec<_>) =
+            self.queue.drain(..).partition(|(at, _, _)| *at <= now);
+        self.queue = pending;
+        due.sort_by_key(|&(at, sequence, _)| (at, sequence));
+        due.into_iter().map(|(_, _, packet)| packet).collect()
+    }
+
+    /// Uniformly distributed in `[0, 1)` - splitmix64
+    fn random(&mut self) -> f32 {
+        self.rng = self.rng.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn chance(&mut self, percent: f32) -> bool {
+        percent > 0. && self.random() * 100. < percent
+    }
+}
+
+/// Reassembles messages split into `STT_FRAGMENT` datagrams, keyed by tick
+#[derive(Debug, Default)]
+pub struct FragmentBuffer {
+    pending: HashMap<usize, Vec<Option<Vec<u8>>>>,
+    last_completed: Option<usize>,
+}
+
+impl FragmentBuffer {
+    /// Stores received fragment and returns whole message data once all its fragments arrived
+    pub fn insert(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let fragment = match messages::decode_fragment(data) {
+            Ok(fragment) => fragment,
+            Err(error) => {
+                log::error!("Cannot decode fragment: {}", error);
+                return None;
+            }
+        };
+        let tick = fragment.tick;
+        let index = fragment.index as usize;
+        let count = fragment.count as usize;
+
+        if matches!(self.last_completed, Some(last) if tick <= last) {
+            log::debug!(
+                "Dropping stale fragment {}/{} of tick {}",
+                index,
+                count,
+                tick
+            );
+            return None;
+        }
+        if count == 0 || count > messages::MAX_FRAGMENTS || index >= count {
+            log::error!("Invalid fragment {}/{} of tick {}", index, count, tick);
+            return None;
+        }
+
+        let fragments = self
+            .pending
+            .entry(tick)
+            .or_insert_with(|| vec![None; count]);
+        if fragments.len() != count {
+            log::error!(
+                "Fragments count mismatch for tick {}: {} != {}",
+                tick,
+                count,
+                fragments.len()
+            );
+            return None;
+        }
+        fragments[index] = Some(fragment.payload);
+
+        if fragments.iter().all(Option::is_some) {
+            let fragments = self.pending.remove(&tick).unwrap();
+            self.last_completed = Some(tick);
+            self.pending.retain(|&t, _| t > tick);
+            return Some(fragments.into_iter().flatten().flatten().collect());
+        }
+
+        while self.pending.len() > MAX_PENDING_FRAGMENTED {
+            let oldest = *self.pending.keys().min().unwrap();
+            self.pending.remove(&oldest);
+        }
+
+        None
+    }
+}
+
+/// Token bucket per key, limiting how often unauthenticated peers get answered
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    /// tokens refilled per second
+    rate: f32,
+    /// max tokens stored
+    burst: f32,
+    buckets: HashMap<K, (Instant, f32)>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(rate: f32, burst: f32) -> Self {
+        RateLimiter {
+            rate,
+            burst,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token from `key` bucket, if available
+    pub fn allow(&mut self, now: Instant, key: K) -> bool {
+        let (rate, burst) = (self.rate, self.burst);
+        let (last, tokens) = self.buckets.entry(key).or_insert((now, burst));
+        *tokens = f32::min(
+            burst,
+            *tokens + now.saturating_duration_since(*last).as_secs_f32() * rate,
+        );
+        *last = now;
+        if *tokens >= 1. {
+            *tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drops buckets refilled completely, as they are no different from new ones
+    pub fn expire(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, (last, tokens)| {
+            *tokens + now.saturating_duration_since(*last).as_secs_f32() * rate < burst
+        });
+    }
+}
+
+pub type PositionsSnapshot = HashMap<Entity, Vec2>;
+
+/// Timestamped positions of remote entities, rendered some time in the past
+/// to smooth out the gaps between received states
+#[derive(Debug, Default)]
+pub struct InterpolationBuffer {
+    snapshots: VecDeque<(f64, PositionsSnapshot)>,
+}
+
+impl InterpolationBuffer {
+    pub fn latest(&self) -> Option<&PositionsSnapshot> {
+        self.snapshots.back().map(|(_, snapshot)| snapshot)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Stores snapshot taken at `time` (seconds). Out of order snapshots are dropped.
+    pub fn push(&mut self, time: f64, snapshot: PositionsSnapshot) {
+        if matches!(self.snapshots.back(), Some((last, _)) if time <= *last) {
+            return;
+        }
+        self.snapshots.push_back((time, snapshot));
+        while self.snapshots.len() > MAX_INTERPOLATION_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Positions at `time`, interpolated between two surrounding snapshots.
+    ///
+    /// Past the newest snapshot positions are extrapolated from last two snapshots,
+    /// for no more than `max_extrapolation` seconds.
+    pub fn sample(&mut self, time: f64, max_extrapolation: f64) -> PositionsSnapshot {
+        // keep single snapshot older than `time` - it is the start of interpolated span
+        while self.snapshots.len() > 2 && self.snapshots[1].0 <= time {
+            self.snapshots.pop_front();
+        }
+
+        let next = match self.snapshots.iter().position(|(t, _)| *t > time) {
+            Some(next) => next,
+            None => return self.extrapolate(time, max_extrapolation),
+        };
+        if next == 0 {
+            return self.snapshots[0].1.clone();
+        }
+
+        let (t0, from) = &self.snapshots[next - 1];
+        let (t1, to) = &self.snapshots[next];
+        let alpha = ((time - t0) / (t1 - t0)) as f32;
+        to.iter()
+            .map(|(&entity, &position)| match from.get(&entity) {
+                Some(&start) => (entity, start.lerp(position, alpha)),
+                None => (entity, position),
+            })
+            .collect()
+    }
+
+    fn extrapolate(&self, time: f64, max_extrapolation: f64) -> PositionsSnapshot {
+        let len = self.snapshots.len();
+        if len == 0 {
+            return PositionsSnapshot::new();
+        }
+        let (t1, last) = &self.snapshots[len - 1];
+        if len == 1 || max_extrapolation <= 0. {
+            return last.clone();
+        }
+
+        let (t0, previous) = &self.snapshots[len - 2];
+        let alpha = ((time - t1).min(max_extrapolation) / (t1 - t0)) as f32;
+        last.iter()
+            .map(|(&entity, &position)| match previous.get(&entity) {
+                Some(&start) => (entity, position + (position - start) * alpha),
+                None => (entity, position),
+            })
+            .collect()
     }
 }
 
@@ -47,13 +454,19 @@ pub struct GameWorld {
     physics: PhysicsEngine,
 }
 
-#[derive(Debug, Clone, SerBin, DeBin)]
+/// Command sent by client to be executed on server, if permitted
+#[derive(Debug, Clone, PartialEq)]
 pub enum NetCommand {
     Command(String),
+    /// Result of command execution, sent back to the client that asked for it
+    Output(String),
 }
 
-#[derive(Debug, Default, Clone, SerBin, DeBin)]
-pub struct NetSnapshot {}
+/// All replicated entities with all their components
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NetSnapshot {
+    pub entities: HashMap<Entity, Vec<ComponentValue>>,
+}
 
 // impl OrbWorld for GameWorld {
 //     type ClientId = SocketAddr;
@@ -140,3 +553,142 @@ impl GameWorld {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::vec2;
+
+    #[test]
+    fn interpolation() {
+        let entity = Entity::from_bits(1 << 32 | 1).unwrap();
+        let other = Entity::from_bits(1 << 32 | 2).unwrap();
+        let mut buffer = InterpolationBuffer::default();
+        assert!(buffer.sample(1., 0.1).is_empty());
+
+        buffer.push(1., [(entity, vec2(0., 0.))].iter().copied().collect());
+        assert_eq!(buffer.sample(0.5, 0.1)[&entity], vec2(0., 0.));
+        assert_eq!(buffer.sample(2., 0.1)[&entity], vec2(0., 0.));
+
+        buffer.push(
+            2.,
+            [(entity, vec2(10., -10.)), (other, vec2(1., 1.))]
+                .iter()
+                .copied()
+                .collect(),
+        );
+        buffer.push(1.5, PositionsSnapshot::new()); // out of order
+        let positions = buffer.sample(1.25, 0.1);
+        assert_eq!(positions[&entity], vec2(2.5, -2.5));
+        assert_eq!(positions[&other], vec2(1., 1.));
+
+        // extrapolation is capped
+        assert_eq!(buffer.sample(2.1, 0.5)[&entity], vec2(11., -11.));
+        assert_eq!(buffer.sample(3., 0.5)[&entity], vec2(15., -15.));
+        assert_eq!(buffer.sample(3., 0.)[&entity], vec2(10., -10.));
+    }
+
+    #[test]
+    fn stats() {
+        let mut stats = PacketStats::default();
+        stats.add_rtt(Duration::from_millis(100));
+        assert_eq!((stats.rtt, stats.jitter), (100., 0.));
+        stats.add_rtt(Duration::from_millis(180));
+        assert_eq!((stats.rtt, stats.jitter), (110., 5.));
+
+        assert_eq!(stats.ping(0), None);
+        let first = stats.ping(1000).unwrap();
+        assert_eq!(stats.ping(1000), None);
+        stats.last_ping = None;
+        let second = stats.ping(1000).unwrap();
+        assert_ne!(first, second);
+
+        stats.pong(second); // first one lost
+        assert_eq!(stats.loss, 100. / 16. * 15. / 16.);
+        stats.pong(first); // too late
+        assert_eq!(stats.loss, 100. / 16. * 15. / 16.);
+        assert!(stats.pings.is_empty());
+
+        for _ in 0..=MAX_PENDING_PINGS {
+            stats.last_ping = None;
+            stats.ping(1000);
+        }
+        assert_eq!(stats.pings.len(), MAX_PENDING_PINGS);
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let now = Instant::now();
+        let ms = |millis| now + Duration::from_millis(millis);
+        let mut limiter = RateLimiter::new(2., 3.);
+
+        assert!((0..3).all(|_| limiter.allow(now, 1)));
+        assert!(!limiter.allow(now, 1));
+        assert!(limiter.allow(now, 2));
+        assert!(!limiter.allow(ms(400), 1));
+        assert!(limiter.allow(ms(500), 1));
+        assert!(!limiter.allow(ms(500), 1));
+
+        limiter.expire(ms(1000));
+        assert_eq!(limiter.buckets.len(), 1);
+        limiter.expire(ms(2000));
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn simulator() {
+        let now = Instant::now();
+        let ms = |millis| now + Duration::from_millis(millis);
+        let conditions = Arc::new(RwLock::new(SimConditions::default()));
+        let mut simulator = NetworkSimulator::new(conditions.clone());
+
+        simulator.send(now, 1);
+        simulator.send(now, 2);
+        assert_eq!(simulator.poll(now), vec![1, 2]);
+
+        conditions.write().unwrap().latency = 100;
+        simulator.send(now, 3);
+        simulator.send(ms(10), 4);
+        assert!(simulator.poll(ms(99)).is_empty());
+        assert_eq!(simulator.poll(ms(100)), vec![3]);
+        assert_eq!(simulator.poll(ms(110)), vec![4]);
+
+        conditions.write().unwrap().loss = 100.;
+        simulator.send(now, 5);
+        assert!(simulator.poll(ms(1000)).is_empty());
+
+        *conditions.write().unwrap() = SimConditions {
+            duplicate: 100.,
+            ..Default::default()
+        };
+        simulator.send(now, 6);
+        assert_eq!(simulator.poll(now), vec![6, 6]);
+
+        *conditions.write().unwrap() = SimConditions {
+            reorder: 100.,
+            ..Default::default()
+        };
+        simulator.send(now, 7);
+        conditions.write().unwrap().reorder = 0.;
+        simulator.send(now, 8);
+        assert_eq!(simulator.poll(ms(SIM_REORDER_DELAY as u64)), vec![8, 7]);
+
+        // same seed - same decisions
+        let lossy = SimConditions {
+            loss: 50.,
+            jitter: 20,
+            seed: 1337,
+            ..Default::default()
+        };
+        let run = || {
+            let mut simulator = NetworkSimulator::new(Arc::new(RwLock::new(lossy)));
+            for packet in 0..100 {
+                simulator.send(now, packet);
+            }
+            simulator.poll(ms(20))
+        };
+        let delivered = run();
+        assert!(!delivered.is_empty() && delivered.len() < 100);
+        assert_eq!(delivered, run());
+    }
+}
@@ -0,0 +1,639 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::constants::MAX_PACKET_SIZE;
+
+const HEADER_SIZE: usize = 8; // sequence, ack, ack bits
+const FRAGMENT_HEADER_SIZE: usize = 5; // channel, message id, fragment index, fragments count
+const FRAGMENT_SIZE: usize = MAX_PACKET_SIZE - HEADER_SIZE - FRAGMENT_HEADER_SIZE;
+const MAX_FRAGMENTS: usize = u8::MAX as usize;
+const MAX_PENDING_MESSAGES: usize = 64; // incomplete messages kept for reassembly, per peer
+const MAX_DELIVERED_HISTORY: usize = 1024; // reliable message ids remembered for dropping duplicates
+const MAX_ORDERED_BUFFER: u16 = 256; // ordered messages held back waiting for a missing one, per peer
+const RESEND_TIMEOUT: Duration = Duration::from_millis(200);
+/// datagram sequence never used by channels, marking connectionless datagrams
+const OUT_OF_BAND_SEQUENCE: u16 = u16::MAX;
+
+/// Wraps payload sent outside of any connection, e.g. server browser queries
+pub fn out_of_band(payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(payload.len() + 2);
+    datagram.extend_from_slice(&OUT_OF_BAND_SEQUENCE.to_le_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// Payload of connectionless datagram, `None` if it belongs to `Channels`
+pub fn decode_out_of_band(datagram: &[u8]) -> Option<&[u8]> {
+    if datagram.get(0..2) == Some(&OUT_OF_BAND_SEQUENCE.to_le_bytes()[..]) {
+        Some(&datagram[2..])
+    } else {
+        None
+    }
+}
+
+/// Delivery guarantees of a packet
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// may be lost, duplicated or arrive out of order
+    Unreliable,
+    /// arrives exactly once, in any order
+    ReliableUnordered,
+    /// arrives exactly once, in the order it was sent
+    ReliableOrdered,
+}
+
+impl Channel {
+    fn id(self) -> u8 {
+        match self {
+            Channel::Unreliable => 0,
+            Channel::ReliableUnordered => 1,
+            Channel::ReliableOrdered => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Channel> {
+        match id {
+            0 => Some(Channel::Unreliable),
+            1 => Some(Channel::ReliableUnordered),
+            2 => Some(Channel::ReliableOrdered),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self.id() as usize
+    }
+
+    fn is_reliable(self) -> bool {
+        self != Channel::Unreliable
+    }
+}
+
+/// Payload to be delivered to `addr` over `channel`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    addr: SocketAddr,
+    payload: Vec<u8>,
+    channel: Channel,
+}
+
+impl Packet {
+    pub fn new(addr: SocketAddr, payload: Vec<u8>, channel: Channel) -> Packet {
+        Packet {
+            addr,
+            payload,
+            channel,
+        }
+    }
+
+    pub fn unreliable(addr: SocketAddr, payload: Vec<u8>) -> Packet {
+        Packet::new(addr, payload, Channel::Unreliable)
+    }
+
+    pub fn reliable_unordered(addr: SocketAddr, payload: Vec<u8>) -> Packet {
+        Packet::new(addr, payload, Channel::ReliableUnordered)
+    }
+
+    pub fn reliable_ordered(addr: SocketAddr, payload: Vec<u8>) -> Packet {
+        Packet::new(addr, payload, Channel::ReliableOrdered)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Fragment {
+    channel: Channel,
+    message: u16,
+    index: u8,
+    count: u8,
+    data: Vec<u8>,
+}
+
+/// `a` is more recent than `b`, taking wrap-around into account
+fn sequence_greater(a: u16, b: u16) -> bool {
+    (a.wrapping_sub(b) as i16) > 0
+}
+
+#[derive(Debug, Default)]
+struct Peer {
+    /// next datagram sequence number
+    sequence: u16,
+    /// next message id, per channel
+    messages: [u16; 3],
+    queue: VecDeque<Fragment>,
+    /// reliable fragments sent and not acknowledged yet, by datagram sequence
+    in_flight: HashMap<u16, (Instant, Fragment)>,
+
+    /// most recent datagram sequence received
+    remote_sequence: Option<u16>,
+    /// bit `n` set if datagram `remote_sequence - 1 - n` was received
+    received_bits: u32,
+    ack_pending: bool,
+
+    /// fragments of incomplete messages, by channel and message id
+    pending: HashMap<(Channel, u16), Vec<Option<Vec<u8>>>>,
+    /// keys of `pending`, oldest first
+    pending_order: VecDeque<(Channel, u16)>,
+    /// unordered message ids delivered recently, with `delivered_order` to forget the oldest
+    delivered: HashSet<u16>,
+    delivered_order: VecDeque<u16>,
+    /// next ordered message id to be delivered
+    ordered_next: u16,
+    ordered: HashMap<u16, Vec<u8>>,
+}
+
+impl Peer {
+    fn header(&mut self) -> Vec<u8> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        if self.sequence == OUT_OF_BAND_SEQUENCE {
+            self.sequence = 0;
+        }
+
+        let mut datagram = Vec::with_capacity(MAX_PACKET_SIZE);
+        datagram.extend_from_slice(&sequence.to_le_bytes());
+        datagram.extend_from_slice(&self.remote_sequence.unwrap_or(0).to_le_bytes());
+        datagram.extend_from_slice(&self.received_bits.to_le_bytes());
+        self.ack_pending = false;
+        datagram
+    }
+
+    fn receive_sequence(&mut self, sequence: u16) {
+        match self.remote_sequence {
+            None => {
+                self.remote_sequence = Some(sequence);
+            }
+            Some(remote) if sequence_greater(sequence, remote) => {
+                let shift = sequence.wrapping_sub(remote) as u32;
+                self.received_bits = if shift > 32 {
+                    0
+                } else {
+                    ((self.received_bits as u64) << shift | 1 << (shift - 1)) as u32
+                };
+                self.remote_sequence = Some(sequence);
+            }
+            Some(remote) => {
+                let distance = remote.wrapping_sub(sequence) as u32;
+                if distance > 0 && distance <= 32 {
+                    self.received_bits |= 1 << (distance - 1);
+                }
+            }
+        }
+    }
+
+    fn receive_acks(&mut self, ack: u16, bits: u32) {
+        self.in_flight.remove(&ack);
+        for n in 0..32 {
+            if bits & (1 << n) != 0 {
+                self.in_flight.remove(&ack.wrapping_sub(n + 1));
+            }
+        }
+    }
+
+    /// Stores received fragment and returns messages it made deliverable.
+    /// Returns `None` if the fragment cannot be buffered now - its datagram must not
+    /// be acknowledged then, so the peer sends it again later.
+    fn receive_fragment(&mut self, fragment: Fragment) -> Option<Vec<Vec<u8>>> {
+        let Fragment {
+            channel,
+            message,
+            index,
+            count,
+            data,
+        } = fragment;
+
+        if self.is_delivered(channel, message) {
+            return Some(Vec::new());
+        }
+        if count == 0 || index >= count {
+            log::error!(
+                "Invalid fragment {}/{} of message {}",
+                index,
+                count,
+                message
+            );
+            return Some(Vec::new());
+        }
+        if channel == Channel::ReliableOrdered
+            && message.wrapping_sub(self.ordered_next) >= MAX_ORDERED_BUFFER
+        {
+            log::warn!("Ordered message {} too far ahead - deferring", message);
+            return None;
+        }
+
+        let data = if count == 1 {
+            data
+        } else {
+            let key = (channel, message);
+            if let Entry::Vacant(entry) = self.pending.entry(key) {
+                entry.insert(vec![None; count as usize]);
+                self.pending_order.push_back(key);
+                if self.pending.len() > MAX_PENDING_MESSAGES {
+                    let oldest = self.pending_order.pop_front().unwrap();
+                    log::warn!("Too many incomplete messages - dropping {:?}", oldest);
+                    self.pending.remove(&oldest);
+                }
+            }
+            let fragments = self.pending.get_mut(&key).unwrap();
+            if fragments.len() != count as usize {
+                log::error!("Fragments count mismatch for message {}", message);
+                return Some(Vec::new());
+            }
+            fragments[index as usize] = Some(data);
+            if !fragments.iter().all(Option::is_some) {
+                return Some(Vec::new());
+            }
+            let fragments = self.pending.remove(&key).unwrap();
+            self.pending_order.retain(|&pending| pending != key);
+            fragments.into_iter().flatten().flatten().collect()
+        };
+
+        let messages = match channel {
+            Channel::Unreliable => vec![data],
+            Channel::ReliableUnordered => {
+                self.delivered.insert(message);
+                self.delivered_order.push_back(message);
+                if self.delivered_order.len() > MAX_DELIVERED_HISTORY {
+                    let oldest = self.delivered_order.pop_front().unwrap();
+                    self.delivered.remove(&oldest);
+                }
+                vec![data]
+            }
+            Channel::ReliableOrdered => {
+                self.ordered.insert(message, data);
+                let mut messages = Vec::new();
+                while let Some(data) = self.ordered.remove(&self.ordered_next) {
+                    messages.push(data);
+                    self.ordered_next = self.ordered_next.wrapping_add(1);
+                }
+                messages
+            }
+        };
+        Some(messages)
+    }
+
+    fn is_delivered(&self, channel: Channel, message: u16) -> bool {
+        match channel {
+            Channel::Unreliable => false,
+            Channel::ReliableUnordered => self.delivered.contains(&message),
+            Channel::ReliableOrdered => {
+                !sequence_greater(message, self.ordered_next.wrapping_sub(1))
+                    || self.ordered.contains_key(&message)
+            }
+        }
+    }
+}
+
+/// Unreliable, reliable-unordered and reliable-ordered channels over plain datagrams
+///
+/// Every datagram carries its sequence number, and the latest received sequence number
+/// with a bitfield of 32 preceding ones, acknowledging datagrams received from the peer.
+/// Reliable fragments not acknowledged in time are resent in a new datagram.
+/// Messages bigger than a datagram are split into fragments and reassembled on receive.
+#[derive(Debug, Default)]
+pub struct Channels {
+    peers: HashMap<SocketAddr, Peer>,
+}
+
+impl Channels {
+    /// Queues `packet` to be sent on next `poll`
+    pub fn send(&mut self, packet: Packet) {
+        let count = packet.payload.len().div_ceil(FRAGMENT_SIZE);
+        if count > MAX_FRAGMENTS {
+            log::error!(
+                "Packet to [{}] too big: {} bytes - dropping",
+                packet.addr,
+                packet.payload.len()
+            );
+            return;
+        }
+
+        let peer = self.peers.entry(packet.addr).or_default();
+        let channel = packet.channel;
+        let message = peer.messages[channel.index()];
+        peer.messages[channel.index()] = message.wrapping_add(1);
+
+        if packet.payload.is_empty() {
+            peer.queue.push_back(Fragment {
+                channel,
+                message,
+                index: 0,
+                count: 1,
+                data: Vec::new(),
+            });
+        }
+        for (index, data) in packet.payload.chunks(FRAGMENT_SIZE).enumerate() {
+            peer.queue.push_back(Fragment {
+                channel,
+                message,
+                index: index as u8,
+                count: count as u8,
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    /// Processes datagram received from `addr`. Returns payloads of delivered packets.
+    pub fn receive(&mut self, addr: SocketAddr, datagram: &[u8]) -> Vec<Vec<u8>> {
+        if datagram.len() < HEADER_SIZE {
+            log::error!("Datagram from [{}] too short", addr);
+            return Vec::new();
+        }
+        let sequence = u16::from_le_bytes([datagram[0], datagram[1]]);
+        let ack = u16::from_le_bytes([datagram[2], datagram[3]]);
+        let bits = u32::from_le_bytes([datagram[4], datagram[5], datagram[6], datagram[7]]);
+
+        let peer = self.peers.entry(addr).or_default();
+        peer.receive_acks(ack, bits);
+
+        let data = &datagram[HEADER_SIZE..];
+        if data.is_empty() {
+            peer.receive_sequence(sequence);
+            return Vec::new(); // acknowledgement only
+        }
+        if data.len() < FRAGMENT_HEADER_SIZE {
+            log::error!("Datagram from [{}] has malformed fragment", addr);
+            return Vec::new();
+        }
+        let channel = match Channel::from_id(data[0]) {
+            Some(channel) => channel,
+            None => {
+                log::error!("Datagram from [{}] for unknown channel {}", addr, data[0]);
+                return Vec::new();
+            }
+        };
+
+        let delivered = peer.receive_fragment(Fragment {
+            channel,
+            message: u16::from_le_bytes([data[1], data[2]]),
+            index: data[3],
+            count: data[4],
+            data: data[FRAGMENT_HEADER_SIZE..].to_vec(),
+        });
+        match delivered {
+            Some(delivered) => {
+                peer.receive_sequence(sequence);
+                if channel.is_reliable() {
+                    peer.ack_pending = true;
+                }
+                delivered
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Datagrams due to be sent at `now` - queued packets, resends of unacknowledged
+    /// reliable ones and acknowledgements
+    pub fn poll(&mut self, now: Instant) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut datagrams = Vec::new();
+
+        for (&addr, peer) in self.peers.iter_mut() {
+            let mut timed_out: Vec<u16> = peer
+                .in_flight
+                .iter()
+                .filter(|(_, (sent, _))| now.duration_since(*sent) >= RESEND_TIMEOUT)
+                .map(|(&sequence, _)| sequence)
+                .collect();
+            timed_out.sort_by_key(|&sequence| sequence.wrapping_sub(peer.sequence));
+            let mut resend: VecDeque<Fragment> = timed_out
+                .into_iter()
+                .filter_map(|sequence| peer.in_flight.remove(&sequence))
+                .map(|(_, fragment)| fragment)
+                .collect();
+            resend.append(&mut peer.queue);
+
+            for fragment in resend {
+                let sequence = peer.sequence;
+                let mut datagram = peer.header();
+                datagram.push(fragment.channel.id());
+                datagram.extend_from_slice(&fragment.message.to_le_bytes());
+                datagram.push(fragment.index);
+                datagram.push(fragment.count);
+                datagram.extend_from_slice(&fragment.data);
+                datagrams.push((addr, datagram));
+
+                if fragment.channel.is_reliable() {
+                    peer.in_flight.insert(sequence, (now, fragment));
+                }
+            }
+
+            if peer.ack_pending {
+                datagrams.push((addr, peer.header()));
+            }
+        }
+
+        datagrams
+    }
+
+    /// Number of reliable fragments to `addr` waiting for acknowledgement
+    pub fn in_flight(&self, addr: &SocketAddr) -> usize {
+        self.peers.get(addr).map_or(0, |peer| peer.in_flight.len())
+    }
+
+    /// Drops channels state of peers not matching `f`
+    pub fn retain<F: FnMut(&SocketAddr) -> bool>(&mut self, mut f: F) {
+        self.peers.retain(|addr, _| f(addr));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::networking::{NetworkSimulator, SimConditions};
+    use std::sync::{Arc, RwLock};
+
+    /// Two endpoints connected by simulated network, stepped 10ms at a time
+    struct Link {
+        now: Instant,
+        a: (SocketAddr, Channels),
+        b: (SocketAddr, Channels),
+        network: NetworkSimulator<(SocketAddr, SocketAddr, Vec<u8>)>,
+    }
+
+    impl Link {
+        fn new(conditions: SimConditions) -> Link {
+            Link {
+                now: Instant::now(),
+                a: ("127.0.0.1:1".parse().unwrap(), Channels::default()),
+                b: ("127.0.0.1:2".parse().unwrap(), Channels::default()),
+                network: NetworkSimulator::new(Arc::new(RwLock::new(conditions))),
+            }
+        }
+
+        /// Payloads received by `b`
+        fn step(&mut self) -> Vec<Vec<u8>> {
+            for (to, datagram) in self.a.1.poll(self.now) {
+                self.network.send(self.now, (self.a.0, to, datagram));
+            }
+            for (to, datagram) in self.b.1.poll(self.now) {
+                self.network.send(self.now, (self.b.0, to, datagram));
+            }
+
+            let mut received = Vec::new();
+            for (from, to, datagram) in self.network.poll(self.now) {
+                if to == self.b.0 {
+                    received.extend(self.b.1.receive(from, &datagram));
+                } else {
+                    self.a.1.receive(from, &datagram);
+                }
+            }
+            self.now += Duration::from_millis(10);
+            received
+        }
+
+        fn run(&mut self, steps: usize) -> Vec<Vec<u8>> {
+            (0..steps).flat_map(|_| self.step()).collect()
+        }
+    }
+
+    #[test]
+    fn sequences() {
+        assert!(sequence_greater(1, 0));
+        assert!(sequence_greater(0, u16::MAX));
+        assert!(!sequence_greater(0, 1));
+        assert!(!sequence_greater(5, 5));
+
+        let mut peer = Peer::default();
+        peer.receive_sequence(10);
+        peer.receive_sequence(12);
+        assert_eq!(peer.remote_sequence, Some(12));
+        assert_eq!(peer.received_bits, 0b10);
+        peer.receive_sequence(11);
+        assert_eq!(peer.received_bits, 0b11);
+        peer.receive_sequence(50);
+        assert_eq!(peer.received_bits, 0);
+    }
+
+    #[test]
+    fn receive_limits() {
+        let fragment = |channel, message, index, count| Fragment {
+            channel,
+            message,
+            index,
+            count,
+            data: vec![message as u8],
+        };
+
+        let mut peer = Peer::default();
+        for message in 0..=MAX_PENDING_MESSAGES as u16 {
+            let delivered = peer.receive_fragment(fragment(Channel::Unreliable, message, 0, 2));
+            assert_eq!(delivered, Some(Vec::new()));
+        }
+        assert_eq!(peer.pending.len(), MAX_PENDING_MESSAGES);
+        assert!(!peer.pending.contains_key(&(Channel::Unreliable, 0)));
+        let delivered = peer.receive_fragment(fragment(Channel::Unreliable, 1, 1, 2));
+        assert_eq!(delivered, Some(vec![vec![1, 1]]));
+        assert_eq!(peer.pending_order.len(), MAX_PENDING_MESSAGES - 1);
+
+        let mut peer = Peer::default();
+        let far = fragment(Channel::ReliableOrdered, MAX_ORDERED_BUFFER, 0, 1);
+        assert_eq!(peer.receive_fragment(far), None);
+        let next = fragment(Channel::ReliableOrdered, 1, 0, 1);
+        assert_eq!(peer.receive_fragment(next), Some(Vec::new()));
+        let first = fragment(Channel::ReliableOrdered, 0, 0, 1);
+        assert_eq!(peer.receive_fragment(first), Some(vec![vec![0], vec![1]]));
+
+        let mut peer = Peer::default();
+        let unordered = fragment(Channel::ReliableUnordered, 5, 0, 1);
+        assert_eq!(
+            peer.receive_fragment(unordered.clone()),
+            Some(vec![vec![5]])
+        );
+        assert_eq!(peer.receive_fragment(unordered.clone()), Some(Vec::new()));
+        for message in 6..6 + MAX_DELIVERED_HISTORY as u16 {
+            peer.receive_fragment(fragment(Channel::ReliableUnordered, message, 0, 1));
+        }
+        assert_eq!(peer.delivered.len(), MAX_DELIVERED_HISTORY);
+        assert_eq!(peer.receive_fragment(unordered), Some(vec![vec![5]]));
+    }
+
+    #[test]
+    fn reliable() {
+        let lossy = SimConditions {
+            latency: 20,
+            jitter: 30,
+            loss: 30.,
+            duplicate: 10.,
+            reorder: 20.,
+            seed: 1337,
+        };
+
+        let mut link = Link::new(lossy);
+        let b = link.b.0;
+        for n in 0..100u8 {
+            link.a.1.send(Packet::reliable_ordered(b, vec![n]));
+        }
+        let received = link.run(300);
+        assert_eq!(received, (0..100u8).map(|n| vec![n]).collect::<Vec<_>>());
+        assert_eq!(link.a.1.in_flight(&b), 0);
+
+        let mut link = Link::new(lossy);
+        for n in 0..100u8 {
+            link.a.1.send(Packet::reliable_unordered(b, vec![n]));
+        }
+        let mut received = link.run(300);
+        received.sort();
+        assert_eq!(received, (0..100u8).map(|n| vec![n]).collect::<Vec<_>>());
+
+        let mut link = Link::new(SimConditions {
+            loss: 100.,
+            ..Default::default()
+        });
+        link.a.1.send(Packet::unreliable(b, vec![1]));
+        link.a.1.send(Packet::reliable_unordered(b, vec![2]));
+        assert!(link.run(10).is_empty());
+        assert_eq!(link.a.1.in_flight(&b), 1);
+    }
+
+    #[test]
+    fn out_of_band_datagrams() {
+        let mut peer = Peer {
+            sequence: OUT_OF_BAND_SEQUENCE - 1,
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            assert_eq!(decode_out_of_band(&peer.header()), None);
+        }
+        assert_eq!(peer.sequence, 2);
+
+        assert_eq!(decode_out_of_band(&out_of_band(&[1, 2])), Some(&[1, 2][..]));
+        assert_eq!(decode_out_of_band(&[0xFF]), None);
+    }
+
+    #[test]
+    fn fragmentation() {
+        let payload: Vec<u8> = (0..FRAGMENT_SIZE * 3 + 10).map(|n| n as u8).collect();
+
+        let mut link = Link::new(SimConditions {
+            reorder: 50.,
+            seed: 1,
+            ..Default::default()
+        });
+        let b = link.b.0;
+        link.a.1.send(Packet::unreliable(b, payload.clone()));
+        link.a.1.send(Packet::reliable_ordered(b, payload.clone()));
+        link.a.1.send(Packet::reliable_ordered(b, Vec::new()));
+        assert_eq!(link.run(10), vec![payload.clone(), payload, Vec::new()]);
+
+        let mut link = Link::new(SimConditions::default());
+        link.a.1.send(Packet::unreliable(
+            b,
+            vec![0; FRAGMENT_SIZE * (MAX_FRAGMENTS + 1)],
+        ));
+        assert!(link.run(10).is_empty());
+    }
+}
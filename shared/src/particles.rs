@@ -1,4 +1,5 @@
-use super::*;
+use crate::math::{vec2, Vec2};
+use gvfs::filesystem::{File, Filesystem};
 use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 
@@ -0,0 +1,189 @@
+use crate::{
+    mapfile::SpawnType,
+    math::Vec2,
+    soldier::Soldier,
+    weapons::{Weapon, WeaponKind},
+};
+use nanoserde::{DeBin, DeBinErr, SerBin};
+
+const TOUCH_RADIUS: f32 = 12.0;
+
+pub const MAX_HEALTH: f32 = 150.0;
+pub const MAX_VEST: f32 = 100.0;
+pub const MAX_GRENADES: u8 = 5;
+pub const MAX_CLUSTERS: u8 = 3;
+pub const BERSERKER_TIME: usize = 900; // ticks
+pub const PREDATOR_TIME: usize = 1500; // ticks
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PickupKind {
+    Medikit,
+    Grenades,
+    Clusters,
+    Vest,
+    Berserker,
+    Predator,
+}
+
+impl PickupKind {
+    pub const ALL: [PickupKind; 6] = [
+        PickupKind::Medikit,
+        PickupKind::Grenades,
+        PickupKind::Clusters,
+        PickupKind::Vest,
+        PickupKind::Berserker,
+        PickupKind::Predator,
+    ];
+
+    pub fn spawn_type(&self) -> SpawnType {
+        match self {
+            PickupKind::Medikit => SpawnType::Medikits,
+            PickupKind::Grenades => SpawnType::Grenades,
+            PickupKind::Clusters => SpawnType::Clusters,
+            PickupKind::Vest => SpawnType::Vest,
+            PickupKind::Berserker => SpawnType::Berserker,
+            PickupKind::Predator => SpawnType::Predator,
+        }
+    }
+
+    /// Gives kit to `soldier`, returns `false` when the soldier has no use for it
+    pub fn apply(&self, soldier: &mut Soldier) -> bool {
+        if soldier.dead_meat {
+            return false;
+        }
+
+        match self {
+            PickupKind::Medikit => {
+                if soldier.health >= MAX_HEALTH {
+                    return false;
+                }
+                soldier.health = MAX_HEALTH;
+            }
+            PickupKind::Grenades => {
+                return refill_grenades(soldier, WeaponKind::FragGrenade, MAX_GRENADES)
+            }
+            PickupKind::Clusters => {
+                return refill_grenades(soldier, WeaponKind::ClusterGrenade, MAX_CLUSTERS)
+            }
+            PickupKind::Vest => {
+                if soldier.vest >= MAX_VEST {
+                    return false;
+                }
+                soldier.vest = MAX_VEST;
+            }
+            PickupKind::Berserker => soldier.berserker = BERSERKER_TIME,
+            PickupKind::Predator => soldier.predator = PREDATOR_TIME,
+        }
+        true
+    }
+}
+
+impl SerBin for PickupKind {
+    fn ser_bin(&self, output: &mut Vec<u8>) {
+        (*self as u8).ser_bin(output);
+    }
+}
+
+impl DeBin for PickupKind {
+    fn de_bin(offset: &mut usize, bytes: &[u8]) -> Result<Self, DeBinErr> {
+        let start = *offset;
+        match PickupKind::ALL.get(u8::de_bin(offset, bytes)? as usize) {
+            Some(&kind) => Ok(kind),
+            None => Err(DeBinErr {
+                o: start,
+                l: 1,
+                s: bytes.len(),
+            }),
+        }
+    }
+}
+
+/// Kit lying at a spawnpoint, respawning a while after it was taken
+#[derive(Debug, Copy, Clone)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    pub pos: Vec2,
+    /// tick taken kit comes back at
+    respawn_at: Option<usize>,
+}
+
+impl Pickup {
+    pub fn new(kind: PickupKind, pos: Vec2) -> Pickup {
+        Pickup {
+            kind,
+            pos,
+            respawn_at: None,
+        }
+    }
+
+    pub fn available(&self) -> bool {
+        self.respawn_at.is_none()
+    }
+
+    pub fn touches(&self, pos: Vec2) -> bool {
+        self.available() && self.pos.distance(pos) <= TOUCH_RADIUS
+    }
+
+    /// Gives kit to `soldier` if it can use it, the kit is gone for `respawn_ticks` then
+    pub fn take(&mut self, soldier: &mut Soldier, tick: usize, respawn_ticks: usize) -> bool {
+        if !self.available() || !self.kind.apply(soldier) {
+            return false;
+        }
+        self.respawn_at = Some(tick + respawn_ticks);
+        true
+    }
+
+    /// Brings taken kit back when it is due, returns whether it respawned
+    pub fn update(&mut self, tick: usize) -> bool {
+        match self.respawn_at {
+            Some(at) if tick >= at => {
+                self.respawn_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Fills tertiary weapon with up to `max` grenades of `kind`, replacing other grenades
+fn refill_grenades(soldier: &mut Soldier, kind: WeaponKind, max: u8) -> bool {
+    let grenades = &mut soldier.weapons[2];
+    if grenades.kind == kind && grenades.ammo_count >= max {
+        return false;
+    }
+    if grenades.kind != kind {
+        *grenades = Weapon::new(kind, false);
+    }
+    grenades.ammo_count = max;
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::vec2;
+
+    #[test]
+    fn kind_serialization() {
+        for kind in PickupKind::ALL.iter() {
+            let bytes = SerBin::serialize_bin(kind);
+            assert_eq!(PickupKind::deserialize_bin(&bytes).unwrap(), *kind);
+        }
+        assert!(PickupKind::deserialize_bin(&[PickupKind::ALL.len() as u8]).is_err());
+    }
+
+    #[test]
+    fn respawn() {
+        let mut pickup = Pickup::new(PickupKind::Medikit, vec2(100., 0.));
+        assert!(pickup.touches(vec2(105., 5.)));
+        assert!(!pickup.touches(vec2(150., 0.)));
+
+        pickup.respawn_at = Some(10);
+        assert!(!pickup.available());
+        assert!(!pickup.touches(vec2(100., 0.)));
+        assert!(!pickup.update(9));
+        assert!(pickup.update(10));
+        assert!(pickup.touches(vec2(100., 0.)));
+        assert!(!pickup.update(11));
+    }
+}
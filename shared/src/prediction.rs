@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use crate::{control::Control, math::Vec2};
+
+/// Inputs older than this many ticks are dropped, even if server did not acknowledge them
+const MAX_INPUTS_RETAIN: usize = 60;
+
+/// Client-side prediction of locally controlled state `S`
+///
+/// Every input applied ahead of server is kept along with the state predicted
+/// after it. Once server acknowledges the input, its authoritative state is
+/// compared with the prediction - if they differ, later inputs are replayed
+/// on top of the authoritative state.
+#[derive(Debug)]
+pub struct Prediction<S> {
+    history: BTreeMap<usize, (Control, Vec2, S)>,
+}
+
+impl<S> Default for Prediction<S> {
+    fn default() -> Self {
+        Prediction {
+            history: BTreeMap::new(),
+        }
+    }
+}
+
+impl<S: Clone> Prediction<S> {
+    /// Applies input of `tick` to `state` with `step` and remembers the result
+    pub fn predict<F>(&mut self, tick: usize, control: Control, aim: Vec2, state: &mut S, step: F)
+    where
+        F: FnOnce(&mut S, Control, Vec2),
+    {
+        step(state, control, aim);
+        self.history.insert(tick, (control, aim, state.clone()));
+    }
+
+    /// Inputs not applied by server yet, in tick order
+    pub fn inputs(&self, ack_tick: usize) -> impl Iterator<Item = (usize, Control, Vec2)> + '_ {
+        self.history
+            .range(ack_tick + 1..)
+            .map(|(&tick, &(control, aim, _))| (tick, control, aim))
+    }
+
+    /// Checks state predicted for `ack_tick` against authoritative one.
+    ///
+    /// `correct` fixes predicted state up and returns whether it was wrong. Inputs
+    /// after `ack_tick` are then replayed with `step` and `state` is replaced with
+    /// the result. When there is no prediction for `ack_tick`, `state` is corrected
+    /// directly. Returns whether prediction was wrong.
+    pub fn reconcile<C, F>(
+        &mut self,
+        ack_tick: usize,
+        state: &mut S,
+        correct: C,
+        mut step: F,
+    ) -> bool
+    where
+        C: Fn(&mut S) -> bool,
+        F: FnMut(&mut S, Control, Vec2),
+    {
+        let mut corrected = match self.history.get(&ack_tick) {
+            Some((_, _, predicted)) => predicted.clone(),
+            None => return correct(state),
+        };
+        if !correct(&mut corrected) {
+            return false;
+        }
+
+        self.history.get_mut(&ack_tick).unwrap().2 = corrected.clone();
+        for (_tick, (control, aim, predicted)) in self.history.range_mut(ack_tick + 1..) {
+            step(&mut corrected, *control, *aim);
+            *predicted = corrected.clone();
+        }
+        *state = corrected;
+        true
+    }
+
+    /// Drops inputs applied by server before `ack_tick` and ones too old to replay at `tick`.
+    /// Prediction for `ack_tick` itself is kept, as it is compared with next server state.
+    pub fn retain(&mut self, ack_tick: usize, tick: usize) {
+        let low_tick = usize::max(ack_tick, tick.saturating_sub(MAX_INPUTS_RETAIN));
+        self.history = self.history.split_off(&low_tick);
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::vec2;
+
+    /// Moves one unit right per tick with `RIGHT` pressed
+    fn step(pos: &mut Vec2, control: Control, _aim: Vec2) {
+        if control.contains(Control::RIGHT) {
+            pos.x += 1.;
+        }
+    }
+
+    fn correct(authoritative: Vec2) -> impl Fn(&mut Vec2) -> bool {
+        move |pos| {
+            if pos.distance(authoritative) < 0.01 {
+                return false;
+            }
+            *pos = authoritative;
+            true
+        }
+    }
+
+    #[test]
+    fn reconcile() {
+        let mut prediction = Prediction::default();
+        let mut pos = Vec2::ZERO;
+        for tick in 1..=5 {
+            prediction.predict(tick, Control::RIGHT, vec2(1., 0.), &mut pos, step);
+        }
+        assert_eq!(pos, vec2(5., 0.));
+        let ticks: Vec<usize> = prediction.inputs(2).map(|(tick, _, _)| tick).collect();
+        assert_eq!(ticks, vec![3, 4, 5]);
+
+        // server agrees with prediction
+        assert!(!prediction.reconcile(2, &mut pos, correct(vec2(2., 0.)), step));
+        assert_eq!(pos, vec2(5., 0.));
+
+        // server got blocked at tick 3 - ticks 4 and 5 are replayed from there
+        assert!(prediction.reconcile(3, &mut pos, correct(vec2(2., 0.)), step));
+        assert_eq!(pos, vec2(4., 0.));
+
+        // replayed predictions are what server confirms next
+        assert!(!prediction.reconcile(4, &mut pos, correct(vec2(3., 0.)), step));
+        assert_eq!(pos, vec2(4., 0.));
+
+        prediction.retain(4, 5);
+        assert_eq!(prediction.inputs(0).count(), 2);
+        assert!(!prediction.reconcile(4, &mut pos, correct(vec2(3., 0.)), step));
+
+        // nothing predicted for acknowledged tick - state is corrected as is
+        prediction.clear();
+        assert!(prediction.reconcile(5, &mut pos, correct(vec2(10., 0.)), step));
+        assert_eq!(pos, vec2(10., 0.));
+    }
+
+    #[test]
+    fn retain() {
+        let mut prediction = Prediction::default();
+        let mut pos = Vec2::ZERO;
+        for tick in 1..=MAX_INPUTS_RETAIN * 2 {
+            prediction.predict(tick, Control::empty(), Vec2::ZERO, &mut pos, step);
+        }
+        prediction.retain(10, MAX_INPUTS_RETAIN * 2);
+        assert_eq!(prediction.inputs(0).count(), MAX_INPUTS_RETAIN + 1);
+        prediction.retain(MAX_INPUTS_RETAIN * 2, MAX_INPUTS_RETAIN * 2);
+        assert_eq!(prediction.inputs(0).count(), 1);
+        assert_eq!(prediction.inputs(MAX_INPUTS_RETAIN * 2).count(), 0);
+    }
+}
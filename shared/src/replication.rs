@@ -0,0 +1,508 @@
+use core::any::TypeId;
+use hecs::{Component, Entity, EntityRef, World};
+use nanoserde::{DeBin, SerBin};
+use std::{
+    any::type_name,
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+};
+
+use crate::messages::DecodeError;
+
+/// Stable wire id of a replicated component type. Top bit is reserved by the protocol.
+pub type ComponentId = u8;
+
+pub const MAX_COMPONENT_ID: ComponentId = 0x7F;
+
+/// Serialized value of a replicated component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentValue {
+    pub id: ComponentId,
+    pub data: Vec<u8>,
+}
+
+impl ComponentValue {
+    pub fn new<T: SerBin>(id: ComponentId, value: &T) -> Self {
+        ComponentValue {
+            id,
+            data: SerBin::serialize_bin(value),
+        }
+    }
+
+    pub fn get<T: DeBin>(&self) -> Result<T, DecodeError> {
+        let mut offset = 0;
+        let value = T::de_bin(&mut offset, &self.data).map_err(|err| {
+            if err.o.saturating_add(err.l) > err.s {
+                DecodeError::Truncated { offset: err.o }
+            } else {
+                DecodeError::InvalidValue { offset: err.o }
+            }
+        })?;
+        if offset != self.data.len() {
+            return Err(DecodeError::TrailingBytes {
+                offset,
+                len: self.data.len(),
+            });
+        }
+        Ok(value)
+    }
+}
+
+/// Registry of component types replicated from server to clients.
+///
+/// Every type is registered once with a stable id, which is then used to
+/// collect it from server `World`, put it on the wire and insert it into
+/// client `World`.
+#[derive(Clone, Default, Debug)]
+pub struct ReplicationRegistry(Vec<ReplicationEntry>);
+
+impl ReplicationRegistry {
+    /// Registers `T` with the registry under `id`.
+    pub fn register<T: Component + SerBin + DeBin>(mut self, id: ComponentId) -> Self {
+        assert!(
+            id > 0 && id <= MAX_COMPONENT_ID,
+            "Component id {} of {} out of range",
+            id,
+            type_name::<T>()
+        );
+        if let Some(entry) = self
+            .0
+            .iter()
+            .find(|item| item.id == id || item.type_id == TypeId::of::<T>())
+        {
+            panic!(
+                "Cannot register {} as {}: already registered {} as {}",
+                type_name::<T>(),
+                id,
+                entry.type_name,
+                entry.id
+            );
+        }
+        self.0.push(register::<T>(id));
+        self
+    }
+
+    pub fn id_of<T: Component>(&self) -> Option<ComponentId> {
+        self.0
+            .iter()
+            .find(|item| item.type_id == TypeId::of::<T>())
+            .map(|item| item.id)
+    }
+
+    /// Serializes all registered components of given entity
+    pub fn collect(&self, entity: &EntityRef) -> Vec<ComponentValue> {
+        self.0
+            .iter()
+            .filter_map(|entry| {
+                (entry.collect)(entity).map(|data| ComponentValue { id: entry.id, data })
+            })
+            .collect()
+    }
+
+    /// Deserializes component and inserts it into (replacing existing) entity
+    pub fn insert(
+        &self,
+        world: &mut World,
+        entity: Entity,
+        component: &ComponentValue,
+    ) -> Result<(), DecodeError> {
+        let entry = self.entry(component.id)?;
+        (entry.insert)(world, entity, component)
+    }
+
+    pub fn remove(
+        &self,
+        world: &mut World,
+        entity: Entity,
+        id: ComponentId,
+    ) -> Result<(), DecodeError> {
+        let entry = self.entry(id)?;
+        (entry.remove)(world, entity);
+        Ok(())
+    }
+
+    fn entry(&self, id: ComponentId) -> Result<&ReplicationEntry, DecodeError> {
+        self.0
+            .iter()
+            .find(|item| item.id == id)
+            .ok_or(DecodeError::BadComponentType(id))
+    }
+}
+
+#[derive(Clone)]
+struct ReplicationEntry {
+    id: ComponentId,
+    type_id: TypeId,
+    type_name: &'static str,
+    collect: fn(&EntityRef) -> Option<Vec<u8>>,
+    insert: fn(&mut World, Entity, &ComponentValue) -> Result<(), DecodeError>,
+    remove: fn(&mut World, Entity),
+}
+
+fn register<T: Component + SerBin + DeBin>(id: ComponentId) -> ReplicationEntry {
+    ReplicationEntry {
+        id,
+        type_id: TypeId::of::<T>(),
+        type_name: type_name::<T>(),
+        collect: |entity| {
+            entity
+                .get::<T>()
+                .map(|component| SerBin::serialize_bin(&*component))
+        },
+        insert: |world, entity, component| {
+            let value = component.get::<T>()?;
+            if let Err(error) = world.insert_one(entity, value) {
+                log::error!("Cannot insert {}: {}", type_name::<T>(), error);
+            }
+            Ok(())
+        },
+        remove: |world, entity| {
+            let _ = world.remove_one::<T>(entity);
+        },
+    }
+}
+
+impl Debug for ReplicationEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ReplicationEntry {{ id: {}, type_id: {:?}, type_name: {} }}",
+            self.id, self.type_id, self.type_name
+        )
+    }
+}
+
+/// Replicated components of entities, as known at some tick
+pub type EntitiesSnapshot = HashMap<Entity, Vec<ComponentValue>>;
+
+/// Maximum number of snapshots kept while waiting for acknowledgement
+const MAX_SNAPSHOT_HISTORY: usize = 64;
+
+/// Snapshots sent to (or received from) a single peer, keyed by tick
+#[derive(Debug, Default)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<(usize, EntitiesSnapshot)>,
+}
+
+impl SnapshotHistory {
+    pub fn get(&self, tick: usize) -> Option<&EntitiesSnapshot> {
+        self.snapshots
+            .iter()
+            .find(|(t, _)| *t == tick)
+            .map(|(_, snapshot)| snapshot)
+    }
+
+    /// Most recently pushed snapshot
+    pub fn latest(&self) -> Option<&EntitiesSnapshot> {
+        self.snapshots.back().map(|(_, snapshot)| snapshot)
+    }
+
+    pub fn push(&mut self, tick: usize, snapshot: EntitiesSnapshot) {
+        self.snapshots.retain(|(t, _)| *t != tick);
+        self.snapshots.push_back((tick, snapshot));
+        while self.snapshots.len() > MAX_SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Drops snapshots older than acknowledged tick - these will never be used as a base again
+    pub fn acknowledge(&mut self, ack_tick: usize) {
+        self.snapshots.retain(|(t, _)| *t >= ack_tick);
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+/// Difference between two snapshots, as carried by `GameState` message
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SnapshotDelta {
+    /// new or changed components
+    pub entities: HashMap<Entity, Vec<ComponentValue>>,
+    pub removed: HashMap<Entity, Vec<ComponentId>>,
+    pub despawned: Vec<Entity>,
+}
+
+impl SnapshotDelta {
+    /// Computes changes turning `base` into `current`
+    pub fn diff(base: &EntitiesSnapshot, current: &EntitiesSnapshot) -> Self {
+        let mut delta = SnapshotDelta::default();
+        for (&entity, components) in current.iter() {
+            let base_components = base.get(&entity);
+
+            let changed: Vec<ComponentValue> = components
+                .iter()
+                .filter(|component| {
+                    base_components
+                        .map_or(true, |base_components| !base_components.contains(component))
+                })
+                .cloned()
+                .collect();
+            if !changed.is_empty() {
+                delta.entities.insert(entity, changed);
+            }
+
+            if let Some(base_components) = base_components {
+                let gone: Vec<ComponentId> = base_components
+                    .iter()
+                    .map(|component| component.id)
+                    .filter(|&id| !components.iter().any(|component| component.id == id))
+                    .collect();
+                if !gone.is_empty() {
+                    delta.removed.insert(entity, gone);
+                }
+            }
+        }
+
+        delta.despawned = base
+            .keys()
+            .filter(|entity| !current.contains_key(entity))
+            .copied()
+            .collect();
+        delta
+    }
+
+    /// Applies changes on top of `base`, the snapshot this delta was computed against
+    pub fn apply(&self, base: &EntitiesSnapshot) -> EntitiesSnapshot {
+        let mut snapshot = base.clone();
+        for entity in self.despawned.iter() {
+            snapshot.remove(entity);
+        }
+        for (entity, ids) in self.removed.iter() {
+            if let Some(components) = snapshot.get_mut(entity) {
+                components.retain(|component| !ids.contains(&component.id));
+            }
+        }
+        for (&entity, changed) in self.entities.iter() {
+            let components = snapshot.entry(entity).or_default();
+            for component in changed {
+                components.retain(|c| c.id != component.id);
+                components.push(component.clone());
+            }
+        }
+        snapshot.retain(|_, components| !components.is_empty());
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        components::{replication_registry, Nick, Position, Soldier, SoldierState},
+        messages::{decode_message, encode_message, NetworkMessage},
+        weapons::WeaponKind,
+    };
+
+    fn entity(world: &mut World) -> Entity {
+        world.spawn(())
+    }
+
+    fn position(x: f32) -> ComponentValue {
+        ComponentValue::new(3, &Position::new(x, 0.))
+    }
+
+    fn nick(name: &str) -> ComponentValue {
+        ComponentValue::new(2, &Nick(name.to_string()))
+    }
+
+    fn sorted(mut snapshot: EntitiesSnapshot) -> EntitiesSnapshot {
+        for components in snapshot.values_mut() {
+            components.sort_by_key(|component| component.id);
+        }
+        snapshot
+    }
+
+    /// Sends delta through the wire and applies it on receiving side
+    fn transfer(base: &EntitiesSnapshot, current: &EntitiesSnapshot) -> EntitiesSnapshot {
+        let delta = SnapshotDelta::diff(base, current);
+        let msg = NetworkMessage::GameState {
+            tick: 2,
+            base_tick: 1,
+            ack_tick: 0,
+            entities: delta.entities,
+            removed: delta.removed,
+            despawned: delta.despawned,
+        };
+        match decode_message(&encode_message(msg)).unwrap() {
+            NetworkMessage::GameState {
+                entities,
+                removed,
+                despawned,
+                ..
+            } => SnapshotDelta {
+                entities,
+                removed,
+                despawned,
+            }
+            .apply(base),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn replicate() {
+        let registry = replication_registry();
+
+        let mut server = World::new();
+        let entity = server.spawn((Soldier, Nick("test".to_string()), Position::new(1., 2.)));
+        let components = registry.collect(&server.entity(entity).unwrap());
+        assert_eq!(components.len(), 3);
+
+        let mut client = World::new();
+        let local = client.spawn(());
+        for component in components.iter() {
+            registry.insert(&mut client, local, component).unwrap();
+        }
+        assert_eq!(client.get::<Nick>(local).unwrap().0, "test");
+        assert_eq!(
+            *client.get::<Position>(local).unwrap(),
+            Position::new(1., 2.)
+        );
+
+        let id = registry.id_of::<Nick>().unwrap();
+        registry.remove(&mut client, local, id).unwrap();
+        assert!(client.get::<Nick>(local).is_err());
+        assert!(client.get::<Soldier>(local).is_ok());
+
+        assert_eq!(
+            registry.insert(
+                &mut client,
+                local,
+                &ComponentValue {
+                    id: 0x7E,
+                    data: Vec::new()
+                }
+            ),
+            Err(DecodeError::BadComponentType(0x7E))
+        );
+    }
+
+    #[test]
+    fn soldier_state() {
+        let registry = replication_registry();
+        let id = registry.id_of::<SoldierState>().unwrap();
+        for (&active_weapon, &secondary_weapon) in WeaponKind::values()
+            .iter()
+            .zip(WeaponKind::values().iter().rev())
+        {
+            let state = SoldierState {
+                health: 150,
+                vest: 33,
+                active_weapon,
+                secondary_weapon,
+                alpha: 80,
+            };
+            let component = ComponentValue::new(id, &state);
+            assert_eq!(component.data.len(), 5);
+            assert_eq!(component.get::<SoldierState>(), Ok(state));
+        }
+
+        let bad_weapon = ComponentValue {
+            id,
+            data: vec![100, 0, WeaponKind::values().len() as u8, 0],
+        };
+        assert!(bad_weapon.get::<SoldierState>().is_err());
+    }
+
+    #[test]
+    fn snapshot_history() {
+        let mut world = World::new();
+        let a = entity(&mut world);
+        let mut history = SnapshotHistory::default();
+        assert!(history.latest().is_none());
+
+        for tick in 1..=MAX_SNAPSHOT_HISTORY + 10 {
+            let mut snapshot = EntitiesSnapshot::new();
+            snapshot.insert(a, vec![position(tick as f32)]);
+            history.push(tick, snapshot);
+        }
+        assert!(history.get(10).is_none());
+        assert_eq!(history.get(11).unwrap()[&a], vec![position(11.)]);
+        assert_eq!(
+            history.latest().unwrap()[&a],
+            vec![position((MAX_SNAPSHOT_HISTORY + 10) as f32)]
+        );
+
+        // pushing same tick again replaces it
+        history.push(20, EntitiesSnapshot::new());
+        assert!(history.get(20).unwrap().is_empty());
+        assert!(history.latest().unwrap().is_empty());
+
+        history.acknowledge(30);
+        assert!(history.get(29).is_none());
+        assert!(history.get(30).is_some());
+        assert!(history.get(20).is_none());
+
+        history.clear();
+        assert!(history.latest().is_none());
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let mut world = World::new();
+        let (a, b, c) = (entity(&mut world), entity(&mut world), entity(&mut world));
+
+        let mut base = EntitiesSnapshot::new();
+        base.insert(a, vec![position(1.), nick("a")]);
+        base.insert(b, vec![position(2.)]);
+
+        let mut current = EntitiesSnapshot::new();
+        current.insert(a, vec![position(5.)]);
+        current.insert(c, vec![nick("c"), position(3.)]);
+
+        let delta = SnapshotDelta::diff(&base, &current);
+        assert_eq!(delta.entities[&a], vec![position(5.)]);
+        assert_eq!(delta.entities[&c].len(), 2);
+        assert_eq!(delta.removed[&a], vec![2]);
+        assert_eq!(delta.despawned, vec![b]);
+
+        assert_eq!(sorted(transfer(&base, &current)), sorted(current.clone()));
+        assert_eq!(sorted(transfer(&current, &base)), sorted(base.clone()));
+        assert_eq!(
+            sorted(transfer(&EntitiesSnapshot::new(), &current)),
+            sorted(current.clone())
+        );
+        assert_eq!(
+            SnapshotDelta::diff(&current, &current),
+            SnapshotDelta::default()
+        );
+    }
+
+    #[test]
+    fn delta_after_base() {
+        let mut world = World::new();
+        let (a, b) = (entity(&mut world), entity(&mut world));
+
+        // client acknowledged tick 1 and got tick 2, but server still uses tick 1 as a base
+        let mut client = SnapshotHistory::default();
+        let mut tick1 = EntitiesSnapshot::new();
+        tick1.insert(a, vec![position(1.), nick("a")]);
+        client.push(1, tick1.clone());
+
+        // b spawned and a lost its nick at tick 2
+        let mut tick2 = EntitiesSnapshot::new();
+        tick2.insert(a, vec![position(1.)]);
+        tick2.insert(b, vec![position(2.)]);
+        let received = transfer(client.get(1).unwrap(), &tick2);
+        assert_eq!(sorted(received.clone()), sorted(tick2.clone()));
+        client.push(2, received);
+
+        // b despawned and a got its nick back at tick 3 - nothing to tell against tick 1
+        let tick3 = tick1.clone();
+        let delta = SnapshotDelta::diff(&tick1, &tick3);
+        assert_eq!(delta, SnapshotDelta::default());
+
+        let received = transfer(client.get(1).unwrap(), &tick3);
+        assert_eq!(sorted(received.clone()), sorted(tick3.clone()));
+
+        // changes to apply on top of the latest received state leave no ghosts
+        let sync = SnapshotDelta::diff(client.latest().unwrap(), &received);
+        assert_eq!(sync.despawned, vec![b]);
+        assert_eq!(sync.entities[&a], vec![nick("a")]);
+        client.push(3, received);
+        client.acknowledge(1);
+        assert_eq!(sorted(client.latest().unwrap().clone()), sorted(tick3));
+    }
+}
@@ -1,4 +1,20 @@
-use super::*;
+use crate::{
+    anims::{Anim, AnimState},
+    bullet::{BulletParams, BulletStyle, EmitterItem},
+    calc::*,
+    components::Team,
+    constants::AIM_DISTANCE,
+    control::Control as ControlFlags,
+    mapfile::{MapFile, MapSpawnpoint, PolyType},
+    math::{vec2, Vec2},
+    particles::{Particle, ParticleSystem},
+    weapons::{Weapon, WeaponKind},
+};
+use gvfs::filesystem::Filesystem;
+
+mod control;
+
+pub use control::Control;
 
 const SLIDELIMIT: f32 = 0.2;
 const SURFACECOEFX: f32 = 0.970;
@@ -15,9 +31,14 @@ const POS_PRONE: u8 = 3;
 const MAX_VELOCITY: f32 = 11.0;
 const SOLDIER_COL_RADIUS: f32 = 3.0;
 
+const VEST_ABSORB: f32 = 0.67; // part of damage taken by the vest
+const BERSERKER_DAMAGE: f32 = 4.0;
+const PREDATOR_ALPHA: u8 = 10;
+
 static mut SOLDIER_SKELETON: Option<ParticleSystem> = None;
 
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct Soldier {
     pub active: bool,
     pub dead_meat: bool,
@@ -51,17 +72,24 @@ pub struct Soldier {
     pub weapons: [Weapon; 3],
     pub fired: u8,
     pub particle: Particle,
+    pub team: Team,
+    /// carries a flag
+    pub flagger: bool,
+    /// ticks of berserker bonus left
+    pub berserker: usize,
+    /// ticks of predator bonus left
+    pub predator: usize,
 }
 
 impl Soldier {
-    pub fn initialize(fs: &mut Filesystem, config: &Config) {
+    pub fn initialize(fs: &mut Filesystem, gravity: f32) {
         unsafe {
             SOLDIER_SKELETON.replace(ParticleSystem::load_from_file(
                 fs,
                 "gostek.po",
                 4.5,
                 1.0,
-                1.06 * config.phys.gravity,
+                1.06 * gravity,
                 0.0,
                 0.9945,
             ));
@@ -80,6 +108,11 @@ impl Soldier {
         &self.weapons[2]
     }
 
+    /// Bullets hit harder while berserker bonus lasts
+    pub fn damage_multiplier(&self) -> f32 {
+        iif!(self.berserker > 0, BERSERKER_DAMAGE, 1.0)
+    }
+
     pub fn switch_weapon(&mut self) {
         let w = (self.active_weapon + 1) % 2;
         self.active_weapon = w;
@@ -88,28 +121,6 @@ impl Soldier {
         // burst_count = 0;
     }
 
-    pub fn update_keys(&mut self, pressed: bool, keycode: mq::KeyCode) {
-        match keycode {
-            mq::KeyCode::A => self.control.left = pressed,
-            mq::KeyCode::D => self.control.right = pressed,
-            mq::KeyCode::W => self.control.up = pressed,
-            mq::KeyCode::S => self.control.down = pressed,
-            mq::KeyCode::Q => self.control.change = pressed,
-            mq::KeyCode::E => self.control.throw = pressed,
-            mq::KeyCode::F => self.control.drop = pressed,
-            mq::KeyCode::X => self.control.prone = pressed,
-            _ => {}
-        }
-    }
-
-    pub fn update_mouse_button(&mut self, pressed: bool, button: mq::MouseButton) {
-        match button {
-            mq::MouseButton::Left => self.control.fire = pressed,
-            mq::MouseButton::Right => self.control.jets = pressed,
-            _ => (),
-        }
-    }
-
     pub fn new(spawn: &MapSpawnpoint, gravity: f32) -> Soldier {
         let particle = Particle {
             active: true,
@@ -161,9 +172,30 @@ impl Soldier {
             weapons,
             fired: 0,
             particle,
+            team: Team::None,
+            flagger: false,
+            berserker: 0,
+            predator: 0,
         }
     }
 
+    /// Takes `damage` off health, returns whether the hit killed the soldier.
+    /// Vest takes part of the damage while it lasts.
+    pub fn hit(&mut self, damage: f32) -> bool {
+        if self.dead_meat {
+            return false;
+        }
+
+        let absorbed = f32::min(damage * VEST_ABSORB, self.vest);
+        self.vest -= absorbed;
+        self.health -= damage - absorbed;
+        if self.health <= 0.0 {
+            self.health = 0.0;
+            self.dead_meat = true;
+        }
+        self.dead_meat
+    }
+
     pub fn legs_apply_animation(&mut self, id: Anim, frame: usize) {
         if !self.legs_animation.is_any(&[Anim::Prone, Anim::ProneMove])
             && self.legs_animation.id != id
@@ -189,20 +221,30 @@ impl Soldier {
         }
     }
 
-    #[allow(clippy::manual_range_contains)]
-    pub fn update(
+    /// Steps simulation with network input - pressed `control` flags and normalized
+    /// `aim` direction. Server and client prediction must run exactly the same step.
+    pub fn step(
         &mut self,
-        resources: &Resources,
+        control: ControlFlags,
+        aim: Vec2,
+        map: &MapFile,
         emitter: &mut Vec<EmitterItem>,
-        config: &Config,
+        gravity: f32,
     ) {
-        let map = &*resources.get::<MapFile>().unwrap();
+        let aim = self.particle.pos + aim * AIM_DISTANCE;
+        self.control.set_flags(control);
+        self.control.mouse_aim_x = aim.x as i32;
+        self.control.mouse_aim_y = aim.y as i32;
+        self.update(map, emitter, gravity);
+    }
 
+    #[allow(clippy::manual_range_contains)]
+    pub fn update(&mut self, map: &MapFile, emitter: &mut Vec<EmitterItem>, gravity: f32) {
         let mut body_y = 0.0;
         let mut arm_s;
 
         self.particle.euler();
-        self.control(emitter, config.phys.gravity);
+        self.control(emitter, gravity);
 
         *self.skeleton.old_pos_mut(21) = self.skeleton.pos(21);
         *self.skeleton.old_pos_mut(23) = self.skeleton.pos(23);
@@ -361,10 +403,10 @@ impl Soldier {
             self.on_ground = false;
 
             let (x, y) = self.particle.pos.into();
-            self.check_map_collision(map, config, x - 3.5, y - 12.0, 1);
+            self.check_map_collision(map, gravity, x - 3.5, y - 12.0, 1);
 
             let (x, y) = self.particle.pos.into();
-            self.check_map_collision(map, config, x + 3.5, y - 12.0, 1);
+            self.check_map_collision(map, gravity, x + 3.5, y - 12.0, 1);
 
             body_y = 0.0;
             arm_s = 0.0;
@@ -400,10 +442,10 @@ impl Soldier {
             }
 
             let (x, y) = self.particle.pos.into();
-            self.on_ground = self.check_map_collision(map, config, x + 2.0, y + 2.0 - body_y, 0);
+            self.on_ground = self.check_map_collision(map, gravity, x + 2.0, y + 2.0 - body_y, 0);
 
             let (x, y) = self.particle.pos.into();
-            self.on_ground |= self.check_map_collision(map, config, x - 2.0, y + 2.0 - arm_s, 0);
+            self.on_ground |= self.check_map_collision(map, gravity, x - 2.0, y + 2.0 - arm_s, 0);
 
             let (x, y) = self.particle.pos.into();
             let grounded = self.on_ground;
@@ -427,7 +469,9 @@ impl Soldier {
                 }
             }
 
-            self.alpha = 255;
+            self.alpha = iif!(self.predator > 0, PREDATOR_ALPHA, 255);
+            self.berserker = self.berserker.saturating_sub(1);
+            self.predator = self.predator.saturating_sub(1);
 
             self.skeleton.do_verlet_timestep_for(22, 29);
             self.skeleton.do_verlet_timestep_for(24, 30);
@@ -456,7 +500,7 @@ impl Soldier {
     pub fn check_map_collision(
         &mut self,
         map: &MapFile,
-        config: &Config,
+        gravity: f32,
         x: f32,
         y: f32,
         area: i32,
@@ -470,7 +514,7 @@ impl Soldier {
                 let poly = map.sectors_poly[rx as usize][ry as usize].polys[j] as usize - 1;
                 let polytype = map.polygons[poly].polytype;
 
-                if polytype != PolyType::NoCollide && polytype != PolyType::OnlyBulletsCollide {
+                if map.polygons[poly].player_collides(self.team, self.flagger) {
                     let polygons = map.polygons[poly];
                     if map.point_in_poly(pos, &polygons) {
                         self.handle_special_polytypes(map, polytype, pos);
@@ -522,7 +566,7 @@ impl Soldier {
                                     && (step.y > SLIDELIMIT)
                                 {
                                     self.particle.pos = self.particle.old_pos;
-                                    self.particle.force.y -= config.phys.gravity;
+                                    self.particle.force.y -= gravity;
                                 }
 
                                 if (step.y > SLIDELIMIT)
@@ -596,7 +640,7 @@ impl Soldier {
                 let poly = map.sectors_poly[rx as usize][ry as usize].polys[j] as usize - 1;
                 let polytype = map.polygons[poly].polytype;
 
-                if polytype != PolyType::NoCollide && polytype != PolyType::OnlyBulletsCollide {
+                if map.polygons[poly].player_collides(self.team, self.flagger) {
                     for i in 0..3 {
                         let vert = vec2(
                             map.polygons[poly].vertices[i].x,
@@ -647,7 +691,7 @@ impl Soldier {
                     let poly = map.sectors_poly[rx as usize][ry as usize].polys[j] as usize - 1;
                     let polytype = map.polygons[poly].polytype;
 
-                    if polytype != PolyType::NoCollide && polytype != PolyType::OnlyBulletsCollide {
+                    if map.polygons[poly].player_collides(self.team, self.flagger) {
                         for k in 0..2 {
                             let mut norm = map.perps[poly][k];
                             norm *= -SOLDIER_COL_RADIUS;
@@ -800,9 +844,8 @@ impl Soldier {
             position: pos,
             velocity: bullet_velocity + inherited_velocity,
             timeout: weapon.timeout as i16,
-            hit_multiply: weapon.hit_multiply,
-            team: Team::None,
-            sprite: weapon.bullet_sprite,
+            hit_multiply: weapon.hit_multiply * self.damage_multiplier(),
+            team: self.team,
         };
 
         match weapon.kind {
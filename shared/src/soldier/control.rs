@@ -1,4 +1,5 @@
 use super::*;
+use crate::control::Control as ControlFlags;
 
 const POS_STAND: u8 = 1;
 const POS_CROUCH: u8 = 2;
@@ -17,7 +18,7 @@ const SECOND: i32 = 60;
 
 const DEFAULT_IDLETIME: i32 = SECOND * 8;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Control {
     pub left: bool,
     pub right: bool,
@@ -43,6 +44,25 @@ pub struct Control {
     pub was_reloading_weapon: bool,
 }
 
+impl Control {
+    /// Sets pressed keys from network control `flags`
+    pub fn set_flags(&mut self, flags: ControlFlags) {
+        self.left = flags.contains(ControlFlags::LEFT);
+        self.right = flags.contains(ControlFlags::RIGHT);
+        self.up = flags.contains(ControlFlags::UP);
+        self.down = flags.contains(ControlFlags::DOWN);
+        self.fire = flags.contains(ControlFlags::FIRE);
+        self.jets = flags.contains(ControlFlags::JETS);
+        self.grenade = flags.contains(ControlFlags::GRENADE);
+        self.change = flags.contains(ControlFlags::CHANGE);
+        self.throw = flags.contains(ControlFlags::THROW);
+        self.drop = flags.contains(ControlFlags::DROP);
+        self.reload = flags.contains(ControlFlags::RELOAD);
+        self.prone = flags.contains(ControlFlags::PRONE);
+        self.flag_throw = flags.contains(ControlFlags::FLAG_THROW);
+    }
+}
+
 impl Soldier {
     #[allow(clippy::collapsible_if)]
     pub fn control(&mut self, emitter: &mut Vec<EmitterItem>, gravity: f32) {
@@ -245,9 +265,8 @@ impl Soldier {
                 position: self.skeleton.pos(16) + velocity,
                 velocity,
                 timeout: weapon.timeout as i16,
-                hit_multiply: weapon.hit_multiply,
+                hit_multiply: weapon.hit_multiply * self.damage_multiplier(),
                 team: Team::None,
-                sprite: weapon.bullet_sprite,
             }));
 
             self.control.drop = false;
@@ -1,7 +1,5 @@
 mod debug;
 pub use debug::*;
-mod input;
-pub use input::*;
 
 #[derive(Debug)]
 pub struct Time {
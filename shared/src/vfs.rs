@@ -0,0 +1,44 @@
+use gvfs::filesystem::{File, Filesystem};
+use std::{env, path};
+
+/// Mounts `resources` directory when run from cargo, and adds all `.smod` archives
+/// found in VFS root to the `filesystem` - `soldat.smod` first, so mods can override it
+pub fn mount_resources(filesystem: &mut Filesystem) {
+    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+        let mut path = path::PathBuf::from(manifest_dir);
+        path.push("../resources");
+        filesystem.mount(path.canonicalize().unwrap().as_path(), true);
+    }
+    log::info!("Full VFS info: {:#?}", filesystem);
+
+    let mut mods = Vec::new();
+
+    let soldat_smod = path::Path::new("/soldat.smod");
+    if filesystem.is_file(soldat_smod) {
+        mods.push((
+            filesystem.open(soldat_smod).unwrap(),
+            soldat_smod.to_string_lossy().to_string(),
+        ));
+    }
+
+    for f in filesystem.read_dir(path::Path::new("/")).unwrap() {
+        let f = f.as_path();
+        if let Some(name) = f.to_str() {
+            if filesystem.is_file(f) && f != soldat_smod && name.ends_with(".smod") {
+                mods.push((filesystem.open(f).unwrap(), name.to_string()));
+            }
+        }
+    }
+    for (md, path) in mods.drain(..) {
+        match md {
+            File::VfsFile(file) => {
+                filesystem.add_zip_file(file).unwrap_or_else(|err| {
+                    panic!(
+                        "Failed to add `{}` file to VFS. (Make sure it is a proper ZIP file.): {}",
+                        path, err
+                    )
+                });
+            }
+        }
+    }
+}
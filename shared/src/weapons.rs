@@ -1,4 +1,5 @@
-use super::*;
+use crate::bullet::BulletStyle;
+use nanoserde::{DeBin, DeBinErr, SerBin};
 
 const SECOND: u16 = 60;
 const BULLET_TIMEOUT: u16 = SECOND * 7;
@@ -43,6 +44,14 @@ pub enum WeaponKind {
     ThrownKnife,
 }
 
+/// Soldier body part hit by a bullet
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HitRegion {
+    Head,
+    Chest,
+    Legs,
+}
+
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Weapon {
     pub kind: WeaponKind,
@@ -77,10 +86,6 @@ pub struct Weapon {
     pub fire_mode: u8,
     pub timeout: u16,
     pub bullet_style: BulletStyle,
-    pub sprite: Option<gfx::Weapon>,
-    pub clip_sprite: Option<gfx::Weapon>,
-    pub fire_sprite: Option<gfx::Weapon>,
-    pub bullet_sprite: Option<gfx::Weapon>,
 }
 
 impl Default for WeaponKind {
@@ -154,7 +159,36 @@ impl WeaponKind {
     }
 }
 
+impl SerBin for WeaponKind {
+    fn ser_bin(&self, output: &mut Vec<u8>) {
+        (*self as u8).ser_bin(output);
+    }
+}
+
+impl DeBin for WeaponKind {
+    fn de_bin(offset: &mut usize, bytes: &[u8]) -> Result<Self, DeBinErr> {
+        let start = *offset;
+        match WeaponKind::values().get(u8::de_bin(offset, bytes)? as usize) {
+            Some(&kind) => Ok(kind),
+            None => Err(DeBinErr {
+                o: start,
+                l: 1,
+                s: bytes.len(),
+            }),
+        }
+    }
+}
+
 impl Weapon {
+    /// Damage multiplier of a hit in body `region`
+    pub fn modifier(&self, region: HitRegion) -> f32 {
+        match region {
+            HitRegion::Head => self.modifier_head,
+            HitRegion::Chest => self.modifier_chest,
+            HitRegion::Legs => self.modifier_legs,
+        }
+    }
+
     pub fn new(kind: WeaponKind, realistic: bool) -> Weapon {
         let mut weapon = Weapon {
             kind,
@@ -168,10 +202,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = true;
                 weapon.fire_mode = 2;
-                weapon.sprite = Some(gfx::Weapon::Deagles);
-                weapon.clip_sprite = Some(gfx::Weapon::DeaglesClip);
-                weapon.bullet_sprite = Some(gfx::Weapon::DeaglesBullet);
-                weapon.fire_sprite = Some(gfx::Weapon::DeaglesFire);
 
                 if realistic {
                     weapon.hit_multiply = 1.66;
@@ -215,10 +245,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = true;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Mp5);
-                weapon.clip_sprite = Some(gfx::Weapon::Mp5Clip);
-                weapon.bullet_sprite = Some(gfx::Weapon::Mp5Bullet);
-                weapon.fire_sprite = Some(gfx::Weapon::Mp5Fire);
 
                 if realistic {
                     weapon.hit_multiply = 0.94;
@@ -262,10 +288,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = true;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Ak74);
-                weapon.clip_sprite = Some(gfx::Weapon::Ak74Clip);
-                weapon.bullet_sprite = Some(gfx::Weapon::Ak74Bullet);
-                weapon.fire_sprite = Some(gfx::Weapon::Ak74Fire);
 
                 if realistic {
                     weapon.hit_multiply = 1.08;
@@ -309,10 +331,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = true;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Steyr);
-                weapon.clip_sprite = Some(gfx::Weapon::SteyrClip);
-                weapon.bullet_sprite = Some(gfx::Weapon::SteyrBullet);
-                weapon.fire_sprite = Some(gfx::Weapon::SteyrFire);
 
                 if realistic {
                     weapon.hit_multiply = 0.68;
@@ -356,10 +374,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = false;
                 weapon.fire_mode = 2;
-                weapon.sprite = Some(gfx::Weapon::Spas);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::SpasFire);
 
                 if realistic {
                     weapon.hit_multiply = 1.2;
@@ -403,10 +417,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = false;
                 weapon.fire_mode = 2;
-                weapon.sprite = Some(gfx::Weapon::Ruger);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = Some(gfx::Weapon::RugerBullet);
-                weapon.fire_sprite = Some(gfx::Weapon::RugerFire);
 
                 if realistic {
                     weapon.hit_multiply = 2.22;
@@ -450,10 +460,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = true;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::M79);
-                weapon.clip_sprite = Some(gfx::Weapon::M79Clip);
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::M79Fire);
 
                 if realistic {
                     weapon.hit_multiply = 1600.0;
@@ -497,10 +503,6 @@ impl Weapon {
                 weapon.ini_name = "Barret M82A1";
                 weapon.clip_reload = true;
                 weapon.fire_mode = 2;
-                weapon.sprite = Some(gfx::Weapon::Barrett);
-                weapon.clip_sprite = Some(gfx::Weapon::BarrettClip);
-                weapon.bullet_sprite = Some(gfx::Weapon::BarrettBullet);
-                weapon.fire_sprite = Some(gfx::Weapon::BarrettFire);
 
                 if realistic {
                     weapon.hit_multiply = 4.95;
@@ -544,10 +546,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = true;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Minimi);
-                weapon.clip_sprite = Some(gfx::Weapon::MinimiClip);
-                weapon.bullet_sprite = Some(gfx::Weapon::MinimiBullet);
-                weapon.fire_sprite = Some(gfx::Weapon::MinimiFire);
 
                 if realistic {
                     weapon.hit_multiply = 0.81;
@@ -591,10 +589,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Minigun);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = Some(gfx::Weapon::MinigunBullet);
-                weapon.fire_sprite = Some(gfx::Weapon::MinigunFire);
 
                 if realistic {
                     weapon.hit_multiply = 0.43;
@@ -638,10 +632,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = true;
                 weapon.fire_mode = 2;
-                weapon.sprite = Some(gfx::Weapon::Socom);
-                weapon.clip_sprite = Some(gfx::Weapon::SocomClip);
-                weapon.bullet_sprite = Some(gfx::Weapon::ColtBullet);
-                weapon.fire_sprite = Some(gfx::Weapon::SocomFire);
 
                 if realistic {
                     weapon.hit_multiply = 1.30;
@@ -685,10 +675,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Knife);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = None;
 
                 if realistic {
                     weapon.hit_multiply = 2250.0;
@@ -732,10 +718,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Chainsaw);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::ChainsawFire);
 
                 if realistic {
                     weapon.hit_multiply = 21.0;
@@ -779,10 +761,6 @@ impl Weapon {
                 weapon.ini_name = "M72 LAW";
                 weapon.clip_reload = true;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Law);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::LawFire);
 
                 if realistic {
                     weapon.hit_multiply = 1500.0;
@@ -826,10 +804,6 @@ impl Weapon {
                 weapon.ini_name = "Flamed Arrows";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Bow);
-                weapon.clip_sprite = Some(gfx::Weapon::BowS);
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::BowFire);
 
                 if realistic {
                     weapon.hit_multiply = 8.0;
@@ -873,10 +847,6 @@ impl Weapon {
                 weapon.ini_name = "Rambo Bow";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Bow);
-                weapon.clip_sprite = Some(gfx::Weapon::BowS);
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::BowFire);
 
                 if realistic {
                     weapon.hit_multiply = 12.0;
@@ -920,10 +890,6 @@ impl Weapon {
                 weapon.ini_name = weapon.name;
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Flamer);
-                weapon.clip_sprite = Some(gfx::Weapon::Flamer);
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::FlamerFire);
 
                 if realistic {
                     weapon.hit_multiply = 12.0;
@@ -967,10 +933,6 @@ impl Weapon {
                 weapon.ini_name = "Stationary Gun";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Minigun);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = None;
 
                 if realistic {
                     weapon.hit_multiply = 1.55;
@@ -1014,10 +976,6 @@ impl Weapon {
                 weapon.ini_name = "Punch";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = None;
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = None;
 
                 if realistic {
                     weapon.hit_multiply = 330.0;
@@ -1061,10 +1019,6 @@ impl Weapon {
                 weapon.ini_name = "Grenade";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::FragGrenade);
-                weapon.clip_sprite = Some(gfx::Weapon::FragGrenade);
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::Ak74Fire);
 
                 if realistic {
                     weapon.hit_multiply = 1500.0;
@@ -1108,10 +1062,6 @@ impl Weapon {
                 weapon.ini_name = "";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::FragGrenade);
-                weapon.clip_sprite = Some(gfx::Weapon::FragGrenade);
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::Ak74Fire);
 
                 if realistic {
                     weapon.hit_multiply = 1500.0;
@@ -1155,10 +1105,6 @@ impl Weapon {
                 weapon.ini_name = "";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::FragGrenade);
-                weapon.clip_sprite = Some(gfx::Weapon::FragGrenade);
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = Some(gfx::Weapon::Ak74Fire);
 
                 if realistic {
                     weapon.hit_multiply = 1500.0;
@@ -1202,10 +1148,6 @@ impl Weapon {
                 weapon.ini_name = "";
                 weapon.clip_reload = false;
                 weapon.fire_mode = 0;
-                weapon.sprite = Some(gfx::Weapon::Knife);
-                weapon.clip_sprite = None;
-                weapon.bullet_sprite = None;
-                weapon.fire_sprite = None;
 
                 if realistic {
                     weapon.hit_multiply = 2250.0;